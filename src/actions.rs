@@ -0,0 +1,440 @@
+//! Filesystem actions taken on duplicate copies of a file, and policies picking which copy is kept.
+
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{Error, Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use crate::Fileinfo;
+use crate::range::HashRange;
+use crate::throttle::Throttle;
+
+/// An action replacing a duplicate copy of a retained file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action{
+    /// Removes the duplicate copy.
+    Delete,
+    /// Replaces the duplicate copy with a hard link to the retained file.
+    Hardlink,
+    /// Makes the duplicate copy share the retained file's storage, keeping it an independent file. Linux only, on filesystems such as Btrfs and XFS.
+    Reflink,
+}
+
+impl Action{
+    /// Applies the action to `target`, a duplicate of `keep`. Hard links are created beside the target and renamed over it, so the target is never missing if linking fails.
+    /// They link to the file `keep` resolves to, so a symlink is never linked in place of the file it leads to.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::fs;
+    /// use ddh::actions::Action;
+    ///
+    /// let temp = tempfile::tempdir().unwrap();
+    /// let dir = temp.path();
+    /// fs::write(dir.join("keep"), b"same").unwrap();
+    /// fs::write(dir.join("copy"), b"same").unwrap();
+    /// Action::Hardlink.apply(&dir.join("keep"), &dir.join("copy")).unwrap();
+    /// assert_eq!(b"same".to_vec(), fs::read(dir.join("copy")).unwrap());
+    /// Action::Delete.apply(&dir.join("keep"), &dir.join("copy")).unwrap();
+    /// assert!(!dir.join("copy").exists());
+    /// ```
+    pub fn apply(&self, keep: &Path, target: &Path) -> Result<(), Error>{
+        match self {
+            Action::Delete => fs::remove_file(target),
+            Action::Hardlink => {
+                let file_name = target.file_name()
+                    .ok_or_else(|| Error::other("Path has no file name"))?
+                    .to_string_lossy();
+                let temp = target.with_file_name(format!(".{}.ddh-link", file_name));
+                fs::hard_link(fs::canonicalize(keep)?, &temp)?;
+                fs::rename(&temp, target).inspect_err(|_e| {
+                    let _ = fs::remove_file(&temp);
+                })
+            },
+            Action::Reflink => crate::clones::dedupe_range(keep, target).map(|_| ()),
+        }
+    }
+}
+
+/// Compares two files byte for byte, without trusting any hash.
+///
+/// # Examples
+/// ```
+/// use std::fs;
+/// use ddh::actions::same_contents;
+///
+/// let temp = tempfile::tempdir().unwrap();
+/// let dir = temp.path();
+/// fs::write(dir.join("a"), b"same").unwrap();
+/// fs::write(dir.join("b"), b"same").unwrap();
+/// fs::write(dir.join("c"), b"sane").unwrap();
+/// assert!(same_contents(&dir.join("a"), &dir.join("b")).unwrap());
+/// assert!(!same_contents(&dir.join("a"), &dir.join("c")).unwrap());
+/// ```
+pub fn same_contents(a: &Path, b: &Path) -> Result<bool, Error>{
+    same_range_contents(a, b, None, None)
+}
+
+// Compares only the bytes in `range` of each file, for scans hashing that range alone. Reads of both files count against the throttle
+pub(crate) fn same_range_contents(a: &Path, b: &Path, range: Option<HashRange>, throttle: Option<&Throttle>) -> Result<bool, Error>{
+    let (mut a, mut b) = (fs::File::open(a)?, fs::File::open(b)?);
+    let length = a.metadata()?.len();
+    if length != b.metadata()?.len() {
+        return Ok(false)
+    }
+    let (start, end) = range.map_or((0, length), |x| x.bounds(length));
+    a.seek(SeekFrom::Start(start))?;
+    b.seek(SeekFrom::Start(start))?;
+    let (mut a, mut b) = (a.take(end - start), b.take(end - start));
+    let mut a_buffer = vec![0u8; 64*1024];
+    let mut b_buffer = vec![0u8; 64*1024];
+    loop {
+        let n = a.read(&mut a_buffer)?;
+        if n == 0 {
+            return Ok(b.read(&mut b_buffer[..1])? == 0)
+        }
+        b.read_exact(&mut b_buffer[..n])?;
+        if let Some(t) = throttle {
+            t.acquire(2*n as u64);
+        }
+        if a_buffer[..n] != b_buffer[..n] {
+            return Ok(false)
+        }
+    }
+}
+
+/// Checks whether two paths lead to the same physical file, such as a symlink and its target or two hard links to one file. Paths which cannot be read never match.
+///
+/// # Examples
+/// ```
+/// use std::fs;
+/// use ddh::actions::same_file;
+///
+/// let temp = tempfile::tempdir().unwrap();
+/// let dir = temp.path();
+/// fs::write(dir.join("a"), b"same").unwrap();
+/// fs::write(dir.join("b"), b"same").unwrap();
+/// fs::hard_link(dir.join("a"), dir.join("link")).unwrap();
+/// assert!(same_file(&dir.join("a"), &dir.join("link")));
+/// assert!(!same_file(&dir.join("a"), &dir.join("b")));
+/// ```
+pub fn same_file(a: &Path, b: &Path) -> bool{
+    match (file_identity(a), file_identity(b)) {
+        (Some(a), Some(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Gets the indices into `file.get_paths()` of the copies an action should replace when the copy at `keep` is kept.
+/// Paths leading to the same physical file, such as a followed symlink and its target, count as one copy.
+/// Every path to the kept file is left alone, so a copy is never replaced out from under an alias of the file being kept.
+/// Each other file is replaced once, through a path which is not a symlink where there is one.
+///
+/// # Examples
+/// ```
+/// #[cfg(unix)] {
+///     use std::fs;
+///     use std::os::unix::fs::symlink;
+///     use ddh::ScanOptions;
+///     use ddh::actions::{action_targets, Action};
+///
+///     let temp = tempfile::tempdir().unwrap();
+///     let root = temp.path();
+///     fs::write(root.join("photo"), b"same").unwrap();
+///     fs::write(root.join("copy"), b"same").unwrap();
+///     symlink(root.join("photo"), root.join("alias")).unwrap();
+///
+///     let options = ScanOptions::builder().follow_symlinks(true).build();
+///     let (files, _) = ddh::deduplicate_dirs_with(vec![&root], &options);
+///     let group = files.iter().find(|x| x.get_paths().len() == 3).unwrap();
+///     let index = |name| group.get_paths().iter().position(|x| *x == root.join(name)).unwrap();
+///     assert_eq!(vec![index("photo")], action_targets(group, index("copy")));
+///
+///     // Keeping the symlink leaves the file it leads to alone
+///     let targets = action_targets(group, index("alias"));
+///     assert_eq!(vec![index("copy")], targets);
+///     for target in targets {
+///         Action::Delete.apply(&root.join("alias"), &group.get_paths()[target]).unwrap();
+///     }
+///     assert_eq!(b"same".to_vec(), fs::read(root.join("alias")).unwrap());
+///     assert!(!root.join("copy").exists());
+/// }
+/// ```
+pub fn action_targets(file: &Fileinfo, keep: usize) -> Vec<usize>{
+    let paths = file.get_paths();
+    let kept = file_identity(&paths[keep]);
+    // Picks one path for each other physical file, replacing a symlink with a real path when one turns up
+    let mut targets: Vec<usize> = Vec::new();
+    let mut seen: HashMap<_, usize> = HashMap::new();
+    for (index, path) in paths.iter().enumerate(){
+        let identity = match file_identity(path) {
+            Some(identity) if Some(&identity) == kept.as_ref() => continue,
+            Some(identity) => identity,
+            None if index == keep => continue,
+            None => {
+                targets.push(index);
+                continue
+            },
+        };
+        match seen.get(&identity) {
+            Some(&slot) => if is_symlink(&paths[targets[slot]]) && !is_symlink(path) {
+                targets[slot] = index;
+            },
+            None => {
+                seen.insert(identity, targets.len());
+                targets.push(index);
+            },
+        }
+    }
+    targets.sort_unstable();
+    targets
+}
+
+pub(crate) fn is_symlink(path: &Path) -> bool{
+    fs::symlink_metadata(path).is_ok_and(|x| x.file_type().is_symlink())
+}
+
+#[cfg(unix)]
+pub(crate) fn file_identity(path: &Path) -> Option<(u64, u64)>{
+    use std::os::unix::fs::MetadataExt;
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+pub(crate) fn file_identity(path: &Path) -> Option<PathBuf>{
+    fs::canonicalize(path).ok()
+}
+
+/// Gets the device and inode number of the file `path` leads to, following symlinks. Paths with the same pair are hard links to one file,
+/// so deleting or linking all but one of them frees nothing. Returns None if the file cannot be read, and on platforms without inodes.
+///
+/// # Examples
+/// ```
+/// use std::fs;
+/// use ddh::actions::inode;
+///
+/// let temp = tempfile::tempdir().unwrap();
+/// let root = temp.path();
+/// fs::write(root.join("a"), b"abc").unwrap();
+/// fs::write(root.join("b"), b"abc").unwrap();
+/// fs::hard_link(root.join("a"), root.join("link")).unwrap();
+///
+/// if cfg!(unix) {
+///     assert_eq!(inode(&root.join("a")), inode(&root.join("link")));
+///     assert_ne!(inode(&root.join("a")), inode(&root.join("b")));
+/// }
+/// ```
+#[cfg(unix)]
+pub fn inode(path: &Path) -> Option<(u64, u64)>{
+    file_identity(path)
+}
+
+#[cfg(not(unix))]
+pub fn inode(_path: &Path) -> Option<(u64, u64)>{
+    None
+}
+
+/// Removes the directories left empty by deleting the files at `deleted`, working up from the deepest, and returns each directory with the result of removing it.
+/// A directory holding nothing but deleted files and pruned directories is pruned in turn, up to but never including a search directory in `roots`.
+/// Directories outside every root are left alone. With `dry_run` nothing is removed: the files are taken to be deleted already, and the directories
+/// which would be left empty are returned.
+///
+/// # Examples
+/// ```
+/// use std::fs;
+/// use ddh::actions::prune_empty_dirs;
+///
+/// let temp = tempfile::tempdir().unwrap();
+/// let root = temp.path();
+/// fs::create_dir_all(root.join("a/b/c")).unwrap();
+/// fs::create_dir_all(root.join("kept")).unwrap();
+/// fs::write(root.join("a/b/c/copy"), b"same").unwrap();
+/// fs::write(root.join("kept/copy"), b"same").unwrap();
+/// let deleted = vec![root.join("a/b/c/copy")];
+///
+/// let planned = prune_empty_dirs(&deleted, &[&root], true);
+/// assert_eq!(3, planned.len());
+/// assert!(root.join("a/b/c").exists());
+///
+/// fs::remove_file(root.join("a/b/c/copy")).unwrap();
+/// let pruned: Vec<_> = prune_empty_dirs(&deleted, &[&root], false).into_iter().map(|x| x.0).collect();
+/// let canonical = fs::canonicalize(&root).unwrap();
+/// assert_eq!(vec![canonical.join("a/b/c"), canonical.join("a/b"), canonical.join("a")], pruned);
+/// assert!(!root.join("a").exists());
+/// assert!(root.is_dir());
+///
+/// // The search directory itself is never removed, even once empty
+/// fs::remove_file(root.join("kept/copy")).unwrap();
+/// prune_empty_dirs(&[root.join("kept/copy")], &[&root], false);
+/// assert!(!root.join("kept").exists());
+/// assert!(root.is_dir());
+/// ```
+pub fn prune_empty_dirs<P: AsRef<Path>>(deleted: &[PathBuf], roots: &[P], dry_run: bool) -> Vec<(PathBuf, Result<(), Error>)>{
+    let roots: Vec<PathBuf> = roots.iter().filter_map(|x| fs::canonicalize(x).ok()).collect();
+    // Deleted files no longer resolve, so each is named under its canonical parent
+    let mut gone: HashSet<PathBuf> = deleted.iter()
+        .filter_map(|x| Some(fs::canonicalize(x.parent()?).ok()?.join(x.file_name()?)))
+        .collect();
+    let mut candidates: Vec<PathBuf> = Vec::new();
+    for path in gone.iter(){
+        for dir in path.ancestors().skip(1){
+            if roots.iter().any(|x| x == dir) || !roots.iter().any(|x| dir.starts_with(x)) || candidates.iter().any(|x| x == dir) {
+                break
+            }
+            candidates.push(dir.to_path_buf());
+        }
+    }
+    // Deeper directories first, so each is pruned before its parent is looked at
+    candidates.sort_by(|a, b| b.components().count().cmp(&a.components().count()).then_with(|| a.cmp(b)));
+    let mut pruned = Vec::new();
+    for dir in candidates.into_iter(){
+        let empty = match fs::read_dir(&dir) {
+            Ok(entries) => entries.filter_map(|x| x.ok()).all(|x| gone.contains(&x.path())),
+            Err(_e) => false,
+        };
+        if !empty {
+            continue
+        }
+        let result = if dry_run {Ok(())} else {fs::remove_dir(&dir)};
+        if result.is_ok() {
+            gone.insert(dir.clone());
+        }
+        pruned.push((dir, result));
+    }
+    pruned
+}
+
+/// A rule for picking which copy in a group of duplicates is kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Retention{
+    /// Keeps the most recently modified copy.
+    Newest,
+    /// Keeps the least recently modified copy.
+    Oldest,
+    /// Keeps the copy with the fewest path components.
+    Shallowest,
+    /// Keeps the copy under the most recently dated directory, as found by `backup_date`, for backups kept in folders such as `backup-2021-01-01`.
+    /// Dated copies win over undated ones, and copies under the same date, or with none, fall back to `Newest`.
+    NewestBackup,
+}
+
+/// Finds the date in the name of the innermost directory holding `path` that has one, as `(year, month, day)`.
+/// Dates are written year first, as `2021-01-01`, `2021_01_01`, `2021.01.01` or `20210101`, and must not run into other digits.
+///
+/// # Examples
+/// ```
+/// use std::path::Path;
+/// use ddh::actions::backup_date;
+///
+/// assert_eq!(Some((2021, 1, 31)), backup_date(Path::new("/backups/backup-2021-01-31/photos/cat.jpg")));
+/// assert_eq!(Some((2023, 7, 4)), backup_date(Path::new("/snapshots/20210101/daily_20230704/cat.jpg")));
+/// assert_eq!(None, backup_date(Path::new("/backups/backup-2021-01-31.jpg")));
+/// assert_eq!(None, backup_date(Path::new("/photos/2021-13-01/cat.jpg")));
+/// assert_eq!(None, backup_date(Path::new("/photos/id-202101011/cat.jpg")));
+/// ```
+pub fn backup_date(path: &Path) -> Option<(u32, u32, u32)>{
+    path.parent()?.components().rev()
+        .filter_map(|x| match x {
+            Component::Normal(name) => date_in(&name.to_string_lossy()),
+            _ => None,
+        })
+        .next()
+}
+
+fn date_in(name: &str) -> Option<(u32, u32, u32)>{
+    let bytes = name.as_bytes();
+    let digits = |at: usize, count: usize| -> Option<u32> {
+        let run = bytes.get(at..at + count)?;
+        if run.iter().all(u8::is_ascii_digit) {std::str::from_utf8(run).ok()?.parse().ok()} else {None}
+    };
+    (0..bytes.len()).filter(|&i| i == 0 || !bytes[i - 1].is_ascii_digit()).find_map(|start| {
+        let year = digits(start, 4)?;
+        let separator = bytes.get(start + 4).copied().filter(|x| matches!(x, b'-' | b'_' | b'.'));
+        let month_at = start + 4 + separator.is_some() as usize;
+        let month = digits(month_at, 2)?;
+        let day_at = match separator {
+            Some(separator) if bytes.get(month_at + 2) == Some(&separator) => month_at + 3,
+            Some(_) => return None,
+            None => month_at + 2,
+        };
+        let day = digits(day_at, 2)?;
+        if bytes.get(day_at + 2).is_some_and(u8::is_ascii_digit) {
+            return None
+        }
+        let valid = (1900..=2999).contains(&year) && (1..=12).contains(&month) && (1..=31).contains(&day);
+        if valid {Some((year, month, day))} else {None}
+    })
+}
+
+/// Picks the copy to keep from each group of duplicates. Every other copy becomes the target of an `Action`.
+///
+/// Copies under the preferred directory are considered first, falling back to every copy if none are under it.
+/// The retention rule then picks among those copies. Copies with no known modification time lose to any copy with one.
+/// Remaining ties go to the shallowest path, then to the path that sorts first.
+#[derive(Debug, Clone, Default)]
+pub struct RetentionPolicy{
+    retention: Option<Retention>,
+    preferred: Option<PathBuf>,
+}
+
+impl RetentionPolicy{
+    /// Creates a policy from an optional rule and an optional preferred directory.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::fs;
+    /// use ddh::actions::{Retention, RetentionPolicy};
+    ///
+    /// let temp = tempfile::tempdir().unwrap();
+    /// let root = temp.path();
+    /// fs::create_dir_all(root.join("keep/deeper")).unwrap();
+    /// fs::write(root.join("copy"), b"same").unwrap();
+    /// fs::write(root.join("keep/deeper/copy"), b"same").unwrap();
+    ///
+    /// let (files, _) = ddh::deduplicate_dirs(vec![&root]);
+    /// let group = files.iter().find(|x| x.get_paths().len() == 2).unwrap();
+    /// let shallowest = RetentionPolicy::new(Some(Retention::Shallowest), None);
+    /// assert_eq!(root.join("copy"), group.get_paths()[shallowest.choose(group).unwrap()]);
+    /// let preferred = RetentionPolicy::new(Some(Retention::Shallowest), Some(root.join("keep")));
+    /// assert_eq!(root.join("keep/deeper/copy"), group.get_paths()[preferred.choose(group).unwrap()]);
+    /// ```
+    pub fn new(retention: Option<Retention>, preferred: Option<PathBuf>) -> Self{
+        let preferred = preferred.map(|x| fs::canonicalize(&x).unwrap_or(x));
+        RetentionPolicy{retention, preferred}
+    }
+    /// Gets the index into `file.get_paths()` of the copy to keep, or `None` if the group has no paths.
+    pub fn choose(&self, file: &Fileinfo) -> Option<usize>{
+        let paths = file.get_paths();
+        let preferred: Vec<usize> = match &self.preferred {
+            Some(preferred) => (0..paths.len())
+                .filter(|&x| fs::canonicalize(&paths[x]).unwrap_or_else(|_e| paths[x].clone()).starts_with(preferred))
+                .collect(),
+            None => Vec::new(),
+        };
+        let candidates: Vec<usize> = if preferred.is_empty() {(0..paths.len()).collect()} else {preferred};
+        let modified = file.get_modified();
+        let newest = |a: usize, b: usize| match (modified.get(a).copied().flatten(), modified.get(b).copied().flatten()) {
+            (Some(x), Some(y)) => y.cmp(&x),
+            (x, y) => y.is_some().cmp(&x.is_some()),
+        };
+        candidates.into_iter().min_by(|&a, &b| {
+            let by_rule = match self.retention {
+                Some(Retention::Newest) => newest(a, b),
+                Some(Retention::NewestBackup) => match (backup_date(&paths[a]), backup_date(&paths[b])) {
+                    (Some(x), Some(y)) => y.cmp(&x).then_with(|| newest(a, b)),
+                    (None, None) => newest(a, b),
+                    (x, y) => y.is_some().cmp(&x.is_some()),
+                },
+                Some(Retention::Oldest) => match (modified.get(a).copied().flatten(), modified.get(b).copied().flatten()) {
+                    (Some(x), Some(y)) => x.cmp(&y),
+                    (x, y) => y.is_some().cmp(&x.is_some()),
+                },
+                Some(Retention::Shallowest) | None => Ordering::Equal,
+            };
+            by_rule
+                .then_with(|| paths[a].components().count().cmp(&paths[b].components().count()))
+                .then_with(|| paths[a].cmp(&paths[b]))
+        })
+    }
+}
@@ -0,0 +1,243 @@
+//! Duplicate detection among the members of tar archives, read in one pass without extracting them.
+//! Members are named `archive.tar!member` and can only be reported on, as they have no path of their own to act on.
+
+use std::fs;
+use std::io::{self, BufReader, Error, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use rayon::prelude::*;
+use crate::{dedup, profile, ContentHasher, Fileinfo, ScanOptions, BLOCK_SIZE};
+use crate::profile::Stage;
+
+const HEADER_SIZE: usize = 512;
+
+/// The header fields of one archive member.
+struct Header{
+    name: String,
+    size: u64,
+    modified: Option<SystemTime>,
+    kind: u8,
+}
+
+/// Constructs a list of unique files among the members of each tar archive, pooling the members of every archive given.
+/// Each member is read once as the archive streams past and hashed in full, so only one member is held in memory at a time and archives
+/// may be pipes, such as the output of `gzip -dc`. Members cannot be read twice, so matches rest on their full hash rather than a byte for byte comparison.
+/// Directories, links and other entries which are not regular files are skipped. An archive which cannot be read, or which ends partway through,
+/// is reported as an error alongside whichever members were read from it.
+///
+/// # Examples
+/// ```
+/// use std::fs;
+/// use std::path::Path;
+/// use ddh::ScanOptions;
+///
+/// // Writes a minimal ustar archive holding the given members
+/// fn tar(members: &[(&str, &[u8])]) -> Vec<u8> {
+///     let mut archive = Vec::new();
+///     for (name, contents) in members.iter() {
+///         let mut header = [0u8; 512];
+///         header[..name.len()].copy_from_slice(name.as_bytes());
+///         header[100..107].copy_from_slice(b"0000644");
+///         header[124..135].copy_from_slice(format!("{:011o}", contents.len()).as_bytes());
+///         header[136..147].copy_from_slice(b"00000000000");
+///         header[156] = b'0';
+///         header[257..263].copy_from_slice(b"ustar\0");
+///         header[148..156].copy_from_slice(b"        ");
+///         let sum: u32 = header.iter().map(|&x| x as u32).sum();
+///         header[148..155].copy_from_slice(format!("{:06o}\0", sum).as_bytes());
+///         archive.extend_from_slice(&header);
+///         archive.extend_from_slice(contents);
+///         archive.resize((archive.len() + 511) / 512 * 512, 0);
+///     }
+///     archive.resize(archive.len() + 1024, 0);
+///     archive
+/// }
+///
+/// let temp = tempfile::tempdir().unwrap();
+/// let root = temp.path();
+/// let archive = root.join("backup.tar");
+/// fs::write(&archive, tar(&[("a.txt", b"same"), ("docs/b.txt", b"same"), ("c.txt", b"other")])).unwrap();
+///
+/// let (files, errors) = ddh::archive::deduplicate_archives(vec![&archive], &ScanOptions::default());
+/// assert!(errors.is_empty());
+/// let shared: Vec<_> = files.iter().filter(|x| x.get_paths().len() > 1).collect();
+/// assert_eq!(1, shared.len());
+/// assert!(shared[0].get_paths().contains(&Path::new(&format!("{}!docs/b.txt", archive.display())).to_path_buf()));
+///
+/// fs::write(&archive, b"not an archive").unwrap();
+/// let (_, errors) = ddh::archive::deduplicate_archives(vec![&archive], &ScanOptions::default());
+/// assert_eq!(1, errors.len());
+/// ```
+pub fn deduplicate_archives<P: AsRef<Path> + Sync>(archives: Vec<P>, options: &ScanOptions) -> (Vec<Fileinfo>, Vec<(PathBuf, Error)>){
+    let pool = options.thread_pool();
+    let (members, mut errors) = profile::measure(options.profile.as_deref(), Stage::FullHashing, || pool.install(|| {
+        archives.par_iter().enumerate()
+            .map(|(root, archive)| {
+                let archive = archive.as_ref();
+                let mut members = Vec::new();
+                let errors = match read_archive(archive, root, options, &mut members) {
+                    Ok(()) => Vec::new(),
+                    Err(e) => vec![(archive.to_path_buf(), e)],
+                };
+                (members, errors)
+            })
+            .reduce(|| (Vec::new(), Vec::new()), |mut a, b| {
+                a.0.extend(b.0);
+                a.1.extend(b.1);
+                a
+            })
+    }));
+    let mut files = dedup::consolidate_hashed(members, options);
+    if options.deterministic {
+        crate::sort_results(&mut files, &mut errors, options, &pool);
+    }
+    (files, errors)
+}
+
+fn read_archive(archive: &Path, root: usize, options: &ScanOptions, members: &mut Vec<Fileinfo>) -> Result<(), Error>{
+    let mut reader = BufReader::new(fs::File::open(archive)?);
+    let mut long_name: Option<String> = None;
+    let mut extended_name: Option<String> = None;
+    let mut extended_size: Option<u64> = None;
+    while let Some(header) = read_header(&mut reader)? {
+        if options.is_stopped(){
+            return Ok(())
+        }
+        let size = extended_size.take().unwrap_or(header.size);
+        let mut data = (&mut reader).take(size);
+        match header.kind {
+            // GNU long names and pax extended headers describe the member after them
+            b'L' => long_name = Some(trim_name(&read_all(&mut data)?)),
+            b'x' => {
+                for (key, value) in pax_records(&read_all(&mut data)?){
+                    match key.as_str() {
+                        "path" => extended_name = Some(value),
+                        "size" => extended_size = value.parse().ok(),
+                        _ => {},
+                    }
+                }
+            },
+            b'0' | b'\0' | b'7' => {
+                let name = extended_name.take().or_else(|| long_name.take()).unwrap_or(header.name);
+                let path = PathBuf::from(format!("{}!{}", archive.display(), name));
+                if options.is_size_excluded(size) || options.is_extension_excluded(&path) || options.is_path_filtered(&path) || options.is_excluded(&path){
+                    io::copy(&mut data, &mut io::sink())?;
+                } else {
+                    let (partial_hash, full_hash) = hash_member(&mut data, size, &path, options)?;
+                    members.push(Fileinfo::new(full_hash, partial_hash, size, path).with_root(root).with_modified(header.modified));
+                }
+            },
+            _ => {
+                extended_name = None;
+                long_name = None;
+            },
+        }
+        io::copy(&mut data, &mut io::sink())?;
+        if data.limit() > 0 {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "Archive ends partway through a member"))
+        }
+        // Member data is padded to a whole number of blocks
+        let padding = (HEADER_SIZE as u64 - size % HEADER_SIZE as u64) % HEADER_SIZE as u64;
+        io::copy(&mut (&mut reader).take(padding), &mut io::sink())?;
+    }
+    Ok(())
+}
+
+// Reads the next header, returning None at the zeroed block ending the archive or at the end of the stream
+fn read_header<R: Read>(reader: &mut R) -> Result<Option<Header>, Error>{
+    let mut block = [0u8; HEADER_SIZE];
+    let mut filled = 0;
+    while filled < HEADER_SIZE {
+        match reader.read(&mut block[filled..])? {
+            0 if filled == 0 => return Ok(None),
+            0 => return Err(Error::new(ErrorKind::UnexpectedEof, "Archive ends partway through a header")),
+            n => filled += n,
+        }
+    }
+    if block.iter().all(|&x| x == 0) {
+        return Ok(None)
+    }
+    // The checksum covers the header with its own field read as spaces
+    let checksum: u64 = block.iter().enumerate().map(|(i, &x)| if (148..156).contains(&i) {b' ' as u64} else {x as u64}).sum();
+    if parse_number(&block[148..156]) != Some(checksum) {
+        return Err(Error::new(ErrorKind::InvalidData, "Not a tar archive, or a header is corrupt"))
+    }
+    let mut name = trim_name(&block[..100]);
+    let prefix = trim_name(&block[345..500]);
+    if &block[257..262] == b"ustar" && !prefix.is_empty() {
+        name = format!("{}/{}", prefix, name);
+    }
+    let size = parse_number(&block[124..136]).ok_or_else(|| Error::new(ErrorKind::InvalidData, "Archive member has an invalid size"))?;
+    let modified = parse_number(&block[136..148]).map(|x| UNIX_EPOCH + Duration::from_secs(x));
+    Ok(Some(Header{name, size, modified, kind: block[156]}))
+}
+
+// Numeric fields are octal text, or big endian binary marked by a set high bit for values too large for octal
+fn parse_number(field: &[u8]) -> Option<u64>{
+    if field.first().is_some_and(|&x| x & 0x80 != 0) {
+        return Some(field[1..].iter().fold(0u64, |a, &x| (a << 8) | x as u64))
+    }
+    let text = std::str::from_utf8(field).ok()?.trim_matches(|x: char| x == '\0' || x == ' ');
+    u64::from_str_radix(text, 8).ok()
+}
+
+fn trim_name(field: &[u8]) -> String{
+    let end = field.iter().position(|&x| x == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+fn read_all<R: Read>(reader: &mut R) -> Result<Vec<u8>, Error>{
+    let mut contents = Vec::new();
+    reader.read_to_end(&mut contents)?;
+    Ok(contents)
+}
+
+// Records are written `<length> <key>=<value>\n`, with the length counting the whole record
+fn pax_records(data: &[u8]) -> Vec<(String, String)>{
+    let mut records = Vec::new();
+    let mut rest = data;
+    while let Some(space) = rest.iter().position(|&x| x == b' ') {
+        let length = match std::str::from_utf8(&rest[..space]).ok().and_then(|x| x.parse::<usize>().ok()) {
+            Some(length) if length > space && length <= rest.len() => length,
+            _ => break,
+        };
+        let record = String::from_utf8_lossy(&rest[space + 1..length]);
+        if let Some((key, value)) = record.trim_end_matches('\n').split_once('=') {
+            records.push((key.to_string(), value.to_string()));
+        }
+        rest = &rest[length..];
+    }
+    records
+}
+
+// Hashes the start of a member as its partial hash while reading on through the rest for its full hash. Empty members are left unhashed, as on disk
+fn hash_member<R: Read>(data: &mut R, size: u64, path: &Path, options: &ScanOptions) -> Result<(Option<u128>, Option<u128>), Error>{
+    if size == 0 {
+        return Ok((None, None))
+    }
+    if let Some(p) = &options.progress{
+        p.add_total(size);
+    }
+    let tracker = options.progress.as_ref().map(|p| p.track_file(path, size));
+    let mut partial = ContentHasher::new(options.hash_algorithm);
+    let mut full = ContentHasher::new(options.hash_algorithm);
+    let mut read = 0;
+    let mut hash_buffer = [0; BLOCK_SIZE * 4];
+    loop {
+        let n = data.read(&mut hash_buffer)?;
+        if n == 0 {
+            break
+        }
+        let sampled = (hash_buffer.len() - read.min(hash_buffer.len())).min(n);
+        partial.write(&hash_buffer[..sampled]);
+        full.write(&hash_buffer[..n]);
+        read += n;
+        if let Some(tracker) = &tracker{
+            tracker.add_hashed(n as u64);
+        }
+        if let Some(t) = &options.throttle{
+            t.acquire(n as u64);
+        }
+    }
+    Ok((Some(partial.finish()), Some(full.finish())))
+}
@@ -0,0 +1,70 @@
+//! A cap on the total size of files a scan takes in, for quick samples of volumes too large to scan in full.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// Limits the combined length of the files a scan takes in. Once a file would overrun the budget, traversal winds down and finds no more files.
+/// Hashing is not stopped, so the files already found are grouped as thoroughly as in a full scan. Results of a scan whose budget ran out cover only part of the search directories.
+#[derive(Debug)]
+pub struct ScanBudget{
+    limit: u64,
+    used: AtomicU64,
+    exhausted: AtomicBool,
+}
+
+impl ScanBudget{
+    /// Creates a budget allowing files totalling `limit` bytes.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::fs;
+    /// use std::sync::Arc;
+    /// use ddh::ScanOptions;
+    /// use ddh::budget::ScanBudget;
+    ///
+    /// let temp = tempfile::tempdir().unwrap();
+    /// let root = temp.path();
+    /// for name in ["a", "b", "c", "d"].iter() {
+    ///     fs::write(root.join(name), vec![1u8; 1000]).unwrap();
+    /// }
+    ///
+    /// let budget = Arc::new(ScanBudget::new(2500));
+    /// let options = ScanOptions::builder().budget(budget.clone()).build();
+    /// let (files, _) = ddh::deduplicate_dirs_with(vec![&root], &options);
+    /// assert_eq!(2, files.iter().map(|x| x.get_paths().len()).sum::<usize>());
+    /// assert!(budget.is_exhausted());
+    /// assert_eq!(2000, budget.used_bytes());
+    ///
+    /// let budget = Arc::new(ScanBudget::new(4000));
+    /// let options = ScanOptions::builder().budget(budget.clone()).build();
+    /// ddh::deduplicate_dirs_with(vec![&root], &options);
+    /// assert!(!budget.is_exhausted());
+    /// ```
+    pub fn new(limit: u64) -> Self{
+        ScanBudget{limit, used: AtomicU64::new(0), exhausted: AtomicBool::new(false)}
+    }
+    /// Gets the configured limit in bytes.
+    pub fn limit(&self) -> u64{
+        self.limit
+    }
+    /// Gets the combined length of the files taken in so far.
+    pub fn used_bytes(&self) -> u64{
+        self.used.load(Ordering::Relaxed)
+    }
+    /// Checks whether a file has been left out for overrunning the budget, in which case the scan's results are partial.
+    pub fn is_exhausted(&self) -> bool{
+        self.exhausted.load(Ordering::Relaxed)
+    }
+    /// Spends `bytes` on a file, returning whether it fit. The first file that does not fit exhausts the budget, so no later file is taken in.
+    pub(crate) fn spend(&self, bytes: u64) -> bool{
+        if self.is_exhausted() {
+            return false
+        }
+        let fits = self.used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |x| x.checked_add(bytes).filter(|&y| y <= self.limit))
+            .is_ok();
+        if !fits {
+            self.exhausted.store(true, Ordering::Relaxed);
+        }
+        fits
+    }
+}
@@ -0,0 +1,186 @@
+//! Persistent stores of file hashes: a cache file used to pick up an interrupted scan where it left off, and extended attributes that travel with each file.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::Error;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+use serde_derive::{Serialize, Deserialize};
+use crate::{HashAlgorithm, HashMode, SampleStrategy};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct CacheEntry{
+    length: u64,
+    modified: (u64, u32),
+    #[serde(default)]
+    hash_algorithm: HashAlgorithm,
+    partial_hash: Option<u128>,
+    full_hash: Option<u128>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct XattrEntry{
+    sample_strategy: SampleStrategy,
+    entry: CacheEntry,
+}
+
+const XATTR_NAME: &str = "user.ddh.hash";
+
+#[derive(Serialize, Deserialize)]
+struct CacheFile{
+    sample_strategy: SampleStrategy,
+    entries: Vec<(PathBuf, CacheEntry)>,
+}
+
+/// Hashes keyed by path. An entry is only reused while the file's length and modification time are unchanged, and only by scans using the same hash algorithm.
+#[derive(Debug)]
+pub struct HashCache{
+    sample_strategy: SampleStrategy,
+    entries: Mutex<HashMap<PathBuf, CacheEntry>>,
+}
+
+impl HashCache{
+    /// Creates an empty cache for partial hashes computed with the given sample strategy.
+    ///
+    /// # Examples
+    /// ```
+    /// use ddh::SampleStrategy;
+    /// use ddh::cache::HashCache;
+    ///
+    /// let cache = HashCache::new(SampleStrategy::Prefix);
+    /// assert!(cache.is_empty());
+    /// ```
+    pub fn new(sample_strategy: SampleStrategy) -> Self{
+        HashCache{sample_strategy, entries: Mutex::new(HashMap::new())}
+    }
+    /// Loads a cache written by `save`. Partial hashes computed with a different sample strategy are discarded.
+    ///
+    /// # Examples
+    /// ```
+    /// use ddh::SampleStrategy;
+    /// use ddh::cache::HashCache;
+    ///
+    /// let temp = tempfile::tempdir().unwrap();
+    /// let cache_file = temp.path().join("cache.json");
+    /// HashCache::new(SampleStrategy::Prefix).save(&cache_file).unwrap();
+    /// let cache = HashCache::load(&cache_file, SampleStrategy::Spread).unwrap();
+    /// assert_eq!(0, cache.len());
+    /// ```
+    pub fn load<P: AsRef<Path>>(path: P, sample_strategy: SampleStrategy) -> Result<Self, Error>{
+        let cache_file: CacheFile = serde_json::from_str(&fs::read_to_string(path)?)?;
+        let same_strategy = cache_file.sample_strategy == sample_strategy;
+        let entries = cache_file.entries
+            .into_iter()
+            .map(|(path, mut entry)| {
+                if !same_strategy {
+                    entry.partial_hash = None;
+                }
+                (path, entry)
+            })
+            .collect();
+        Ok(HashCache{sample_strategy, entries: Mutex::new(entries)})
+    }
+    /// Writes the cache to a file as JSON.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error>{
+        let cache_file = CacheFile{
+            sample_strategy: self.sample_strategy,
+            entries: self.entries.lock().unwrap().iter().map(|(k, v)| (k.clone(), *v)).collect(),
+        };
+        fs::write(path, serde_json::to_string(&cache_file)?)
+    }
+    /// Gets the number of paths with cached hashes.
+    pub fn len(&self) -> usize{
+        self.entries.lock().unwrap().len()
+    }
+    /// Checks whether the cache holds no hashes.
+    pub fn is_empty(&self) -> bool{
+        self.len() == 0
+    }
+    pub(crate) fn lookup(&self, path: &Path, length: u64, mode: &HashMode, hash_algorithm: HashAlgorithm) -> Option<u128>{
+        let modified = modified_time(path)?;
+        let entries = self.entries.lock().unwrap();
+        let entry = entries.get(path).filter(|x| x.length == length && x.modified == modified && x.hash_algorithm == hash_algorithm)?;
+        match mode {
+            HashMode::Partial => entry.partial_hash,
+            HashMode::Full => entry.full_hash,
+        }
+    }
+    pub(crate) fn record(&self, path: &Path, length: u64, mode: &HashMode, hash_algorithm: HashAlgorithm, hash: u128){
+        let modified = match modified_time(path) {
+            Some(modified) => modified,
+            None => return,
+        };
+        let fresh = CacheEntry{length, modified, hash_algorithm, partial_hash: None, full_hash: None};
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.entry(path.to_path_buf()).or_insert(fresh);
+        if entry.length != length || entry.modified != modified || entry.hash_algorithm != hash_algorithm {
+            *entry = fresh;
+        }
+        match mode {
+            HashMode::Partial => entry.partial_hash = Some(hash),
+            HashMode::Full => entry.full_hash = Some(hash),
+        }
+    }
+}
+
+/// Reads a hash stored in the file's extended attributes by `xattr_record`, if the file is unchanged since.
+pub(crate) fn xattr_lookup(path: &Path, length: u64, mode: &HashMode, sample_strategy: SampleStrategy, hash_algorithm: HashAlgorithm) -> Option<u128>{
+    let modified = modified_time(path)?;
+    let stored: XattrEntry = serde_json::from_slice(&get_xattr(path)?).ok()?;
+    if stored.entry.length != length || stored.entry.modified != modified || stored.entry.hash_algorithm != hash_algorithm {
+        return None
+    }
+    match mode {
+        HashMode::Partial if stored.sample_strategy == sample_strategy => stored.entry.partial_hash,
+        HashMode::Partial => None,
+        HashMode::Full => stored.entry.full_hash,
+    }
+}
+
+/// Stores a hash in the file's extended attributes. Failures, such as a filesystem without extended attributes or a read only file, are ignored.
+pub(crate) fn xattr_record(path: &Path, length: u64, mode: &HashMode, sample_strategy: SampleStrategy, hash_algorithm: HashAlgorithm, hash: u128){
+    let modified = match modified_time(path) {
+        Some(modified) => modified,
+        None => return,
+    };
+    let fresh = CacheEntry{length, modified, hash_algorithm, partial_hash: None, full_hash: None};
+    let mut stored = get_xattr(path)
+        .and_then(|x| serde_json::from_slice::<XattrEntry>(&x).ok())
+        .filter(|x| x.entry.length == length && x.entry.modified == modified && x.entry.hash_algorithm == hash_algorithm)
+        .unwrap_or(XattrEntry{sample_strategy, entry: fresh});
+    if stored.sample_strategy != sample_strategy {
+        stored.sample_strategy = sample_strategy;
+        stored.entry.partial_hash = None;
+    }
+    match mode {
+        HashMode::Partial => stored.entry.partial_hash = Some(hash),
+        HashMode::Full => stored.entry.full_hash = Some(hash),
+    }
+    if let Ok(value) = serde_json::to_vec(&stored) {
+        set_xattr(path, &value);
+    }
+}
+
+#[cfg(unix)]
+fn get_xattr(path: &Path) -> Option<Vec<u8>>{
+    xattr::get(path, XATTR_NAME).ok().flatten()
+}
+
+#[cfg(unix)]
+fn set_xattr(path: &Path, value: &[u8]){
+    let _ = xattr::set(path, XATTR_NAME, value);
+}
+
+#[cfg(not(unix))]
+fn get_xattr(_path: &Path) -> Option<Vec<u8>>{
+    None
+}
+
+#[cfg(not(unix))]
+fn set_xattr(_path: &Path, _value: &[u8]){}
+
+fn modified_time(path: &Path) -> Option<(u64, u32)>{
+    let since_epoch = fs::metadata(path).ok()?.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some((since_epoch.as_secs(), since_epoch.subsec_nanos()))
+}
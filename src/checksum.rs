@@ -0,0 +1,46 @@
+//! SHA-256 checksums of written reports, kept in a sidecar file so later edits to an archived report can be detected.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use sha2::{Digest, Sha256};
+
+/// Computes the SHA-256 digest of `data`, as a lowercase hex string.
+///
+/// # Examples
+/// ```
+/// use ddh::checksum::sha256_hex;
+///
+/// assert_eq!("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad", sha256_hex(b"abc"));
+/// assert_eq!("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855", sha256_hex(b""));
+/// assert_eq!("248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1", sha256_hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"));
+/// ```
+pub fn sha256_hex(data: &[u8]) -> String{
+    Sha256::digest(data).iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+/// Writes the SHA-256 of `file` to a sidecar beside it named with `.sha256` appended, returning the sidecar's path.
+/// The sidecar holds one line in the format `sha256sum -c` checks, naming the file relative to the sidecar.
+///
+/// # Examples
+/// ```
+/// use std::fs;
+/// use ddh::checksum::write_sidecar;
+///
+/// let temp = tempfile::tempdir().unwrap();
+/// let root = temp.path();
+/// fs::write(root.join("Results.txt"), b"abc").unwrap();
+///
+/// let sidecar = write_sidecar(root.join("Results.txt")).unwrap();
+/// assert_eq!(root.join("Results.txt.sha256"), sidecar);
+/// assert_eq!("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad  Results.txt\n", fs::read_to_string(&sidecar).unwrap());
+/// ```
+pub fn write_sidecar<P: AsRef<Path>>(file: P) -> Result<PathBuf, io::Error>{
+    let file = file.as_ref();
+    let name = file.file_name().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Checksummed path has no file name"))?;
+    let mut sidecar = file.as_os_str().to_os_string();
+    sidecar.push(".sha256");
+    let sidecar = PathBuf::from(sidecar);
+    fs::write(&sidecar, format!("{}  {}\n", sha256_hex(&fs::read(file)?), name.to_string_lossy()))?;
+    Ok(sidecar)
+}
@@ -0,0 +1,221 @@
+//! Detection of copies sharing their storage, such as copy-on-write clones and hard links, which waste no space despite being separate paths,
+//! and deduplication of copies into such clones.
+
+use std::collections::HashMap;
+use std::io::Error;
+use std::path::{Path, PathBuf};
+use crate::Fileinfo;
+
+/// Where a file's data lives on disk, as a list of `(logical offset, physical offset, length)` extents.
+type Extents = Vec<(u64, u64, u64)>;
+
+/// Copies within one group of duplicates, split by the storage they occupy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorageSets<'a>{
+    /// Sets of two or more paths sharing the same storage, as copy-on-write clones or hard links do.
+    pub shared: Vec<Vec<&'a PathBuf>>,
+    /// Number of separately stored copies. Deleting all but one of them reclaims `(distinct - 1) * length` bytes.
+    pub distinct: usize,
+}
+
+/// Splits the copies of `file` by the extents their data occupies on disk.
+///
+/// Extents are read with the Linux `FIEMAP` ioctl, supported by Btrfs, XFS, ext4 and most other Linux filesystems.
+/// Paths whose extents cannot be read, including every path on other platforms, are counted as separately stored.
+///
+/// # Examples
+/// ```
+/// use std::fs;
+/// use ddh::clones::storage_sets;
+///
+/// let temp = tempfile::tempdir().unwrap();
+/// let root = temp.path();
+/// fs::write(root.join("a"), vec![7u8; 20000]).unwrap();
+/// fs::write(root.join("b"), vec![7u8; 20000]).unwrap();
+/// fs::hard_link(root.join("a"), root.join("c")).unwrap();
+/// // Data still waiting to be written has no extents yet
+/// for name in ["a", "b"].iter() {
+///     fs::File::open(root.join(name)).unwrap().sync_all().unwrap();
+/// }
+///
+/// let (files, _) = ddh::deduplicate_dirs(vec![&root]);
+/// let sets = storage_sets(&files[0]);
+/// assert_eq!(3, files[0].get_paths().len());
+/// if cfg!(target_os = "linux") {
+///     assert_eq!(2, sets.distinct);
+///     assert_eq!(1, sets.shared.len());
+/// }
+/// ```
+pub fn storage_sets(file: &Fileinfo) -> StorageSets<'_>{
+    let mut by_extents: HashMap<Extents, Vec<&PathBuf>> = HashMap::new();
+    let mut distinct = 0;
+    for path in file.get_paths().iter(){
+        match extents(path) {
+            Some(extents) if !extents.is_empty() => by_extents.entry(extents).or_default().push(path),
+            _ => distinct += 1,
+        }
+    }
+    distinct += by_extents.len();
+    let mut shared: Vec<Vec<&PathBuf>> = by_extents.into_values().filter(|x| x.len() > 1).collect();
+    shared.iter_mut().for_each(|x| x.sort());
+    shared.sort();
+    StorageSets{shared, distinct}
+}
+
+#[cfg(target_os = "linux")]
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct FiemapExtent{
+    logical: u64,
+    physical: u64,
+    length: u64,
+    reserved64: [u64; 2],
+    flags: u32,
+    reserved: [u32; 3],
+}
+
+#[cfg(target_os = "linux")]
+#[repr(C)]
+#[derive(Default)]
+struct Fiemap{
+    start: u64,
+    length: u64,
+    flags: u32,
+    mapped_extents: u32,
+    extent_count: u32,
+    reserved: u32,
+    extents: [FiemapExtent; 32],
+}
+
+#[cfg(target_os = "linux")]
+fn extents(path: &Path) -> Option<Extents>{
+    use std::os::unix::io::AsRawFd;
+
+    const FS_IOC_FIEMAP: libc::c_ulong = 0xC020660B;
+    const FIEMAP_EXTENT_LAST: u32 = 0x1;
+    // Extents without a stable physical location: unknown, delayed allocation, encoded, unaligned or inline
+    const FIEMAP_EXTENT_UNPLACED: u32 = 0x2 | 0x4 | 0x8 | 0x100 | 0x200;
+    const MAX_EXTENTS: usize = 4096;
+
+    let file = std::fs::File::open(path).ok()?;
+    let mut extents = Vec::new();
+    let mut start = 0u64;
+    loop {
+        // Not syncing first, as FIEMAP_FLAG_SYNC slows scans severalfold. Unwritten data shows as delayed allocation and is treated as unknown
+        let mut request = Fiemap{start, length: u64::MAX - start, ..Fiemap::default()};
+        request.extent_count = request.extents.len() as u32;
+        // Safe as the kernel writes at most extent_count extents into the request
+        if unsafe {libc::ioctl(file.as_raw_fd(), FS_IOC_FIEMAP as _, &mut request as *mut Fiemap)} != 0 {
+            return None
+        }
+        if request.mapped_extents == 0 {
+            return Some(extents)
+        }
+        for extent in request.extents.iter().take(request.mapped_extents as usize){
+            if extent.flags & FIEMAP_EXTENT_UNPLACED != 0 {
+                return None
+            }
+            extents.push((extent.logical, extent.physical, extent.length));
+            if extent.flags & FIEMAP_EXTENT_LAST != 0 {
+                return Some(extents)
+            }
+            start = extent.logical + extent.length;
+        }
+        if extents.len() >= MAX_EXTENTS {
+            return None
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn extents(_path: &Path) -> Option<Extents>{
+    None
+}
+
+/// Makes `target` share the storage of `keep` with the Linux `FIDEDUPERANGE` ioctl, leaving both as independent files with unchanged contents.
+/// The kernel compares the files itself and refuses ranges whose bytes differ. Returns the number of bytes deduplicated.
+///
+/// Only filesystems supporting reflinks, such as Btrfs and XFS, accept the request. Elsewhere, and on other platforms, an error is returned and nothing changes.
+///
+/// # Examples
+/// ```
+/// use std::fs;
+/// use ddh::clones::dedupe_range;
+///
+/// let temp = tempfile::tempdir().unwrap();
+/// let dir = temp.path();
+/// fs::write(dir.join("keep"), vec![7u8; 20000]).unwrap();
+/// fs::write(dir.join("copy"), vec![7u8; 20000]).unwrap();
+/// match dedupe_range(&dir.join("keep"), &dir.join("copy")) {
+///     Ok(bytes) => assert_eq!(20000, bytes),
+///     Err(e) => println!("Filesystem cannot share extents: {}", e),
+/// }
+/// assert_eq!(vec![7u8; 20000], fs::read(dir.join("copy")).unwrap());
+/// ```
+#[cfg(target_os = "linux")]
+pub fn dedupe_range(keep: &Path, target: &Path) -> Result<u64, Error>{
+    use std::os::unix::io::AsRawFd;
+
+    const FIDEDUPERANGE: libc::c_ulong = 0xC0189436;
+    const FILE_DEDUPE_RANGE_DIFFERS: i32 = 1;
+    // Filesystems cap the bytes handled per request, so large files are submitted in pieces
+    const CHUNK: u64 = 16*1024*1024;
+
+    let source = std::fs::File::open(keep)?;
+    let destination = std::fs::OpenOptions::new().write(true).open(target)?;
+    let length = source.metadata()?.len();
+    if length != destination.metadata()?.len() {
+        return Err(Error::other("Files differ in length"))
+    }
+    let mut offset = 0;
+    while offset < length {
+        let mut request = FileDedupeRange{
+            src_offset: offset,
+            src_length: CHUNK.min(length - offset),
+            dest_count: 1,
+            reserved1: 0,
+            reserved2: 0,
+            info: FileDedupeRangeInfo{dest_fd: destination.as_raw_fd() as i64, dest_offset: offset, bytes_deduped: 0, status: 0, reserved: 0},
+        };
+        if unsafe {libc::ioctl(source.as_raw_fd(), FIDEDUPERANGE as _, &mut request as *mut FileDedupeRange)} != 0 {
+            return Err(Error::last_os_error())
+        }
+        match request.info.status {
+            status if status < 0 => return Err(Error::from_raw_os_error(-status)),
+            FILE_DEDUPE_RANGE_DIFFERS => return Err(Error::other("Contents differ")),
+            _ => {},
+        }
+        if request.info.bytes_deduped == 0 {
+            return Err(Error::other("Filesystem deduplicated no bytes"))
+        }
+        offset += request.info.bytes_deduped;
+    }
+    Ok(offset)
+}
+
+/// Makes `target` share the storage of `keep`. Always fails, as extent sharing is only supported on Linux.
+#[cfg(not(target_os = "linux"))]
+pub fn dedupe_range(_keep: &Path, _target: &Path) -> Result<u64, Error>{
+    Err(Error::new(std::io::ErrorKind::Unsupported, "Extent deduplication is only supported on Linux"))
+}
+
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct FileDedupeRangeInfo{
+    dest_fd: i64,
+    dest_offset: u64,
+    bytes_deduped: u64,
+    status: i32,
+    reserved: u32,
+}
+
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct FileDedupeRange{
+    src_offset: u64,
+    src_length: u64,
+    dest_count: u16,
+    reserved1: u16,
+    reserved2: u32,
+    info: FileDedupeRangeInfo,
+}
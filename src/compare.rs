@@ -0,0 +1,152 @@
+//! Comparison of directory trees by the file contents they hold, for verifying backups and finding files already kept elsewhere, built on the file groups from a completed scan.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use serde_derive::Serialize;
+use crate::Fileinfo;
+
+/// Where two trees differ. Trees with no differences hold the same contents at the same relative paths.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct TreeDifferences{
+    /// Files under the first tree whose contents appear nowhere under the second, sorted.
+    pub only_first: Vec<PathBuf>,
+    /// Files under the second tree whose contents appear nowhere under the first, sorted.
+    pub only_second: Vec<PathBuf>,
+    /// Paths, relative to each tree, of files present in both trees with different contents, sorted.
+    pub changed: Vec<PathBuf>,
+}
+
+impl TreeDifferences{
+    /// Checks whether the trees hold the same contents.
+    pub fn is_empty(&self) -> bool{
+        self.only_first.is_empty() && self.only_second.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// A file under the primary search directories with at least one copy under the comparison tree.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PresentFile{
+    /// The file under a primary search directory.
+    pub path: PathBuf,
+    /// Files under the comparison tree with the same contents, sorted.
+    pub copies: Vec<PathBuf>,
+}
+
+/// Finds the files under every search directory but `compare_root` that have a copy under the search directory at `compare_root`, sorted by path.
+/// Unlike `compare_trees` this only looks one way, listing files that are safe to delete from the primary directories because the comparison tree keeps their contents.
+///
+/// # Examples
+/// ```
+/// use std::fs;
+/// use ddh::compare::files_present_in;
+///
+/// let temp = tempfile::tempdir().unwrap();
+/// let root = temp.path();
+/// for tree in ["downloads", "archive/2024"].iter() {
+///     fs::create_dir_all(root.join(tree)).unwrap();
+/// }
+/// fs::write(root.join("downloads/report.pdf"), b"quarterly").unwrap();
+/// fs::write(root.join("archive/2024/q3.pdf"), b"quarterly").unwrap();
+/// fs::write(root.join("downloads/new.pdf"), b"not archived").unwrap();
+/// fs::write(root.join("archive/old.pdf"), b"only archived").unwrap();
+///
+/// let (downloads, archive) = (root.join("downloads"), root.join("archive"));
+/// let (files, _) = ddh::deduplicate_dirs(vec![&downloads, &archive]);
+/// let present = files_present_in(&files, 1);
+/// assert_eq!(1, present.len());
+/// assert_eq!(downloads.join("report.pdf"), present[0].path);
+/// assert_eq!(vec![archive.join("2024/q3.pdf")], present[0].copies);
+/// ```
+pub fn files_present_in(files: &[Fileinfo], compare_root: usize) -> Vec<PresentFile>{
+    let copies_of = |file: &Fileinfo| -> Vec<PathBuf> {
+        file.get_paths().iter().zip(file.get_roots())
+            .filter(|x| *x.1 == compare_root)
+            .map(|x| x.0.clone())
+            .collect()
+    };
+    // Empty files are left ungrouped unless EmptyFilePolicy::Group is chosen, so every empty file under the comparison tree is a copy of each empty primary file
+    let mut empty_copies: Vec<PathBuf> = files.iter().filter(|x| x.get_length() == 0).flat_map(copies_of).collect();
+    empty_copies.sort();
+    let mut present: Vec<PresentFile> = Vec::new();
+    for file in files.iter(){
+        let mut copies = if file.get_length() == 0 {empty_copies.clone()} else {copies_of(file)};
+        if copies.is_empty() {
+            continue
+        }
+        copies.sort();
+        for (path, _) in file.get_paths().iter().zip(file.get_roots()).filter(|x| *x.1 != compare_root){
+            present.push(PresentFile{path: path.clone(), copies: copies.clone()});
+        }
+    }
+    present.sort_by(|a, b| a.path.cmp(&b.path));
+    present
+}
+
+/// Compares the trees under `first` and `second`, which should be the first and second search directories of the scan giving `files`.
+/// Files at the same relative path with different contents are listed as changed rather than as only under either tree.
+/// Other contents are matched wherever they are, so a file renamed or moved in one tree is not a difference.
+///
+/// # Examples
+/// ```
+/// use std::fs;
+/// use std::path::PathBuf;
+/// use ddh::compare::compare_trees;
+///
+/// let temp = tempfile::tempdir().unwrap();
+/// let root = temp.path();
+/// for tree in ["original", "backup"].iter() {
+///     fs::create_dir_all(root.join(tree)).unwrap();
+///     fs::write(root.join(tree).join("same.txt"), b"kept").unwrap();
+/// }
+/// fs::write(root.join("original/notes.txt"), b"draft two").unwrap();
+/// fs::write(root.join("backup/notes.txt"), b"draft one").unwrap();
+/// fs::write(root.join("original/new.txt"), b"not backed up").unwrap();
+///
+/// let (first, second) = (root.join("original"), root.join("backup"));
+/// let (files, _) = ddh::deduplicate_dirs(vec![&first, &second]);
+/// let differences = compare_trees(&files, &first, &second);
+/// assert_eq!(vec![first.join("new.txt")], differences.only_first);
+/// assert!(differences.only_second.is_empty());
+/// assert_eq!(vec![PathBuf::from("notes.txt")], differences.changed);
+/// assert!(!differences.is_empty());
+/// ```
+pub fn compare_trees(files: &[Fileinfo], first: &Path, second: &Path) -> TreeDifferences{
+    // Empty files are left ungrouped unless EmptyFilePolicy::Group is chosen, so they share one content between them
+    let content_of = |index: usize| if files[index].get_length() == 0 {usize::MAX} else {index};
+    let mut relative: [HashMap<&Path, usize>; 2] = [HashMap::new(), HashMap::new()];
+    let mut contents: [HashSet<usize>; 2] = [HashSet::new(), HashSet::new()];
+    for (index, file) in files.iter().enumerate(){
+        for (path, &root) in file.get_paths().iter().zip(file.get_roots()){
+            let tree = match root {0 => first, 1 => second, _ => continue};
+            if let Ok(path) = path.strip_prefix(tree){
+                relative[root].insert(path, content_of(index));
+            }
+            contents[root].insert(content_of(index));
+        }
+    }
+    let mut differences = TreeDifferences::default();
+    for (path, content) in relative[0].iter(){
+        if relative[1].get(path).is_some_and(|x| x != content){
+            differences.changed.push(path.to_path_buf());
+        }
+    }
+    for (index, file) in files.iter().enumerate(){
+        for (path, &root) in file.get_paths().iter().zip(file.get_roots()){
+            let (tree, other, only) = match root {
+                0 => (first, 1, &mut differences.only_first),
+                1 => (second, 0, &mut differences.only_second),
+                _ => continue,
+            };
+            let changed = path.strip_prefix(tree).is_ok_and(|x| {
+                relative[other].get(x).is_some_and(|&y| y != content_of(index))
+            });
+            if !changed && !contents[other].contains(&content_of(index)){
+                only.push(path.clone());
+            }
+        }
+    }
+    differences.only_first.sort();
+    differences.only_second.sort();
+    differences.changed.sort();
+    differences
+}
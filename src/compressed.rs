@@ -0,0 +1,195 @@
+//! Detection of compressed files whose decompressed contents duplicate plain files, built on the file groups from a completed scan.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Error, ErrorKind, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use flate2::read::MultiGzDecoder;
+use rayon::prelude::*;
+use serde_derive::Serialize;
+use crate::{ContentHasher, Fileinfo, HashAlgorithm, ScanOptions};
+
+/// A compression format whose files can be decompressed and compared with plain files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Codec{
+    /// Gzip, as written by gzip, pigz and zlib's gz functions, with any number of members.
+    Gzip,
+}
+
+/// Every supported codec.
+pub const CODECS: &[Codec] = &[Codec::Gzip];
+
+impl Codec{
+    /// Gets the extensions of files compressed with the codec, in lowercase.
+    pub fn extensions(self) -> &'static [&'static str]{
+        match self {
+            Codec::Gzip => &["gz", "tgz"],
+        }
+    }
+    /// Gets the codec of a path from its extension, ignoring case.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::path::Path;
+    /// use ddh::compressed::Codec;
+    ///
+    /// assert_eq!(Some(Codec::Gzip), Codec::from_path(Path::new("logs/app.log.GZ")));
+    /// assert_eq!(None, Codec::from_path(Path::new("logs/app.log")));
+    /// ```
+    pub fn from_path(path: &Path) -> Option<Codec>{
+        let extension = path.extension()?.to_string_lossy().to_lowercase();
+        CODECS.iter().copied().find(|x| x.extensions().contains(&extension.as_str()))
+    }
+    /// Wraps a reader of compressed data in a reader of the decompressed data. Reads fail with `ErrorKind::InvalidData` if the data is corrupt
+    /// or fails its checksum, and with `ErrorKind::UnexpectedEof` if it ends partway through.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::io::Read;
+    /// use ddh::compressed::Codec;
+    ///
+    /// // "one two three, one two three, one two three\n", as compressed by gzip
+    /// let compressed: &[u8] = &[0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0xcb, 0xcf, 0x4b, 0x55, 0x28, 0x29, 0xcf, 0x57, 0x28,
+    ///     0xc9, 0x28, 0x4a, 0x4d, 0xd5, 0x51, 0xc8, 0xc7, 0xc3, 0xe5, 0x02, 0x00, 0x3e, 0x15, 0x30, 0x93, 0x2c, 0x00, 0x00, 0x00];
+    /// let mut text = String::new();
+    /// Codec::Gzip.decoder(compressed).read_to_string(&mut text).unwrap();
+    /// assert_eq!("one two three, one two three, one two three\n", text);
+    ///
+    /// let mut corrupt = compressed.to_vec();
+    /// corrupt[31] ^= 1;
+    /// assert!(Codec::Gzip.decoder(corrupt.as_slice()).read_to_end(&mut Vec::new()).is_err());
+    /// ```
+    pub fn decoder<'a, R: Read + 'a>(self, reader: R) -> Box<dyn Read + 'a>{
+        match self {
+            Codec::Gzip => Box::new(MultiGzDecoder::new(reader)),
+        }
+    }
+    // Gets the decompressed length recorded in the file, modulo 2^32, where the format records one. Gzip records only the length of the last member
+    fn length_hint(self, path: &Path) -> Option<u32>{
+        match self {
+            Codec::Gzip => {
+                let mut file = File::open(path).ok()?;
+                file.seek(SeekFrom::End(-4)).ok()?;
+                let mut trailer = [0u8; 4];
+                file.read_exact(&mut trailer).ok()?;
+                Some(u32::from_le_bytes(trailer))
+            },
+        }
+    }
+}
+
+/// A compressed file whose decompressed contents match plain files in the scan.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CompressedCopy{
+    /// The paths of the compressed file and any exact copies of it, sorted.
+    pub compressed: Vec<PathBuf>,
+    /// The codec the file is compressed with.
+    pub codec: Codec,
+    /// The plain files holding the decompressed contents, sorted.
+    pub plain: Vec<PathBuf>,
+    /// The decompressed length in bytes.
+    pub length: u64,
+}
+
+/// Finds compressed files under the scan, recognized by the `extensions` of each codec, whose decompressed contents match plain files under it.
+/// Only compressed files whose recorded length is shared by a plain file are decompressed, so gzip files of several members, which record only the
+/// length of their last member, are matched only if that is the whole length. Matches are compared byte for byte unless `ScanOptions::trust_full_hash` is set.
+/// Files that cannot be read or decompressed are skipped, as are empty plain files. Results are sorted by their first compressed path.
+///
+/// # Examples
+/// ```
+/// use std::fs;
+/// use ddh::ScanOptions;
+/// use ddh::compressed::find_compressed_copies;
+///
+/// let temp = tempfile::tempdir().unwrap();
+/// let root = temp.path();
+/// fs::write(root.join("counts.txt.gz"), [0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0xcb, 0xcf, 0x4b, 0x55, 0x28, 0x29, 0xcf,
+///     0x57, 0x28, 0xc9, 0x28, 0x4a, 0x4d, 0xd5, 0x51, 0xc8, 0xc7, 0xc3, 0xe5, 0x02, 0x00, 0x3e, 0x15, 0x30, 0x93, 0x2c, 0x00, 0x00, 0x00]).unwrap();
+/// fs::write(root.join("counts.txt"), "one two three, one two three, one two three\n").unwrap();
+/// fs::write(root.join("other.txt"), "one two three, one two three, one two thre3\n").unwrap();
+///
+/// let options = ScanOptions::default();
+/// let (files, _) = ddh::deduplicate_dirs_with(vec![&root], &options);
+/// let copies = find_compressed_copies(&files, &options);
+/// assert_eq!(1, copies.len());
+/// assert_eq!(vec![root.join("counts.txt.gz")], copies[0].compressed);
+/// assert_eq!(vec![root.join("counts.txt")], copies[0].plain);
+/// assert_eq!(44, copies[0].length);
+/// ```
+pub fn find_compressed_copies(files: &[Fileinfo], options: &ScanOptions) -> Vec<CompressedCopy>{
+    let codecs: Vec<Option<Codec>> = files.iter().map(|x| x.get_paths().iter().find_map(|y| Codec::from_path(y))).collect();
+    let mut plain_of_lengths: HashMap<u32, Vec<&Fileinfo>> = HashMap::new();
+    for (file, codec) in files.iter().zip(codecs.iter()){
+        if codec.is_none() && file.get_length() > 0 {
+            plain_of_lengths.entry(file.get_length() as u32).or_default().push(file);
+        }
+    }
+    let mut copies: Vec<CompressedCopy> = files.par_iter()
+        .zip(codecs.par_iter())
+        .filter_map(|(file, codec)| {
+            let codec = (*codec)?;
+            let path = file.get_paths_sorted().into_iter().next()?;
+            let candidates = plain_of_lengths.get(&codec.length_hint(path)?)?;
+            let (hash, length) = decompressed_hash(codec, path, options.hash_algorithm).ok()?;
+            let mut plain: Vec<PathBuf> = candidates.iter()
+                .filter(|x| x.get_length() == length)
+                .filter(|x| plain_hash(x, options).ok() == Some(hash))
+                .filter(|x| options.trust_full_hash || same_decompressed(codec, path, &x.get_paths()[0]).unwrap_or(false))
+                .flat_map(|x| x.get_paths().iter().cloned())
+                .collect();
+            if plain.is_empty() {
+                return None
+            }
+            plain.sort();
+            Some(CompressedCopy{compressed: file.get_paths_sorted().into_iter().cloned().collect(), codec, plain, length})
+        })
+        .collect();
+    copies.sort_by(|a, b| a.compressed.cmp(&b.compressed));
+    copies
+}
+
+fn decompressed_hash(codec: Codec, path: &Path, algorithm: HashAlgorithm) -> Result<(u128, u64), Error>{
+    hash_reader(codec.decoder(File::open(path)?), algorithm)
+}
+
+// Full hashes from the scan are of the whole file only without a hash range
+fn plain_hash(file: &Fileinfo, options: &ScanOptions) -> Result<u128, Error>{
+    match file.get_full_hash() {
+        Some(hash) if options.hash_range.is_none() => Ok(hash),
+        _ => hash_reader(File::open(&file.get_paths()[0])?, options.hash_algorithm).map(|x| x.0),
+    }
+}
+
+fn hash_reader<R: Read>(mut reader: R, algorithm: HashAlgorithm) -> Result<(u128, u64), Error>{
+    let mut hasher = ContentHasher::new(algorithm);
+    let mut buffer = vec![0u8; 64*1024];
+    let mut length = 0;
+    loop {
+        let n = reader.read(&mut buffer)?;
+        if n == 0 {
+            return Ok((hasher.finish(), length))
+        }
+        hasher.write(&buffer[..n]);
+        length += n as u64;
+    }
+}
+
+fn same_decompressed(codec: Codec, compressed: &Path, plain: &Path) -> Result<bool, Error>{
+    let (mut a, mut b) = (codec.decoder(File::open(compressed)?), BufReader::new(File::open(plain)?));
+    let mut a_buffer = vec![0u8; 64*1024];
+    let mut b_buffer = vec![0u8; 64*1024];
+    loop {
+        let n = a.read(&mut a_buffer)?;
+        if n == 0 {
+            return Ok(b.read(&mut b_buffer[..1])? == 0)
+        }
+        if let Err(e) = b.read_exact(&mut b_buffer[..n]) {
+            return if e.kind() == ErrorKind::UnexpectedEof {Ok(false)} else {Err(e)}
+        }
+        if a_buffer[..n] != b_buffer[..n] {
+            return Ok(false)
+        }
+    }
+}
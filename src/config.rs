@@ -0,0 +1,126 @@
+//! Scan configuration files in TOML, as read by `--config`. Keys are the long names of command line options, and `roots` lists the directories to search.
+
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use serde_derive::Deserialize;
+
+/// The settings of a configuration file, each field holding the command line option of the same name. Flags are set with `true`, and `false` or a missing key leaves them unset.
+/// Unknown keys are rejected rather than ignored, as are tables, since no option has a dotted name.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config{
+    /// Directories to search when the command line names none.
+    pub roots: Vec<String>,
+    pub follow_symlinks: bool,
+    pub one_file_system: bool,
+    pub exclude: Vec<String>,
+    pub exclude_from: Option<String>,
+    pub include_ext: Vec<String>,
+    pub exclude_ext: Vec<String>,
+    pub path_filter: Vec<String>,
+    pub path_filter_out: Vec<String>,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    pub empty_files: Option<String>,
+    pub hash_algorithm: Option<String>,
+    pub threads: Option<usize>,
+    pub trust_full_hash: bool,
+    pub tree_cache: Option<String>,
+    pub xattr_cache: bool,
+    pub format: Option<String>,
+    pub json_shape: Option<String>,
+    pub output: Option<String>,
+    pub verbosity: Option<String>,
+    pub min_files: Option<u64>,
+    pub limit: Option<u64>,
+    pub assume_yes: bool,
+    pub keep_newest: bool,
+    pub keep_oldest: bool,
+    pub keep_shallowest: bool,
+    pub exclude_older_backups: bool,
+    pub keep_under: Option<String>,
+    pub delete: bool,
+    pub hardlink: bool,
+    pub dry_run: bool,
+    pub prune_empty_after_delete: bool,
+    pub emit_script: Option<String>,
+}
+
+impl Config{
+    /// Keys whose values add to those given on the command line rather than giving way to them.
+    pub const LISTS: [&'static str; 5] = ["exclude", "include-ext", "exclude-ext", "path-filter", "path-filter-out"];
+    /// Parses a configuration file's text. Errors name the key at fault and where it was found.
+    ///
+    /// # Examples
+    /// ```
+    /// use ddh::config::Config;
+    ///
+    /// let config = Config::parse(r#"
+    /// ## Weekly check of the photo archive
+    /// roots = ["/mnt/photos", '/mnt/backup\photos']
+    /// min-size = 1_024
+    /// keep-newest = true
+    /// delete = false
+    /// exclude = [
+    ///     "*.tmp",   # editor leftovers
+    ///     "Thumbs.db",
+    /// ]
+    /// "#).unwrap();
+    /// assert_eq!(vec!["/mnt/photos", "/mnt/backup\\photos"], config.roots);
+    /// assert_eq!(vec!["--keep-newest", "--min-size=1024", "--exclude=*.tmp", "--exclude=Thumbs.db"], config.options());
+    ///
+    /// assert!(Config::parse("typo-key = false\n").unwrap_err().to_string().contains("unknown field `typo-key`"));
+    /// assert!(Config::parse("[report]\nformat = \"json\"\n").is_err());
+    /// assert!(Config::parse("min-size = \"large\"\n").is_err());
+    /// ```
+    pub fn parse(text: &str) -> Result<Self, Error>{
+        toml::from_str(text).map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))
+    }
+    /// Reads and parses a configuration file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error>{
+        Config::parse(&fs::read_to_string(path)?)
+    }
+    /// Gets the settings as command line options, other than the roots. Flags come first, then options with a value, then each value of a list.
+    pub fn options(&self) -> Vec<String>{
+        let flags = [
+            ("follow-symlinks", self.follow_symlinks),
+            ("one-file-system", self.one_file_system),
+            ("trust-full-hash", self.trust_full_hash),
+            ("xattr-cache", self.xattr_cache),
+            ("assume-yes", self.assume_yes),
+            ("keep-newest", self.keep_newest),
+            ("keep-oldest", self.keep_oldest),
+            ("keep-shallowest", self.keep_shallowest),
+            ("exclude-older-backups", self.exclude_older_backups),
+            ("delete", self.delete),
+            ("hardlink", self.hardlink),
+            ("dry-run", self.dry_run),
+            ("prune-empty-after-delete", self.prune_empty_after_delete),
+        ];
+        let values = [
+            ("exclude-from", self.exclude_from.clone()),
+            ("min-size", self.min_size.map(|x| x.to_string())),
+            ("max-size", self.max_size.map(|x| x.to_string())),
+            ("empty-files", self.empty_files.clone()),
+            ("hash-algorithm", self.hash_algorithm.clone()),
+            ("threads", self.threads.map(|x| x.to_string())),
+            ("tree-cache", self.tree_cache.clone()),
+            ("format", self.format.clone()),
+            ("json-shape", self.json_shape.clone()),
+            ("output", self.output.clone()),
+            ("verbosity", self.verbosity.clone()),
+            ("min-files", self.min_files.map(|x| x.to_string())),
+            ("limit", self.limit.map(|x| x.to_string())),
+            ("keep-under", self.keep_under.clone()),
+            ("emit-script", self.emit_script.clone()),
+        ];
+        let lists = Config::LISTS.iter().zip([&self.exclude, &self.include_ext, &self.exclude_ext, &self.path_filter, &self.path_filter_out]);
+        let mut options: Vec<String> = flags.iter().filter(|x| x.1).map(|x| format!("--{}", x.0)).collect();
+        options.extend(values.iter().filter_map(|(key, value)| value.as_ref().map(|x| format!("--{}={}", key, x))));
+        for (key, list) in lists{
+            options.extend(list.iter().map(|x| format!("--{}={}", key, x)));
+        }
+        options
+    }
+}
@@ -0,0 +1,423 @@
+//! Consolidation of same-length files into groups of identical content.
+
+use std::collections::hash_map::{HashMap, Entry};
+use std::io::Error;
+use std::path::PathBuf;
+use rayon::prelude::*;
+use crate::{actions, profile, ContentHasher, EmptyFilePolicy, Fileinfo, HashMode, HashPolicy, ScanOptions, utils};
+use crate::profile::Stage;
+use crate::scan::LengthBuckets;
+
+/// Paths which could not be read for their partial or full hash or to compare their bytes, with the error that left each out of the results.
+type HashErrors = Vec<(PathBuf, Error)>;
+
+pub(crate) fn consolidate(mut files_of_lengths: LengthBuckets, options: &ScanOptions) -> (Vec<Fileinfo>, HashErrors){
+    let empty_files = files_of_lengths.remove(&0).unwrap_or_default();
+    let (mut files, errors) = if options.content_only {
+        consolidate_by_content(files_of_lengths.into_iter().flat_map(|x| x.1).collect(), options)
+    } else {
+        files_of_lengths.into_par_iter()
+        .map(|x| differentiate_and_consolidate(x.0, x.1, options))
+        .reduce(|| (Vec::new(), Vec::new()), |mut a, b| {
+            a.0.extend(b.0);
+            a.1.extend(b.1);
+            a
+        })
+    };
+    files.extend(consolidate_empty(empty_files, options));
+    (files, errors)
+}
+
+// Empty files all hold the same nothing, so the policy alone decides whether they are grouped, listed alone or left out. Grouped files are given
+// the hash of no bytes rather than being read
+fn consolidate_empty(files: Vec<Fileinfo>, options: &ScanOptions) -> Vec<Fileinfo>{
+    match options.empty_files {
+        EmptyFilePolicy::Unique => files,
+        EmptyFilePolicy::Skip => Vec::new(),
+        EmptyFilePolicy::Group => {
+            let hash = ContentHasher::new(options.hash_algorithm).finish();
+            let files = group_by_hash(files.into_iter().map(|mut x| {
+                x.set_partial_hash(Some(hash));
+                x.set_full_hash(Some(hash));
+                x
+            }).collect());
+            if options.require_name_match{
+                return files.into_iter().flat_map(split_by_name).collect()
+            }
+            files
+        },
+    }
+}
+
+fn differentiate_and_consolidate(file_length: u64, mut files: Vec<Fileinfo>, options: &ScanOptions) -> (Vec<Fileinfo>, HashErrors){
+    if files.is_empty(){
+        panic!("Invalid length vector");
+    }
+    let mut errors: HashErrors = match files.len(){
+        1 => return (files, Vec::new()),
+        n if n>1 && options.hash_only_full => {
+            if let Some(p) = &options.progress{
+                p.add_total(n as u64*file_length);
+            }
+            files.par_iter_mut().filter_map(|x| full_hash(x, options)).collect()
+        },
+        n if n>1 => {
+            let mut errors: HashErrors = files.par_iter_mut()
+                .filter(|x| x.get_partial_hash().is_none())
+                .filter_map(|x| partial_hash(x, options))
+                .collect();
+            files.retain(|x| !x.file_paths.is_empty());
+            if file_length<=4096{
+                files.par_iter_mut().for_each(|x|{
+                    x.set_full_hash(x.get_partial_hash()) ;
+                });
+                let (files, compare_errors) = dedupe(files, options);
+                errors.extend(compare_errors);
+                return (files, errors)
+            }
+            // Counts each partial hash and notes whether any file sharing it needs a full hash to confirm the match
+            let mut partial_hashes: HashMap<u128, (u64, bool)> = HashMap::new();
+            for file in files.iter(){
+                let hash = match file.get_partial_hash() {
+                    Some(hash) => hash,
+                    None => continue,
+                };
+                let needs_full = file.get_roots().iter().any(|&x| options.hash_policy(x) == HashPolicy::Full);
+                match partial_hashes.entry(hash){
+                    Entry::Vacant(e) => { e.insert((0, needs_full)); },
+                    Entry::Occupied(mut e) => {
+                        let entry = e.get_mut();
+                        entry.0+=1;
+                        entry.1|=needs_full;
+                    }
+                }
+            }
+            let dedupe_hashes: Vec<_> = partial_hashes
+                .into_iter()
+                .filter(|x| x.1.0>0 && x.1.1)
+                .map(|y| y.0)
+                .collect();
+            if let Some(p) = &options.progress{
+                let candidates = files.iter()
+                    .filter(|x| x.get_partial_hash().is_some_and(|y| dedupe_hashes.contains(&y)))
+                    .count() as u64;
+                p.add_total(candidates*file_length);
+            }
+            errors.par_extend(files.par_iter_mut()
+                .filter(|x| x.get_partial_hash().is_some_and(|y| dedupe_hashes.contains(&y)))
+                .filter_map(|x| full_hash(x, options)));
+            errors
+        },
+        _ => {panic!("Somehow a vector of negative length was created. Please report this as a bug");}
+    };
+    let (files, compare_errors) = dedupe(files, options);
+    errors.extend(compare_errors);
+    (files, errors)
+}
+
+fn consolidate_by_content(mut files: Vec<Fileinfo>, options: &ScanOptions) -> (Vec<Fileinfo>, HashErrors){
+    if let Some(p) = &options.progress{
+        p.add_total(files.iter().map(|x| x.get_length()).sum());
+    }
+    let mut errors: HashErrors = files.par_iter_mut().filter_map(|x| full_hash(x, options)).collect();
+    let (files, compare_errors) = dedupe(files, options);
+    errors.extend(compare_errors);
+    (files, errors)
+}
+
+// Files which can no longer be read, usually because they were deleted or moved since traversal, lose their paths so grouping drops them, and their error is returned
+fn full_hash(file: &mut Fileinfo, options: &ScanOptions) -> Option<(PathBuf, Error)>{
+    match file.generate_hash(HashMode::Full, options) {
+        Err(e) if !options.is_stopped() => Some(drop_unreadable(file, e, options)),
+        hash => {
+            file.set_full_hash(hash.ok().flatten());
+            None
+        },
+    }
+}
+
+/// Partially hashes `file`, leaving it out of the results and returning its error as `full_hash` does when it cannot be read.
+pub(crate) fn partial_hash(file: &mut Fileinfo, options: &ScanOptions) -> Option<(PathBuf, Error)>{
+    match file.generate_hash(HashMode::Partial, options) {
+        Err(e) if !options.is_stopped() => Some(drop_unreadable(file, e, options)),
+        hash => {
+            file.set_partial_hash(hash.ok().flatten());
+            None
+        },
+    }
+}
+
+fn drop_unreadable(file: &mut Fileinfo, error: Error, options: &ScanOptions) -> (PathBuf, Error){
+    if let Some(stats) = &options.stats{
+        stats.record_vanished(file.file_paths.len() as u64);
+    }
+    let path = file.file_paths[0].clone();
+    file.file_paths.clear();
+    file.file_roots.clear();
+    file.file_modified.clear();
+    file.sparse_paths.clear();
+    file.aliases.clear();
+    file.set_partial_hash(None);
+    file.set_full_hash(None);
+    (path, error)
+}
+
+/// Groups files whose full hashes are already known and cannot be read again, as with archive members, trusting the hashes.
+pub(crate) fn consolidate_hashed(files: Vec<Fileinfo>, options: &ScanOptions) -> Vec<Fileinfo>{
+    let files = profile::measure(options.profile.as_deref(), Stage::Grouping, || group_by_hash(files));
+    if options.require_name_match{
+        return files.into_iter().flat_map(split_by_name).collect()
+    }
+    files
+}
+
+/// Groups files of each length by name alone, without hashing or reading any of them. Empty files follow `ScanOptions::empty_files`, as when grouping by content.
+pub(crate) fn consolidate_by_name(mut files_of_lengths: LengthBuckets, options: &ScanOptions) -> Vec<Fileinfo>{
+    let empty_files = consolidate_empty(files_of_lengths.remove(&0).unwrap_or_default(), options);
+    files_of_lengths.into_par_iter()
+        .flat_map_iter(|(_, mut files)| {
+            if files.len() < 2 {
+                return files
+            }
+            let mut group = files.swap_remove(0);
+            for mut file in files.into_iter(){
+                group.file_paths.append(&mut file.file_paths);
+                group.file_roots.append(&mut file.file_roots);
+                group.file_modified.append(&mut file.file_modified);
+                group.sparse_paths.append(&mut file.sparse_paths);
+                group.aliases.append(&mut file.aliases);
+            }
+            split_by_name(group)
+        })
+        .chain(empty_files)
+        .collect()
+}
+
+fn dedupe(files: Vec<Fileinfo>, options: &ScanOptions) -> (Vec<Fileinfo>, HashErrors){
+    let mut files = profile::measure(options.profile.as_deref(), Stage::Grouping, || group_by_hash(files));
+    let mut errors = Vec::new();
+    if !options.trust_full_hash{
+        (files, errors) = profile::measure(options.profile.as_deref(), Stage::Comparing, || files.into_par_iter().map(|x| split_by_contents(x, options))
+            .reduce(|| (Vec::new(), Vec::new()), |mut a, b| {
+                a.0.extend(b.0);
+                a.1.extend(b.1);
+                a
+            }));
+    }
+    if options.require_name_match{
+        files = files.into_iter().flat_map(split_by_name).collect();
+    }
+    (files, errors)
+}
+
+fn group_by_hash(mut files: Vec<Fileinfo>) -> Vec<Fileinfo>{
+    let mut cache: HashMap<(Option<u128>, Option<u128>), &mut Fileinfo> = HashMap::new();
+    for file in files.iter_mut(){
+        if file.get_partial_hash().is_none() && file.get_full_hash().is_none(){
+            continue
+        }
+        match cache.entry((file.get_partial_hash(), file.get_full_hash())){
+                    Entry::Vacant(e) => {
+                        e.insert(file);
+                    },
+                    Entry::Occupied(mut e) => {
+                        e.get_mut()
+                        .file_paths
+                        .append(&mut file.file_paths);
+                        e.get_mut()
+                        .file_roots
+                        .append(&mut file.file_roots);
+                        e.get_mut()
+                        .file_modified
+                        .append(&mut file.file_modified);
+                        e.get_mut()
+                        .sparse_paths
+                        .append(&mut file.sparse_paths);
+                        e.get_mut()
+                        .aliases
+                        .append(&mut file.aliases);
+                    }
+                }
+    }
+    files.retain(|x| !x.get_paths().is_empty());
+    files
+}
+
+// Splits a group matched by full hash into sets of byte identical files, guarding against hash collisions.
+// Paths which cannot be read for comparison are left out of every set, as they cannot be shown to match, and their error is returned
+fn split_by_contents(file: Fileinfo, options: &ScanOptions) -> (Vec<Fileinfo>, HashErrors){
+    if file.file_paths.len()<2 || file.full_hash.is_none(){
+        return (vec![file], Vec::new())
+    }
+    let mut sets: Vec<Fileinfo> = Vec::new();
+    let mut errors = Vec::new();
+    for ((path, root), modified) in file.file_paths.into_iter().zip(file.file_roots).zip(file.file_modified){
+        let sparse = file.sparse_paths.contains(&path);
+        let aliases: Vec<(PathBuf, PathBuf)> = file.aliases.iter().filter(|x| x.1 == path).cloned().collect();
+        let matching = sets.iter().enumerate().find_map(|(i, x)| {
+            match actions::same_range_contents(&x.file_paths[0], &path, options.hash_range, options.throttle.as_deref()) {
+                Ok(true) => Some(Ok(i)),
+                Ok(false) => None,
+                Err(e) => Some(Err(e)),
+            }
+        }).transpose();
+        let matching = match matching {
+            Ok(matching) => matching,
+            Err(e) => {
+                errors.push((path, e));
+                continue
+            },
+        };
+        match matching.and_then(|x| sets.get_mut(x)){
+            Some(set) => {
+                if sparse {
+                    set.sparse_paths.push(path.clone());
+                }
+                set.aliases.extend(aliases);
+                set.file_paths.push(path);
+                set.file_roots.push(root);
+                set.file_modified.push(modified);
+            },
+            None => {
+                sets.push(Fileinfo::new(file.full_hash, file.partial_hash, file.file_length, path).with_root(root).with_modified(modified).with_sparse(sparse).with_aliases(aliases));
+            },
+        }
+    }
+    (sets, errors)
+}
+
+fn split_by_name(file: Fileinfo) -> Vec<Fileinfo>{
+    if file.file_paths.len()<2{
+        return vec![file]
+    }
+    let mut names: HashMap<Option<String>, Fileinfo> = HashMap::new();
+    for ((path, root), modified) in file.file_paths.into_iter().zip(file.file_roots).zip(file.file_modified){
+        let sparse = file.sparse_paths.contains(&path);
+        let aliases: Vec<(PathBuf, PathBuf)> = file.aliases.iter().filter(|x| x.1 == path).cloned().collect();
+        match names.entry(utils::normalized_file_name(&path)){
+            Entry::Vacant(e) => {
+                e.insert(Fileinfo::new(file.full_hash, file.partial_hash, file.file_length, path).with_root(root).with_modified(modified).with_sparse(sparse).with_aliases(aliases));
+            },
+            Entry::Occupied(mut e) => {
+                if sparse {
+                    e.get_mut().sparse_paths.push(path.clone());
+                }
+                e.get_mut().aliases.extend(aliases);
+                e.get_mut().file_paths.push(path);
+                e.get_mut().file_roots.push(root);
+                e.get_mut().file_modified.push(modified);
+            }
+        }
+    }
+    names.into_values().collect()
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+    use nohash_hasher::IntMap;
+
+    fn buckets(paths: &[PathBuf]) -> LengthBuckets{
+        let mut buckets: LengthBuckets = IntMap::default();
+        for path in paths.iter(){
+            let length = fs::metadata(path).unwrap().len();
+            buckets.entry(length).or_default().push(Fileinfo::new(None, None, length, path.clone()));
+        }
+        buckets
+    }
+
+    fn write(dir: &Path, name: &str, contents: &[u8]) -> PathBuf{
+        let path = dir.join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn groups(files: &[Fileinfo]) -> Vec<Vec<&PathBuf>>{
+        let mut groups: Vec<Vec<&PathBuf>> = files.iter().map(|x| x.get_paths_sorted()).collect();
+        groups.sort();
+        groups
+    }
+
+    #[test]
+    fn groups_identical_contents_beyond_the_partial_hash(){
+        let dir = tempfile::tempdir().unwrap();
+        let mut contents = vec![7u8; 64*1024];
+        let a = write(dir.path(), "a", &contents);
+        let b = write(dir.path(), "b", &contents);
+        // Shares the bytes a partial hash reads with a and b, so only a full hash tells it apart
+        *contents.last_mut().unwrap() = 8;
+        let c = write(dir.path(), "c", &contents);
+        let d = write(dir.path(), "d", b"short");
+        let (files, errors) = consolidate(buckets(&[a.clone(), b.clone(), c.clone(), d.clone()]), &ScanOptions::default());
+        assert!(errors.is_empty());
+        assert_eq!(vec![vec![&a, &b], vec![&c], vec![&d]], groups(&files));
+        let ab = files.iter().find(|x| x.get_paths().len() == 2).unwrap();
+        let c = files.iter().find(|x| x.get_paths()[0] == c).unwrap();
+        assert_eq!(ab.get_partial_hash(), c.get_partial_hash());
+        assert_ne!(ab.get_full_hash(), c.get_full_hash());
+        assert!(files.iter().find(|x| x.get_paths()[0] == d).unwrap().get_partial_hash().is_none());
+    }
+
+    #[test]
+    fn splits_groups_whose_hashes_collide(){
+        let dir = tempfile::tempdir().unwrap();
+        let a = write(dir.path(), "a", b"one");
+        let b = write(dir.path(), "b", b"two");
+        let colliding = || vec![Fileinfo::new(Some(1), Some(1), 3, a.clone()), Fileinfo::new(Some(1), Some(1), 3, b.clone())];
+        assert_eq!(vec![vec![&a], vec![&b]], groups(&dedupe(colliding(), &ScanOptions::default()).0));
+        let trusting = ScanOptions::builder().trust_full_hash(true).build();
+        assert_eq!(vec![vec![&a, &b]], groups(&dedupe(colliding(), &trusting).0));
+    }
+
+    #[test]
+    fn leaves_out_paths_which_cannot_be_compared(){
+        let dir = tempfile::tempdir().unwrap();
+        let a = write(dir.path(), "a", b"one");
+        let b = write(dir.path(), "b", b"one");
+        let missing = dir.path().join("missing");
+        let hashed = vec![Fileinfo::new(Some(1), Some(1), 3, a.clone()), Fileinfo::new(Some(1), Some(1), 3, missing.clone()), Fileinfo::new(Some(1), Some(1), 3, b.clone())];
+        let (files, errors) = dedupe(hashed, &ScanOptions::default());
+        assert_eq!(vec![vec![&a, &b]], groups(&files));
+        assert_eq!(vec![&missing], errors.iter().map(|x| &x.0).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn reports_paths_which_cannot_be_partially_hashed(){
+        let dir = tempfile::tempdir().unwrap();
+        let a = write(dir.path(), "a", b"one");
+        let b = write(dir.path(), "b", b"one");
+        let missing = dir.path().join("missing");
+        let mut files_of_lengths: LengthBuckets = IntMap::default();
+        files_of_lengths.insert(3, [&a, &missing, &b].iter().map(|x| Fileinfo::new(None, None, 3, x.to_path_buf())).collect());
+        let (files, errors) = consolidate(files_of_lengths, &ScanOptions::default());
+        assert_eq!(vec![vec![&a, &b]], groups(&files));
+        assert_eq!(vec![&missing], errors.iter().map(|x| &x.0).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn empty_files_follow_their_policy(){
+        let dir = tempfile::tempdir().unwrap();
+        let a = write(dir.path(), "a", b"");
+        let b = write(dir.path(), "b", b"");
+        let consolidated = |policy| consolidate(buckets(&[a.clone(), b.clone()]), &ScanOptions::builder().empty_files(policy).build()).0;
+        assert_eq!(vec![vec![&a], vec![&b]], groups(&consolidated(EmptyFilePolicy::Unique)));
+        assert_eq!(vec![vec![&a, &b]], groups(&consolidated(EmptyFilePolicy::Group)));
+        assert!(consolidated(EmptyFilePolicy::Skip).is_empty());
+    }
+
+    #[test]
+    fn groups_by_name_without_reading(){
+        let first = tempfile::tempdir().unwrap();
+        let second = tempfile::tempdir().unwrap();
+        let a = write(first.path(), "report.txt", b"one");
+        let b = write(second.path(), "report.txt", b"two");
+        let c = write(second.path(), "other.txt", b"one");
+        let files = consolidate_by_name(buckets(&[a.clone(), b.clone(), c.clone()]), &ScanOptions::default());
+        let mut expected = vec![vec![&c], {let mut x = vec![&a, &b]; x.sort(); x}];
+        expected.sort();
+        assert_eq!(expected, groups(&files));
+        assert!(files.iter().all(|x| x.get_full_hash().is_none()));
+    }
+}
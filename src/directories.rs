@@ -0,0 +1,252 @@
+//! Detection of whole directory trees that duplicate each other, of same-named trees which mostly do, and of directories holding copies of their own files,
+//! built on the file groups from a completed scan.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use serde_derive::Serialize;
+use crate::{utils, Fileinfo};
+
+/// Directories whose trees hold the same contents, compared as a multiset of files regardless of names or layout.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DirectoryGroup{
+    /// The duplicate directories, sorted.
+    pub paths: Vec<PathBuf>,
+    /// Files in each directory tree.
+    pub files: usize,
+    /// Bytes in each directory tree.
+    pub bytes: u64,
+}
+
+/// A directory holding more than one copy of the same contents directly, not counting copies in its subdirectories.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RedundantDirectory{
+    /// The directory.
+    pub path: PathBuf,
+    /// Files in the directory beyond the first copy of each of their contents. Three copies of one file count as two.
+    pub duplicates: usize,
+    /// Bytes freed by keeping only the first copy of each content in the directory.
+    pub bytes: u64,
+}
+
+/// Two directories with the same name whose trees share most of their contents, such as two copies of a photo library which have drifted apart.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OverlappingDirectories{
+    /// The directory sorting first.
+    pub first: PathBuf,
+    /// The other directory.
+    pub second: PathBuf,
+    /// Distinct contents found in both trees.
+    pub shared: usize,
+    /// Distinct contents found in either tree.
+    pub contents: usize,
+    /// The fraction of contents shared, `shared / contents`.
+    pub overlap: f64,
+}
+
+#[derive(Default)]
+struct DirectoryContents{
+    contents: Vec<usize>,
+    bytes: u64,
+    direct_files: bool,
+    children: HashSet<PathBuf>,
+}
+
+/// Finds directories under the search directories whose trees hold exactly the same file contents.
+///
+/// Directories holding no files of their own and a single subdirectory are skipped in favour of that subdirectory.
+/// A group is only reported when it is not already covered by a duplicate parent, so a copied backup is reported once rather than once per subdirectory.
+/// `files` and `search_dirs` should come from the same scan.
+///
+/// # Examples
+/// ```
+/// use std::fs;
+/// use ddh::directories::find_duplicate_directories;
+///
+/// let temp = tempfile::tempdir().unwrap();
+/// let root = temp.path();
+/// fs::create_dir_all(root.join("photos/2020")).unwrap();
+/// fs::create_dir_all(root.join("backup/2020")).unwrap();
+/// fs::write(root.join("photos/2020/a.jpg"), b"first").unwrap();
+/// fs::write(root.join("photos/b.jpg"), b"second").unwrap();
+/// fs::write(root.join("backup/2020/copy of a.jpg"), b"first").unwrap();
+/// fs::write(root.join("backup/b.jpg"), b"second").unwrap();
+///
+/// let (files, _) = ddh::deduplicate_dirs(vec![&root]);
+/// let groups = find_duplicate_directories(&files, &[&root]);
+/// assert_eq!(1, groups.len());
+/// assert_eq!(vec![root.join("backup"), root.join("photos")], groups[0].paths);
+/// assert_eq!(2, groups[0].files);
+/// ```
+pub fn find_duplicate_directories<P: AsRef<Path>>(files: &[Fileinfo], search_dirs: &[P]) -> Vec<DirectoryGroup>{
+    let mut directories: HashMap<PathBuf, DirectoryContents> = HashMap::new();
+    for (content, file) in files.iter().enumerate(){
+        // Empty files are left ungrouped unless EmptyFilePolicy::Group is chosen, so they share a single content id here
+        let content = if file.get_length() == 0 {usize::MAX} else {content};
+        for (path, &root) in file.get_paths().iter().zip(file.get_roots()){
+            let search_dir = match search_dirs.get(root) {
+                Some(search_dir) => search_dir.as_ref(),
+                None => continue,
+            };
+            let mut child: Option<&Path> = None;
+            for ancestor in path.ancestors().skip(1).take_while(|x| x.starts_with(search_dir)){
+                let directory = directories.entry(ancestor.to_path_buf()).or_default();
+                directory.contents.push(content);
+                directory.bytes += file.get_length();
+                match child {
+                    Some(child) => {directory.children.insert(child.to_path_buf());},
+                    None => directory.direct_files = true,
+                }
+                child = Some(ancestor);
+            }
+        }
+    }
+
+    let mut by_contents: HashMap<Vec<usize>, Vec<PathBuf>> = HashMap::new();
+    let mut sizes: HashMap<Vec<usize>, u64> = HashMap::new();
+    for (path, mut directory) in directories.into_iter(){
+        if !directory.direct_files && directory.children.len() == 1 {
+            continue
+        }
+        directory.contents.sort_unstable();
+        sizes.insert(directory.contents.clone(), directory.bytes);
+        by_contents.entry(directory.contents).or_default().push(path);
+    }
+    let duplicated: HashSet<PathBuf> = by_contents.values()
+        .filter(|x| x.len() > 1)
+        .flatten()
+        .cloned()
+        .collect();
+    let mut groups: Vec<DirectoryGroup> = by_contents.into_iter()
+        .filter(|x| x.1.len() > 1)
+        .filter(|x| !x.1.iter().all(|y| y.ancestors().skip(1).any(|z| duplicated.contains(z))))
+        .map(|(contents, mut paths)| {
+            paths.sort();
+            DirectoryGroup{bytes: sizes[&contents], files: contents.len(), paths}
+        })
+        .collect();
+    groups.sort_unstable_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.paths.cmp(&b.paths)));
+    groups
+}
+
+/// Finds pairs of directories with the same name whose trees share at least `min_overlap` of their distinct contents, as likely copies of one backup.
+/// Overlap is the number of contents found in both trees over the number found in either, so files added to only one copy lower it. Empty files are
+/// not counted, and a directory is never paired with one inside it. A pair is only reported when their parents are not a reported pair themselves,
+/// so two copies of a library are reported once rather than once per subdirectory. `files` and `search_dirs` should come from the same scan.
+///
+/// # Examples
+/// ```
+/// use std::fs;
+/// use ddh::directories::find_overlapping_directories;
+///
+/// let temp = tempfile::tempdir().unwrap();
+/// let root = temp.path();
+/// for dir in ["a/Photos/2020", "b/Photos/2020", "b/Music"].iter() {
+///     fs::create_dir_all(root.join(dir)).unwrap();
+/// }
+/// for (i, name) in ["1.jpg", "2.jpg", "3.jpg"].iter().enumerate() {
+///     fs::write(root.join("a/Photos/2020").join(name), format!("photo {}", i)).unwrap();
+///     fs::write(root.join("b/Photos/2020").join(name), format!("photo {}", i)).unwrap();
+/// }
+/// fs::write(root.join("b/Photos/4.jpg"), b"only in one copy").unwrap();
+/// fs::write(root.join("b/Music/song.mp3"), b"photo 0").unwrap();
+///
+/// let (files, _) = ddh::deduplicate_dirs(vec![&root]);
+/// let pairs = find_overlapping_directories(&files, &[&root], 0.7);
+/// assert_eq!(1, pairs.len());
+/// assert_eq!((root.join("a/Photos"), root.join("b/Photos")), (pairs[0].first.clone(), pairs[0].second.clone()));
+/// assert_eq!((3, 4), (pairs[0].shared, pairs[0].contents));
+/// assert!(find_overlapping_directories(&files, &[&root], 0.8).iter().all(|x| x.first != root.join("a/Photos")));
+/// ```
+pub fn find_overlapping_directories<P: AsRef<Path>>(files: &[Fileinfo], search_dirs: &[P], min_overlap: f64) -> Vec<OverlappingDirectories>{
+    let mut directories: HashMap<PathBuf, HashSet<usize>> = HashMap::new();
+    for (content, file) in files.iter().enumerate().filter(|x| x.1.get_length() > 0){
+        for (path, &root) in file.get_paths().iter().zip(file.get_roots()){
+            let search_dir = match search_dirs.get(root) {
+                Some(search_dir) => search_dir.as_ref(),
+                None => continue,
+            };
+            for ancestor in path.ancestors().skip(1).take_while(|x| x.starts_with(search_dir) && *x != search_dir){
+                directories.entry(ancestor.to_path_buf()).or_default().insert(content);
+            }
+        }
+    }
+    let mut by_name: HashMap<String, Vec<&PathBuf>> = HashMap::new();
+    for path in directories.keys(){
+        if let Some(name) = utils::normalized_file_name(path) {
+            by_name.entry(name).or_default().push(path);
+        }
+    }
+    let mut pairs: Vec<OverlappingDirectories> = Vec::new();
+    for paths in by_name.values_mut().filter(|x| x.len() > 1){
+        paths.sort();
+        for (i, first) in paths.iter().enumerate(){
+            for second in paths[i + 1..].iter().filter(|x| !x.starts_with(first) && !first.starts_with(x)){
+                let (a, b) = (&directories[*first], &directories[*second]);
+                let shared = a.intersection(b).count();
+                let contents = a.len() + b.len() - shared;
+                let overlap = shared as f64/contents as f64;
+                if shared > 0 && overlap >= min_overlap {
+                    pairs.push(OverlappingDirectories{first: first.to_path_buf(), second: second.to_path_buf(), shared, contents, overlap});
+                }
+            }
+        }
+    }
+    let reported: HashSet<(&Path, &Path)> = pairs.iter().map(|x| (x.first.as_path(), x.second.as_path())).collect();
+    let nested: Vec<bool> = pairs.iter()
+        .map(|x| match (x.first.parent(), x.second.parent()) {
+            (Some(a), Some(b)) => reported.contains(&(a, b)) || reported.contains(&(b, a)),
+            _ => false,
+        })
+        .collect();
+    let mut pairs: Vec<OverlappingDirectories> = pairs.into_iter().zip(nested).filter(|x| !x.1).map(|x| x.0).collect();
+    pairs.sort_unstable_by(|a, b| b.shared.cmp(&a.shared).then_with(|| a.first.cmp(&b.first)).then_with(|| a.second.cmp(&b.second)));
+    pairs
+}
+
+/// Finds directories holding copies of their own files, ranked by the number of copies, then by bytes and then by path.
+/// Only copies sharing one parent directory count, so a directory whose files duplicate files elsewhere is not reported.
+///
+/// # Examples
+/// ```
+/// use std::fs;
+/// use ddh::directories::find_redundant_directories;
+///
+/// let temp = tempfile::tempdir().unwrap();
+/// let root = temp.path();
+/// fs::create_dir_all(root.join("downloads")).unwrap();
+/// fs::create_dir_all(root.join("photos")).unwrap();
+/// for name in ["report.pdf", "report (1).pdf", "report (2).pdf"].iter() {
+///     fs::write(root.join("downloads").join(name), b"quarterly").unwrap();
+/// }
+/// fs::write(root.join("photos/cat.jpg"), b"cat").unwrap();
+/// fs::write(root.join("photos/cat copy.jpg"), b"cat").unwrap();
+/// fs::write(root.join("photos/report.pdf"), b"quarterly").unwrap();
+///
+/// let (files, _) = ddh::deduplicate_dirs(vec![&root]);
+/// let directories = find_redundant_directories(&files);
+/// assert_eq!(2, directories.len());
+/// assert_eq!((root.join("downloads"), 2, 18), (directories[0].path.clone(), directories[0].duplicates, directories[0].bytes));
+/// assert_eq!((root.join("photos"), 1, 3), (directories[1].path.clone(), directories[1].duplicates, directories[1].bytes));
+/// ```
+pub fn find_redundant_directories(files: &[Fileinfo]) -> Vec<RedundantDirectory>{
+    let mut directories: HashMap<&Path, RedundantDirectory> = HashMap::new();
+    for file in files.iter().filter(|x| x.get_paths().len() > 1){
+        let mut copies: HashMap<&Path, usize> = HashMap::new();
+        for parent in file.get_paths().iter().filter_map(|x| x.parent()){
+            *copies.entry(parent).or_default() += 1;
+        }
+        for (parent, copies) in copies.into_iter().filter(|x| x.1 > 1){
+            let directory = directories.entry(parent)
+                .or_insert_with(|| RedundantDirectory{path: parent.to_path_buf(), duplicates: 0, bytes: 0});
+            directory.duplicates += copies - 1;
+            directory.bytes += (copies as u64 - 1)*file.get_length();
+        }
+    }
+    let mut directories: Vec<RedundantDirectory> = directories.into_values().collect();
+    directories.sort_unstable_by(|a, b| {
+        b.duplicates.cmp(&a.duplicates)
+            .then_with(|| b.bytes.cmp(&a.bytes))
+            .then_with(|| a.path.cmp(&b.path))
+    });
+    directories
+}
@@ -3,21 +3,61 @@
 //! `ddh` is a collection of functions and structs to aid in analysing filesystem directories.
 
 pub mod utils;
+pub mod progress;
+pub mod cache;
+pub mod throttle;
+pub mod budget;
+pub mod actions;
+pub mod manifest;
+pub mod directories;
+pub mod clones;
+pub mod profile;
+pub mod text;
+pub mod stats;
+pub mod compare;
+pub mod template;
+pub mod script;
+pub mod names;
+pub mod range;
+pub mod checksum;
+pub mod archive;
+pub mod watch;
+pub mod selftest;
+pub mod treecache;
+pub mod compressed;
+pub mod config;
+mod scan;
+mod dedup;
+mod spill;
 
-use std::hash::{Hasher};
-use std::fs::{self, DirEntry};
-use std::io::{Read};
+use std::hash::{Hash, Hasher};
+use std::fs;
+use std::io::{Error, Read, Seek, SeekFrom};
 use std::path::{PathBuf, Path};
 use std::cmp::Ordering;
-use serde_derive::{Serialize};
+use serde_derive::{Serialize, Deserialize};
 use siphasher::sip128::Hasher128;
 use rayon::prelude::*;
-use std::sync::mpsc::{Sender, channel};
-use std::collections::hash_map::{HashMap, Entry};
-use std::io::{Error, ErrorKind};
-use nohash_hasher::IntMap;
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use progress::{FileTracker, Progress};
+use profile::{Profile, Stage};
+use stats::ScanStats;
+use cache::HashCache;
+use manifest::Manifest;
+use treecache::TreeCache;
+use throttle::Throttle;
+use budget::ScanBudget;
+use range::HashRange;
 
 const BLOCK_SIZE: usize = 4096;
+/// Bytes read at a time when hashing one file on several threads. Each read is split across the thread pool, so it must be large enough to keep every thread busy.
+const PARALLEL_READ_SIZE: usize = 8*1024*1024;
+/// Wait before the first retry of a failed read, doubled before each retry after it.
+const READ_RETRY_BACKOFF: Duration = Duration::from_millis(100);
 
 #[derive(PartialEq)]
 enum HashMode{
@@ -25,18 +65,887 @@ enum HashMode{
     Partial
 }
 
-enum ChannelPackage{
-    Success(Fileinfo),
-    Fail(PathBuf, std::io::Error),
+/// Selects which parts of a file the partial hash reads.
+///
+/// # Examples
+/// ```
+/// use std::fs;
+/// use ddh::{SampleStrategy, ScanOptions};
+///
+/// // Files sharing their first 16 KiB, as a container header would be, and differing only at the end
+/// let root = tempfile::tempdir().unwrap();
+/// let mut contents = vec![0u8; 64*1024];
+/// fs::write(root.path().join("a.mkv"), &contents).unwrap();
+/// contents[64*1024 - 1] = 1;
+/// fs::write(root.path().join("b.mkv"), &contents).unwrap();
+///
+/// // The prefix matches, so both files are read in full before they are found to differ
+/// let (files, _) = ddh::deduplicate_dirs(vec![root.path()]);
+/// assert_eq!(2, files.len());
+/// assert!(files.iter().all(|x| x.get_full_hash().is_some()));
+///
+/// // The last sample differs, so neither is read past its samples
+/// let options = ScanOptions::builder().sample_strategy(SampleStrategy::Spread).build();
+/// let (files, _) = ddh::deduplicate_dirs_with(vec![root.path()], &options);
+/// assert_eq!(2, files.len());
+/// assert!(files.iter().all(|x| x.get_full_hash().is_none()));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum SampleStrategy{
+    /// Reads only the first block of the file.
+    #[default]
+    Prefix,
+    /// Reads the first, middle and last blocks of the file. This separates files with identical headers, such as media sharing a container format, at the cost of two extra seeks.
+    Spread,
+}
+
+/// Selects the hash used to identify file contents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum HashAlgorithm{
+    /// 128 bit SipHash. Fast, but not collision resistant against crafted files.
+    #[default]
+    Sip128,
+    /// BLAKE3, truncated to 128 bits. Cryptographic, and faster than SipHash on most modern CPUs.
+    Blake3,
+}
+
+impl HashAlgorithm{
+    /// Gets the width of the hashes kept, in bits.
+    pub fn bits(&self) -> u32{
+        match self {
+            HashAlgorithm::Sip128 => 128,
+            HashAlgorithm::Blake3 => 128,
+        }
+    }
+}
+
+pub(crate) enum ContentHasher{
+    Sip128(siphasher::sip128::SipHasher),
+    Blake3(Box<blake3::Hasher>),
 }
 
+impl ContentHasher{
+    pub(crate) fn new(algorithm: HashAlgorithm) -> Self{
+        match algorithm {
+            HashAlgorithm::Sip128 => ContentHasher::Sip128(siphasher::sip128::SipHasher::new()),
+            HashAlgorithm::Blake3 => ContentHasher::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+    pub(crate) fn write(&mut self, bytes: &[u8]){
+        match self {
+            ContentHasher::Sip128(hasher) => hasher.write(bytes),
+            ContentHasher::Blake3(hasher) => {hasher.update(bytes);},
+        }
+    }
+    /// Hashes `bytes` on every thread of the current pool where the algorithm supports it.
+    pub(crate) fn write_parallel(&mut self, bytes: &[u8]){
+        match self {
+            ContentHasher::Sip128(hasher) => hasher.write(bytes),
+            ContentHasher::Blake3(hasher) => {hasher.update_rayon(bytes);},
+        }
+    }
+    pub(crate) fn finish(&self) -> u128{
+        match self {
+            ContentHasher::Sip128(hasher) => hasher.finish128().into(),
+            ContentHasher::Blake3(hasher) => {
+                let mut truncated = [0u8; 16];
+                truncated.copy_from_slice(&hasher.finalize().as_bytes()[..16]);
+                u128::from_le_bytes(truncated)
+            },
+        }
+    }
+}
+
+/// Selects how thoroughly files under a search directory are compared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HashPolicy{
+    /// Confirms partial hash matches with a full hash.
+    #[default]
+    Full,
+    /// Treats a partial hash match as a duplicate, accepting some false matches to avoid reading whole files.
+    /// Files are still fully hashed when they may duplicate a file under a directory with the `Full` policy.
+    Partial,
+}
+
+/// Selects how empty files, which hold no content to compare, are reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EmptyFilePolicy{
+    /// Lists each empty file on its own, counted among the single instance files.
+    #[default]
+    Unique,
+    /// Groups every empty file together as copies of one another, counted among the shared instance files.
+    Group,
+    /// Leaves empty files out of the results, so they count toward neither single instance nor shared files, nor the totals.
+    Skip,
+}
+
+/// Options controlling how `deduplicate_dirs_with` scans and hashes files. The default options match `deduplicate_dirs`.
+#[derive(Debug, Default, Clone)]
+pub struct ScanOptions{
+    /// Counters updated as the full hash stage reads files.
+    pub progress: Option<Arc<Progress>>,
+    /// Fully hashes every file and groups purely by content, skipping the length bucketing and partial hashing.
+    /// This is much slower and exists as an oracle for checking the optimized path.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::fs;
+    /// use std::path::PathBuf;
+    /// use ddh::{Fileinfo, ScanOptions};
+    ///
+    /// let temp = tempfile::tempdir().unwrap();
+    /// let root = temp.path();
+    /// fs::create_dir_all(root.join("a")).unwrap();
+    /// fs::create_dir_all(root.join("b")).unwrap();
+    /// fs::write(root.join("a/small"), b"same").unwrap();
+    /// fs::write(root.join("b/small"), b"same").unwrap();
+    /// fs::write(root.join("a/large"), vec![7u8; 20000]).unwrap();
+    /// fs::write(root.join("b/large"), vec![7u8; 20000]).unwrap();
+    /// fs::write(root.join("b/other"), vec![8u8; 20000]).unwrap();
+    ///
+    /// let groups = |files: Vec<Fileinfo>| {
+    ///     let mut groups: Vec<Vec<PathBuf>> = files.iter().map(|x| {
+    ///         let mut paths = x.get_paths().clone();
+    ///         paths.sort();
+    ///         paths
+    ///     }).collect();
+    ///     groups.sort();
+    ///     groups
+    /// };
+    /// let (optimized, _) = ddh::deduplicate_dirs(vec![&root]);
+    /// let options = ScanOptions{content_only: true, ..ScanOptions::default()};
+    /// let (oracle, _) = ddh::deduplicate_dirs_with(vec![&root], &options);
+    /// assert_eq!(groups(optimized), groups(oracle));
+    /// ```
+    pub content_only: bool,
+    /// Fully hashes every file sharing its length with another, skipping the partial hash stage. Files of a unique length are still never read.
+    /// This saves a read of each candidate on trees of many small files, but reads every candidate in full where a partial hash would have told them apart.
+    /// Root hash policies are ignored.
+    pub hash_only_full: bool,
+    /// Which parts of a file the partial hash reads.
+    pub sample_strategy: SampleStrategy,
+    /// Glob patterns for paths to skip. A pattern excludes a file or directory if it matches either its name or its full path. Excluded directories are not traversed.
+    pub excludes: Vec<glob::Pattern>,
+    /// Regular expressions for the paths of files to scan. When any are given, files whose full path matches none of them are skipped. Directories are always traversed,
+    /// since a file deep inside one may still match, so these narrow the results rather than the traversal. Matches may fall anywhere in the path unless anchored with `^` or `$`.
+    /// Paths which are not valid UTF-8 are matched with their invalid bytes replaced.
+    ///
+    /// Each path is matched once against every expression. Matching takes time linear in the length of the path, however complex the expression, but large
+    /// expressions, and Unicode classes such as `\w`, take longer to compile and match, which shows on trees of millions of files.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::fs;
+    /// use regex::Regex;
+    /// use ddh::ScanOptions;
+    ///
+    /// let temp = tempfile::tempdir().unwrap();
+    /// let root = temp.path();
+    /// fs::create_dir_all(root.join("2024")).unwrap();
+    /// for name in ["IMG_0001.jpg", "IMG_0002.jpg", "IMG_final.jpg", "2024/IMG_0003.jpg"].iter() {
+    ///     fs::write(root.join(name), b"pixels").unwrap();
+    /// }
+    ///
+    /// let scanned = |options: ScanOptions| {
+    ///     let mut paths: Vec<_> = ddh::deduplicate_dirs_with(vec![&root], &options).0.iter().flat_map(|x| x.get_paths().clone()).collect();
+    ///     paths.sort();
+    ///     paths
+    /// };
+    /// let numbered = ScanOptions::builder().path_filter(Regex::new(r"IMG_\d{4}\.jpg$").unwrap()).build();
+    /// assert_eq!(vec![root.join("2024/IMG_0003.jpg"), root.join("IMG_0001.jpg"), root.join("IMG_0002.jpg")], scanned(numbered));
+    /// let numbered_outside_years = ScanOptions::builder()
+    ///     .path_filter(Regex::new(r"IMG_\d{4}\.jpg$").unwrap())
+    ///     .path_filter_out(Regex::new(r"/\d{4}$").unwrap())
+    ///     .build();
+    /// assert_eq!(vec![root.join("IMG_0001.jpg"), root.join("IMG_0002.jpg")], scanned(numbered_outside_years));
+    /// ```
+    pub path_filters: Vec<regex::Regex>,
+    /// Regular expressions for paths to skip. A file or directory is skipped if any of them matches its full path. Skipped directories are not traversed.
+    pub path_filters_out: Vec<regex::Regex>,
+    /// Glob patterns for the extensions of files to scan. When any are given, files without a matching extension are skipped. Directories are always traversed.
+    /// Extensions are lowercased before matching. A pattern matches as many trailing dot separated parts of a file name as it has, so both `gz` and `tar.gz` match `logs.tar.gz`, but `tar` does not.
+    /// The leading dot of a dotfile is part of its name, so `.bashrc` has no extension and `.config.json` has `json`.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::fs;
+    /// use ddh::ScanOptions;
+    ///
+    /// let temp = tempfile::tempdir().unwrap();
+    /// let root = temp.path();
+    /// for name in ["a.jpg", "b.JPEG", "c.png", "d.tar.gz", ".jpg"].iter() {
+    ///     fs::write(root.join(name), b"pixels").unwrap();
+    /// }
+    ///
+    /// let scanned = |options: ScanOptions| {
+    ///     let (files, _) = ddh::deduplicate_dirs_with(vec![&root], &options);
+    ///     let mut names: Vec<String> = files.iter().flat_map(|x| x.get_paths()).map(|x| x.file_name().unwrap().to_string_lossy().into_owned()).collect();
+    ///     names.sort();
+    ///     names
+    /// };
+    /// let photos = ScanOptions::builder().include_extension(glob::Pattern::new("jp*g").unwrap()).build();
+    /// assert_eq!(vec!["a.jpg", "b.JPEG"], scanned(photos));
+    /// let archives = ScanOptions::builder().include_extension(glob::Pattern::new("tar.*").unwrap()).build();
+    /// assert_eq!(vec!["d.tar.gz"], scanned(archives));
+    /// let no_gzip = ScanOptions::builder().exclude_extension(glob::Pattern::new("gz").unwrap()).build();
+    /// assert_eq!(vec![".jpg", "a.jpg", "b.JPEG", "c.png"], scanned(no_gzip));
+    /// ```
+    pub include_extensions: Vec<glob::Pattern>,
+    /// Glob patterns for the extensions of files to skip, matched as for `include_extensions`. Files without an extension are never skipped by these.
+    pub exclude_extensions: Vec<glob::Pattern>,
+    /// Only treats files as duplicates if their names match as well as their contents. Names are compared after Unicode normalization.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::fs;
+    /// use ddh::ScanOptions;
+    ///
+    /// let temp = tempfile::tempdir().unwrap();
+    /// let root = temp.path();
+    /// fs::create_dir_all(root.join("a")).unwrap();
+    /// fs::create_dir_all(root.join("b")).unwrap();
+    /// fs::write(root.join("a/config"), b"same").unwrap();
+    /// fs::write(root.join("b/config"), b"same").unwrap();
+    /// fs::write(root.join("b/settings"), b"same").unwrap();
+    ///
+    /// let options = ScanOptions{require_name_match: true, ..ScanOptions::default()};
+    /// let (files, _) = ddh::deduplicate_dirs_with(vec![&root], &options);
+    /// let mut group_sizes: Vec<usize> = files.iter().map(|x| x.get_paths().len()).collect();
+    /// group_sizes.sort();
+    /// assert_eq!(vec![1, 2], group_sizes);
+    /// ```
+    pub require_name_match: bool,
+    /// Hashes reused for unchanged files and recorded for newly hashed ones.
+    pub cache: Option<Arc<HashCache>>,
+    /// Prior manifest whose full hashes are reused for files at the same path with an unchanged length and modification time, instead of reading them.
+    /// Only the full hash is taken from it, so it speeds up scans with `content_only` set, where every file is fully hashed.
+    /// A manifest hashed with another `hash_algorithm` is never reused, as its hashes would not match those of the scan.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::fs;
+    /// use std::sync::Arc;
+    /// use ddh::{HashAlgorithm, ScanOptions};
+    /// use ddh::manifest::Manifest;
+    ///
+    /// let temp = tempfile::tempdir().unwrap();
+    /// fs::write(temp.path().join("file"), b"contents").unwrap();
+    /// let options = ScanOptions::builder().content_only(true).hash_algorithm(HashAlgorithm::Blake3).build();
+    /// let (files, _) = ddh::deduplicate_dirs_with(vec![temp.path()], &options);
+    /// let mut prior = Manifest::new(HashAlgorithm::Blake3);
+    /// files.iter().for_each(|x| prior.insert(x));
+    ///
+    /// let prior = Arc::new(prior);
+    /// let options = ScanOptions::builder().content_only(true).since_manifest(prior.clone()).build();
+    /// ddh::deduplicate_dirs_with(vec![temp.path()], &options);
+    /// assert_eq!((0, 1), (prior.reused_files(), prior.rehashed_files()));
+    /// ```
+    pub since_manifest: Option<Arc<Manifest>>,
+    /// Directory listings reused for directories whose modification time is unchanged, and recorded for those read. Files rewritten in place
+    /// without their directory changing are scanned with the length and modification time they were last listed with.
+    pub tree_cache: Option<Arc<TreeCache>>,
+    /// Follows symlinks to files and directories. Otherwise symlinks are reported as errors and skipped.
+    pub follow_symlinks: bool,
+    /// Folds symlinks leading to a file already found into that file's collection as aliases, so each physical file is hashed and counted once.
+    /// Only has an effect with `follow_symlinks`.
+    ///
+    /// # Examples
+    /// ```
+    /// #[cfg(unix)] {
+    ///     use std::fs;
+    ///     use std::os::unix::fs::symlink;
+    ///     use ddh::ScanOptions;
+    ///
+    ///     let temp = tempfile::tempdir().unwrap();
+    ///     let root = temp.path();
+    ///     fs::write(root.join("photo"), b"same").unwrap();
+    ///     fs::write(root.join("copy"), b"same").unwrap();
+    ///     symlink(root.join("photo"), root.join("alias")).unwrap();
+    ///
+    ///     let options = ScanOptions{follow_symlinks: true, symlink_targets_once: true, ..ScanOptions::default()};
+    ///     let (files, _) = ddh::deduplicate_dirs_with(vec![&root], &options);
+    ///     assert_eq!(1, files.len());
+    ///     assert_eq!(&vec![root.join("copy"), root.join("photo")], files[0].get_paths());
+    ///     assert_eq!(&vec![(root.join("alias"), root.join("photo"))], files[0].get_aliases());
+    /// }
+    /// ```
+    pub symlink_targets_once: bool,
+    /// Stays on one filesystem. Followed symlinks whose target is on a different device from the directory holding them are skipped, as are mount points unless `follow_mount_points` is set.
+    pub one_file_system: bool,
+    /// Descends into mount points, directories on a different device from their parent, even with `one_file_system`. Mount points are always descended without `one_file_system`.
+    pub follow_mount_points: bool,
+    /// Rate limit shared by every read in the full hash stage.
+    pub throttle: Option<Arc<Throttle>>,
+    /// Cap on the combined length of the files taken in, after which traversal winds down and the files found so far are hashed as usual.
+    pub budget: Option<Arc<ScanBudget>>,
+    /// Bytes of each file hashed and compared, in place of the whole file. Files whose range is empty are never grouped, and hashes are neither read from nor written to caches.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::fs;
+    /// use ddh::ScanOptions;
+    /// use ddh::range::HashRange;
+    ///
+    /// let temp = tempfile::tempdir().unwrap();
+    /// let root = temp.path();
+    /// fs::write(root.join("monday.log"), b"2024-01-01 same body").unwrap();
+    /// fs::write(root.join("tuesday.log"), b"2024-01-02 same body").unwrap();
+    ///
+    /// let (files, _) = ddh::deduplicate_dirs(vec![&root]);
+    /// assert_eq!(2, files.len());
+    /// let options = ScanOptions::builder().hash_range(HashRange::parse("10:").unwrap()).build();
+    /// let (files, _) = ddh::deduplicate_dirs_with(vec![&root], &options);
+    /// assert_eq!(1, files.len());
+    /// ```
+    pub hash_range: Option<HashRange>,
+    /// When set, traversal and hashing wind down as soon as possible. Results returned after a stop are incomplete.
+    pub stop: Option<Arc<AtomicBool>>,
+    /// Hash policy for each search directory, by position. Directories without an entry use `HashPolicy::Full`.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::fs;
+    /// use ddh::{HashPolicy, ScanOptions};
+    ///
+    /// let temp = tempfile::tempdir().unwrap();
+    /// let root = temp.path();
+    /// let mut differs_at_end = vec![3u8; 20000];
+    /// fs::write(root.join("first"), &differs_at_end).unwrap();
+    /// differs_at_end[19999] = 4;
+    /// fs::write(root.join("second"), &differs_at_end).unwrap();
+    ///
+    /// let (full, _) = ddh::deduplicate_dirs(vec![&root]);
+    /// assert_eq!(2, full.len());
+    /// let options = ScanOptions{root_policies: vec![HashPolicy::Partial], ..ScanOptions::default()};
+    /// let (partial, _) = ddh::deduplicate_dirs_with(vec![&root], &options);
+    /// assert_eq!(1, partial.len());
+    /// ```
+    pub root_policies: Vec<HashPolicy>,
+    /// Reads hashes from, and stores them in, each file's `user.ddh.hash` extended attribute. Stored hashes are reused while the file's length and modification time are unchanged.
+    /// Filesystems without extended attributes are hashed as usual. Extended attributes are only supported on Unix.
+    pub xattr_cache: bool,
+    /// Skips sparse files, reporting them as errors. Sparse files are only detected on Unix.
+    pub skip_sparse: bool,
+    /// Skips files another process holds a lock on, or on Windows has open for writing, reporting them as errors of kind `ErrorKind::ResourceBusy`.
+    /// Such files may be changing, so their hashes could match a copy they no longer equal. Locks are only detected on Linux, from `fcntl` and `flock`, and on Windows, from sharing violations.
+    ///
+    /// # Examples
+    /// ```
+    /// #[cfg(target_os = "linux")] {
+    ///     use std::fs;
+    ///     use std::io::ErrorKind;
+    ///     use std::os::unix::io::AsRawFd;
+    ///     use ddh::ScanOptions;
+    ///
+    ///     let temp = tempfile::tempdir().unwrap();
+    ///     let root = temp.path();
+    ///     fs::write(root.join("settled"), b"same").unwrap();
+    ///     fs::write(root.join("writing"), b"same").unwrap();
+    ///     let writer = fs::File::open(root.join("writing")).unwrap();
+    ///     assert_eq!(0, unsafe {libc::flock(writer.as_raw_fd(), libc::LOCK_EX)});
+    ///
+    ///     let options = ScanOptions::builder().skip_locked(true).build();
+    ///     let (files, errors) = ddh::deduplicate_dirs_with(vec![&root], &options);
+    ///     assert_eq!(vec![root.join("settled")], files.iter().flat_map(|x| x.get_paths().clone()).collect::<Vec<_>>());
+    ///     assert_eq!((root.join("writing"), ErrorKind::ResourceBusy), (errors[0].0.clone(), errors[0].1.kind()));
+    ///     drop(writer);
+    /// }
+    /// ```
+    pub skip_locked: bool,
+    /// Hash used to identify file contents.
+    pub hash_algorithm: HashAlgorithm,
+    /// Splits the full hash of each large file across threads, instead of hashing each file on one thread. Only used with `HashAlgorithm::Blake3`, as SipHash cannot be split.
+    /// This speeds up scans of a few huge files, such as disk images, where there are too few files to keep every thread busy. Hashes are the same either way.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::fs;
+    /// use ddh::{HashAlgorithm, ScanOptions};
+    ///
+    /// let temp = tempfile::tempdir().unwrap();
+    /// let root = temp.path();
+    /// let image: Vec<u8> = (0..20*1024*1024u32).map(|x| (x % 251) as u8).collect();
+    /// fs::write(root.join("a.img"), &image).unwrap();
+    /// fs::write(root.join("b.img"), &image).unwrap();
+    ///
+    /// let serial = ScanOptions::builder().hash_algorithm(HashAlgorithm::Blake3).build();
+    /// let parallel = ScanOptions::builder().hash_algorithm(HashAlgorithm::Blake3).parallel_hash(true).build();
+    /// let (serial_files, _) = ddh::deduplicate_dirs_with(vec![&root], &serial);
+    /// let (parallel_files, _) = ddh::deduplicate_dirs_with(vec![&root], &parallel);
+    /// assert_eq!(2, parallel_files[0].get_paths().len());
+    /// assert_eq!(serial_files[0].get_full_hash(), parallel_files[0].get_full_hash());
+    /// ```
+    pub parallel_hash: bool,
+    /// Number of times a read failing with a transient error, such as a timeout on network storage, is retried before the file is given up on.
+    /// Retries wait 100 milliseconds, doubling before each retry after the first. Permanent errors, such as a missing file, are never retried.
+    /// Files whose full hash still cannot be read are left out of the results and returned with the read errors.
+    pub read_retries: u32,
+    /// Number of threads to scan and hash with. Defaults to one per CPU, or `RAYON_NUM_THREADS` if set.
+    pub threads: Option<usize>,
+    /// Skips files shorter than this many bytes.
+    pub min_size: Option<u64>,
+    /// Skips files longer than this many bytes.
+    pub max_size: Option<u64>,
+    /// Returns groups, the paths within each group and errors in a canonical order, so the same tree always gives the same results.
+    /// Groups are ordered by descending length and then by their sorted paths. This costs a sort of the results after hashing.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::fs;
+    /// use ddh::ScanOptions;
+    ///
+    /// let temp = tempfile::tempdir().unwrap();
+    /// let root = temp.path();
+    /// for name in ["d", "b", "c", "a"].iter() {
+    ///     fs::write(root.join(name), b"same").unwrap();
+    /// }
+    /// fs::write(root.join("e"), b"longer").unwrap();
+    ///
+    /// let options = ScanOptions::builder().deterministic(true).build();
+    /// let (files, _) = ddh::deduplicate_dirs_with(vec![&root], &options);
+    /// assert_eq!(&vec![root.join("e")], files[0].get_paths());
+    /// assert_eq!(&vec![root.join("a"), root.join("b"), root.join("c"), root.join("d")], files[1].get_paths());
+    /// ```
+    pub deterministic: bool,
+    /// Groups files whose full hashes match without comparing their bytes. By default every group found by full hash is compared byte for byte,
+    /// and files whose contents differ despite a matching hash are split into separate groups. Trusting the hash avoids reading each duplicate a second time.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::fs;
+    /// use ddh::{SampleStrategy, ScanOptions};
+    /// use ddh::cache::HashCache;
+    ///
+    /// let temp = tempfile::tempdir().unwrap();
+    /// let root = temp.path();
+    /// let mut contents = vec![7u8; 20000];
+    /// fs::write(root.join("a"), &contents).unwrap();
+    /// contents[19999] = 8;
+    /// fs::write(root.join("b"), &contents).unwrap();
+    ///
+    /// // Records both files' hashes, then forges a full hash collision between them
+    /// let cache_file = root.with_extension("json");
+    /// let cache = HashCache::new(SampleStrategy::Prefix);
+    /// let options = ScanOptions::builder().content_only(true).cache(std::sync::Arc::new(cache)).build();
+    /// ddh::deduplicate_dirs_with(vec![&root], &options);
+    /// options.cache.unwrap().save(&cache_file).unwrap();
+    /// let mut forged: serde_json::Value = serde_json::from_str(&fs::read_to_string(&cache_file).unwrap()).unwrap();
+    /// for entry in forged["entries"].as_array_mut().unwrap() {
+    ///     entry[1]["full_hash"] = serde_json::json!(42);
+    /// }
+    /// fs::write(&cache_file, forged.to_string()).unwrap();
+    ///
+    /// let forged_scan = |trust_full_hash| {
+    ///     let cache = HashCache::load(&cache_file, SampleStrategy::Prefix).unwrap();
+    ///     let options = ScanOptions::builder().content_only(true).cache(std::sync::Arc::new(cache)).trust_full_hash(trust_full_hash).build();
+    ///     ddh::deduplicate_dirs_with(vec![&root], &options).0.len()
+    /// };
+    /// assert_eq!(1, forged_scan(true));
+    /// assert_eq!(2, forged_scan(false));
+    /// fs::remove_file(&cache_file).unwrap();
+    /// ```
+    pub trust_full_hash: bool,
+    /// How empty files are grouped. They are never read, whichever policy is chosen.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::fs;
+    /// use ddh::{EmptyFilePolicy, ScanOptions};
+    ///
+    /// let temp = tempfile::tempdir().unwrap();
+    /// let root = temp.path();
+    /// for name in ["a", "b", "c"].iter() {
+    ///     fs::write(root.join(name), b"").unwrap();
+    /// }
+    /// fs::write(root.join("d"), b"contents").unwrap();
+    ///
+    /// let groups = |policy| {
+    ///     let options = ScanOptions::builder().empty_files(policy).build();
+    ///     let mut groups: Vec<usize> = ddh::deduplicate_dirs_with(vec![&root], &options).0.iter().map(|x| x.get_paths().len()).collect();
+    ///     groups.sort();
+    ///     groups
+    /// };
+    /// assert_eq!(vec![1, 1, 1, 1], groups(EmptyFilePolicy::Unique));
+    /// assert_eq!(vec![1, 3], groups(EmptyFilePolicy::Group));
+    /// assert_eq!(vec![1], groups(EmptyFilePolicy::Skip));
+    /// ```
+    pub empty_files: EmptyFilePolicy,
+    /// Holds at most this many files in memory between traversal and hashing, spilling the rest to temporary files and hashing them one partition
+    /// of lengths at a time. Bounds the memory a scan of tens of millions of files takes before its results are gathered, at the cost of writing
+    /// and reading back every file's details and of partial hashing no longer overlapping with traversal. Only the files awaiting hashing are bounded:
+    /// the results, singletons included, are still gathered in memory as they are returned. Results match those of a scan held in memory.
+    /// Only `deduplicate_dirs_with` spills. [`LOW_MEMORY_FILES`] suits most machines.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::fs;
+    /// use ddh::ScanOptions;
+    ///
+    /// let temp = tempfile::tempdir().unwrap();
+    /// let root = temp.path();
+    /// fs::create_dir_all(root.join("copy")).unwrap();
+    /// for i in 0..20u8 {
+    ///     let contents = vec![i; 5000 + (i % 4) as usize];
+    ///     fs::write(root.join(format!("{}", i)), &contents).unwrap();
+    ///     if i % 3 == 0 {
+    ///         fs::write(root.join(format!("copy/{}", i)), &contents).unwrap();
+    ///     }
+    /// }
+    ///
+    /// let scan = |options: ScanOptions| {
+    ///     let (files, errors) = ddh::deduplicate_dirs_with(vec![&root], &options);
+    ///     assert!(errors.is_empty());
+    ///     files.iter().map(|x| (x.get_length(), x.get_full_hash(), x.get_paths().clone())).collect::<Vec<_>>()
+    /// };
+    /// let in_memory = scan(ScanOptions::builder().deterministic(true).build());
+    /// let spilled = scan(ScanOptions::builder().deterministic(true).low_memory(3).build());
+    /// assert_eq!(20, spilled.len());
+    /// assert_eq!(in_memory, spilled);
+    /// ```
+    pub low_memory: Option<usize>,
+    /// Accumulates the time spent in each stage of the scan.
+    pub profile: Option<Arc<Profile>>,
+    /// Collects statistics on how files spread across length buckets.
+    pub stats: Option<Arc<ScanStats>>,
+}
+
+impl ScanOptions{
+    /// Starts building options from the defaults, which match `deduplicate_dirs`.
+    ///
+    /// # Examples
+    /// ```
+    /// use ddh::{HashAlgorithm, ScanOptions};
+    ///
+    /// let options = ScanOptions::builder()
+    ///     .hash_algorithm(HashAlgorithm::Blake3)
+    ///     .exclude(glob::Pattern::new("*.tmp").unwrap())
+    ///     .min_size(1)
+    ///     .threads(2)
+    ///     .build();
+    /// assert_eq!(HashAlgorithm::Blake3, options.hash_algorithm);
+    /// assert_eq!(1, options.excludes.len());
+    /// let (files, errors) = ddh::deduplicate_dirs_with(vec!["/home/jon", "/home/doe"], &options);
+    /// ```
+    pub fn builder() -> ScanOptionsBuilder{
+        ScanOptionsBuilder::default()
+    }
+    fn thread_pool(&self) -> rayon::ThreadPool{
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(self.threads.unwrap_or(0))
+            .build()
+            .expect("Error building thread pool")
+    }
+    fn is_size_excluded(&self, length: u64) -> bool{
+        self.min_size.is_some_and(|x| length < x) || self.max_size.is_some_and(|x| length > x)
+    }
+    fn is_stopped(&self) -> bool{
+        self.stop.as_ref().is_some_and(|x| x.load(AtomicOrdering::Relaxed))
+    }
+    fn hash_policy(&self, root: usize) -> HashPolicy{
+        self.root_policies.get(root).copied().unwrap_or_default()
+    }
+    fn is_extension_excluded(&self, path: &Path) -> bool{
+        if self.include_extensions.is_empty() && self.exclude_extensions.is_empty(){
+            return false
+        }
+        let name = path.file_name().map(|x| x.to_string_lossy().to_lowercase()).unwrap_or_default();
+        let extensions: Vec<&str> = name.trim_start_matches('.').split('.').skip(1).collect();
+        let matches = |pattern: &glob::Pattern| {
+            let parts = pattern.as_str().split('.').count();
+            extensions.len() >= parts && pattern.matches(&extensions[extensions.len() - parts..].join("."))
+        };
+        (!self.include_extensions.is_empty() && !self.include_extensions.iter().any(matches))
+            || self.exclude_extensions.iter().any(matches)
+    }
+    fn is_excluded(&self, path: &Path) -> bool{
+        self.excludes.iter().any(|pattern| {
+            pattern.matches_path(path) || path.file_name().is_some_and(|name| pattern.matches(&name.to_string_lossy()))
+        }) || (!self.path_filters_out.is_empty() && {
+            let path = path.to_string_lossy();
+            self.path_filters_out.iter().any(|x| x.is_match(&path))
+        })
+    }
+    fn is_path_filtered(&self, path: &Path) -> bool{
+        if self.path_filters.is_empty(){
+            return false
+        }
+        let path = path.to_string_lossy();
+        !self.path_filters.iter().any(|x| x.is_match(&path))
+    }
+}
+
+/// Builds `ScanOptions` one setting at a time. Settings left alone keep their defaults.
+#[derive(Debug, Default, Clone)]
+pub struct ScanOptionsBuilder{
+    options: ScanOptions,
+}
+
+impl ScanOptionsBuilder{
+    /// Sets counters updated as the full hash stage reads files.
+    pub fn progress(mut self, progress: Arc<Progress>) -> Self{
+        self.options.progress = Some(progress);
+        self
+    }
+    /// Sets whether every file is fully hashed and grouped purely by content.
+    pub fn content_only(mut self, content_only: bool) -> Self{
+        self.options.content_only = content_only;
+        self
+    }
+    /// Sets whether candidates are fully hashed without a partial hash stage.
+    pub fn hash_only_full(mut self, hash_only_full: bool) -> Self{
+        self.options.hash_only_full = hash_only_full;
+        self
+    }
+    /// Sets which parts of a file the partial hash reads.
+    pub fn sample_strategy(mut self, sample_strategy: SampleStrategy) -> Self{
+        self.options.sample_strategy = sample_strategy;
+        self
+    }
+    /// Adds a glob pattern for paths to skip.
+    pub fn exclude(mut self, pattern: glob::Pattern) -> Self{
+        self.options.excludes.push(pattern);
+        self
+    }
+    /// Adds a glob pattern for the extensions of files to scan.
+    pub fn include_extension(mut self, pattern: glob::Pattern) -> Self{
+        self.options.include_extensions.push(pattern);
+        self
+    }
+    /// Adds a glob pattern for the extensions of files to skip.
+    pub fn exclude_extension(mut self, pattern: glob::Pattern) -> Self{
+        self.options.exclude_extensions.push(pattern);
+        self
+    }
+    /// Adds glob patterns for the extensions of files to scan.
+    pub fn include_extensions<I: IntoIterator<Item = glob::Pattern>>(mut self, patterns: I) -> Self{
+        self.options.include_extensions.extend(patterns);
+        self
+    }
+    /// Adds glob patterns for the extensions of files to skip.
+    pub fn exclude_extensions<I: IntoIterator<Item = glob::Pattern>>(mut self, patterns: I) -> Self{
+        self.options.exclude_extensions.extend(patterns);
+        self
+    }
+    /// Adds glob patterns for paths to skip.
+    pub fn excludes<I: IntoIterator<Item = glob::Pattern>>(mut self, patterns: I) -> Self{
+        self.options.excludes.extend(patterns);
+        self
+    }
+    /// Adds a regular expression for the paths of files to scan.
+    pub fn path_filter(mut self, filter: regex::Regex) -> Self{
+        self.options.path_filters.push(filter);
+        self
+    }
+    /// Adds a regular expression for paths to skip.
+    pub fn path_filter_out(mut self, filter: regex::Regex) -> Self{
+        self.options.path_filters_out.push(filter);
+        self
+    }
+    /// Adds regular expressions for the paths of files to scan.
+    pub fn path_filters<I: IntoIterator<Item = regex::Regex>>(mut self, filters: I) -> Self{
+        self.options.path_filters.extend(filters);
+        self
+    }
+    /// Adds regular expressions for paths to skip.
+    pub fn path_filters_out<I: IntoIterator<Item = regex::Regex>>(mut self, filters: I) -> Self{
+        self.options.path_filters_out.extend(filters);
+        self
+    }
+    /// Sets whether duplicates must also share a name.
+    pub fn require_name_match(mut self, require_name_match: bool) -> Self{
+        self.options.require_name_match = require_name_match;
+        self
+    }
+    /// Sets a cache of hashes to reuse and record.
+    pub fn cache(mut self, cache: Arc<HashCache>) -> Self{
+        self.options.cache = Some(cache);
+        self
+    }
+    /// Sets a prior manifest whose full hashes are reused for unchanged files.
+    pub fn since_manifest(mut self, since_manifest: Arc<Manifest>) -> Self{
+        self.options.since_manifest = Some(since_manifest);
+        self
+    }
+    /// Sets a cache of directory listings to reuse and record.
+    pub fn tree_cache(mut self, tree_cache: Arc<TreeCache>) -> Self{
+        self.options.tree_cache = Some(tree_cache);
+        self
+    }
+    /// Sets whether symlinks are followed.
+    pub fn follow_symlinks(mut self, follow_symlinks: bool) -> Self{
+        self.options.follow_symlinks = follow_symlinks;
+        self
+    }
+    /// Sets whether symlinks leading to a file already found are folded into it as aliases.
+    pub fn symlink_targets_once(mut self, symlink_targets_once: bool) -> Self{
+        self.options.symlink_targets_once = symlink_targets_once;
+        self
+    }
+    /// Sets whether traversal stays on one filesystem.
+    pub fn one_file_system(mut self, one_file_system: bool) -> Self{
+        self.options.one_file_system = one_file_system;
+        self
+    }
+    /// Sets whether mount points are descended despite `one_file_system`.
+    pub fn follow_mount_points(mut self, follow_mount_points: bool) -> Self{
+        self.options.follow_mount_points = follow_mount_points;
+        self
+    }
+    /// Sets a rate limit for reads in the full hash stage.
+    pub fn throttle(mut self, throttle: Arc<Throttle>) -> Self{
+        self.options.throttle = Some(throttle);
+        self
+    }
+    /// Sets a cap on the combined length of the files taken in.
+    pub fn budget(mut self, budget: Arc<ScanBudget>) -> Self{
+        self.options.budget = Some(budget);
+        self
+    }
+    /// Sets the bytes of each file to hash and compare.
+    pub fn hash_range(mut self, hash_range: HashRange) -> Self{
+        self.options.hash_range = Some(hash_range);
+        self
+    }
+    /// Sets a flag which winds down the scan once set.
+    pub fn stop(mut self, stop: Arc<AtomicBool>) -> Self{
+        self.options.stop = Some(stop);
+        self
+    }
+    /// Sets the hash policy for each search directory, by position.
+    pub fn root_policies(mut self, root_policies: Vec<HashPolicy>) -> Self{
+        self.options.root_policies = root_policies;
+        self
+    }
+    /// Sets whether sparse files are skipped.
+    pub fn skip_sparse(mut self, skip_sparse: bool) -> Self{
+        self.options.skip_sparse = skip_sparse;
+        self
+    }
+    /// Sets whether files locked by another process are skipped.
+    pub fn skip_locked(mut self, skip_locked: bool) -> Self{
+        self.options.skip_locked = skip_locked;
+        self
+    }
+    /// Sets whether hashes are stored in extended attributes.
+    pub fn xattr_cache(mut self, xattr_cache: bool) -> Self{
+        self.options.xattr_cache = xattr_cache;
+        self
+    }
+    /// Sets the hash used to identify file contents.
+    pub fn hash_algorithm(mut self, hash_algorithm: HashAlgorithm) -> Self{
+        self.options.hash_algorithm = hash_algorithm;
+        self
+    }
+    /// Sets whether the full hash of each large file is split across threads.
+    pub fn parallel_hash(mut self, parallel_hash: bool) -> Self{
+        self.options.parallel_hash = parallel_hash;
+        self
+    }
+    /// Sets the number of times a read failing with a transient error is retried.
+    pub fn read_retries(mut self, read_retries: u32) -> Self{
+        self.options.read_retries = read_retries;
+        self
+    }
+    /// Sets the number of threads to scan and hash with.
+    pub fn threads(mut self, threads: usize) -> Self{
+        self.options.threads = Some(threads);
+        self
+    }
+    /// Sets the length in bytes below which files are skipped.
+    pub fn min_size(mut self, min_size: u64) -> Self{
+        self.options.min_size = Some(min_size);
+        self
+    }
+    /// Sets the length in bytes above which files are skipped.
+    pub fn max_size(mut self, max_size: u64) -> Self{
+        self.options.max_size = Some(max_size);
+        self
+    }
+    /// Sets whether results are returned in a canonical order.
+    pub fn deterministic(mut self, deterministic: bool) -> Self{
+        self.options.deterministic = deterministic;
+        self
+    }
+    /// Sets the profile accumulating time spent in each stage.
+    pub fn profile(mut self, profile: Arc<Profile>) -> Self{
+        self.options.profile = Some(profile);
+        self
+    }
+    /// Sets the collector for length bucket statistics.
+    pub fn stats(mut self, stats: Arc<ScanStats>) -> Self{
+        self.options.stats = Some(stats);
+        self
+    }
+    /// Sets whether files with matching full hashes are grouped without comparing their bytes.
+    pub fn trust_full_hash(mut self, trust_full_hash: bool) -> Self{
+        self.options.trust_full_hash = trust_full_hash;
+        self
+    }
+    /// Sets how empty files are grouped.
+    pub fn empty_files(mut self, empty_files: EmptyFilePolicy) -> Self{
+        self.options.empty_files = empty_files;
+        self
+    }
+    /// Holds at most this many files in memory before spilling the rest to disk.
+    pub fn low_memory(mut self, files: usize) -> Self{
+        self.options.low_memory = Some(files);
+        self
+    }
+    /// Finishes building the options.
+    pub fn build(self) -> ScanOptions{
+        self.options
+    }
+}
+
+/// Files held in memory by a low memory scan, around a hundred megabytes of paths and details, before the rest are spilled to disk.
+pub const LOW_MEMORY_FILES: usize = 500_000;
+
+/// Version of the JSON report shape. Bump this whenever the serialized form of `Fileinfo` or the report envelope changes.
+pub const JSON_SCHEMA_VERSION: u32 = 2;
+
 /// Serializable struct containing entries for a specific file. These structs will identify individual files as a collection of paths and associated hash and length data.
+///
+/// Collections are compared by length and then by the most refined hash they have: the full hash if set, otherwise the partial hash.
+/// A collection with a full hash never equals one with only a partial hash, even when the partial hashes match. `Hash` agrees with `Eq`.
+///
+/// Paths are serialized as strings, so serializing fails where a path is not valid UTF-8, as is possible on Unix.
+///
+/// # Examples
+/// ```
+/// use std::path::PathBuf;
+/// use ddh::Fileinfo;
+///
+/// let file = Fileinfo::new(None, None, 3, PathBuf::from("photos/a.jpg"));
+/// assert!(serde_json::to_string(&file).unwrap().contains("photos/a.jpg"));
+/// #[cfg(unix)] {
+///     use std::ffi::OsStr;
+///     use std::os::unix::ffi::OsStrExt;
+///     let file = Fileinfo::new(None, None, 3, PathBuf::from(OsStr::from_bytes(b"photos/\xff.jpg")));
+///     assert!(serde_json::to_string(&file).is_err());
+/// }
+/// ```
 #[derive(Debug, Serialize)]
 pub struct Fileinfo{
     full_hash: Option<u128>,
     partial_hash: Option<u128>,
     file_length: u64,
+    #[serde(serialize_with = "serialize_sorted")]
     file_paths: Vec<PathBuf>,
+    #[serde(skip)]
+    file_roots: Vec<usize>,
+    #[serde(skip)]
+    file_modified: Vec<Option<SystemTime>>,
+    #[serde(skip)]
+    sparse_paths: Vec<PathBuf>,
+    #[serde(skip)]
+    aliases: Vec<(PathBuf, PathBuf)>,
+}
+
+// Paths are serialized in sorted order, as reports list them
+fn serialize_sorted<S: serde::Serializer>(paths: &[PathBuf], serializer: S) -> Result<S::Ok, S::Error>{
+    let mut sorted: Vec<&PathBuf> = paths.iter().collect();
+    sorted.sort();
+    serde::Serialize::serialize(&sorted, serializer)
 }
 
 impl Fileinfo{
@@ -55,7 +964,40 @@ impl Fileinfo{
     ///         );
     /// ```
     pub fn new(full_hash: Option<u128>, partial_hash: Option<u128>, length: u64, path: PathBuf) -> Self{
-        Fileinfo{full_hash: full_hash, partial_hash: partial_hash, file_length: length, file_paths: vec![path]}
+        Fileinfo{full_hash, partial_hash, file_length: length, file_paths: vec![path], file_roots: vec![0], file_modified: vec![None], sparse_paths: Vec::new(), aliases: Vec::new()}
+    }
+    fn sort_paths(&mut self){
+        let mut entries: Vec<(PathBuf, usize, Option<SystemTime>)> = self.file_paths.drain(..)
+            .zip(self.file_roots.drain(..))
+            .zip(self.file_modified.drain(..))
+            .map(|((path, root), modified)| (path, root, modified))
+            .collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        for (path, root, modified) in entries{
+            self.file_paths.push(path);
+            self.file_roots.push(root);
+            self.file_modified.push(modified);
+        }
+        self.sparse_paths.sort();
+        self.aliases.sort();
+    }
+    fn with_root(mut self, root: usize) -> Self{
+        self.file_roots = vec![root];
+        self
+    }
+    fn with_modified(mut self, modified: Option<SystemTime>) -> Self{
+        self.file_modified = vec![modified];
+        self
+    }
+    fn with_sparse(mut self, sparse: bool) -> Self{
+        if sparse {
+            self.sparse_paths = self.file_paths.clone();
+        }
+        self
+    }
+    fn with_aliases(mut self, aliases: Vec<(PathBuf, PathBuf)>) -> Self{
+        self.aliases = aliases;
+        self
     }
     /// Gets the length of the files in the current collection.
     ///
@@ -72,23 +1014,40 @@ impl Fileinfo{
         self.file_length
     }
     /// Gets the hash of the full file if available.
+    /// This is `None` until the full hash stage runs, which only happens for files sharing a length and partial hash with another file.
+    /// Files of at most one block take their partial hash as their full hash.
     ///
     /// # Examples
     /// ```
+    /// use std::fs;
     /// use std::path::Path;
     /// use ddh::Fileinfo;
     ///
     /// let fi = Fileinfo::new(Some(123), None, 3, Path::new("./foo/bar.txt").to_path_buf());
     /// let f_hash = fi.get_full_hash();
     /// assert_eq!(Some(123), f_hash);
+    ///
+    /// let temp = tempfile::tempdir().unwrap();
+    /// let root = temp.path();
+    /// fs::write(root.join("copy_a"), vec![1u8; 20000]).unwrap();
+    /// fs::write(root.join("copy_b"), vec![1u8; 20000]).unwrap();
+    /// fs::write(root.join("other"), vec![2u8; 20000]).unwrap();
+    /// let (files, _) = ddh::deduplicate_dirs(vec![&root]);
+    /// let promoted = files.iter().find(|x| x.get_paths().len() == 2).unwrap();
+    /// let partial_only = files.iter().find(|x| x.get_paths().len() == 1).unwrap();
+    /// assert!(promoted.get_full_hash().is_some());
+    /// assert!(partial_only.get_full_hash().is_none());
+    /// assert!(partial_only.get_partial_hash().is_some());
+    /// assert!(promoted != partial_only);
     /// ```
     pub fn get_full_hash(&self) -> Option<u128>{
         self.full_hash
     }
-    fn set_full_hash(&mut self, hash: Option<u128>) -> (){
+    fn set_full_hash(&mut self, hash: Option<u128>){
         self.full_hash = hash
     }
     /// Gets the hash of the partially read file if available.
+    /// This is `None` for files whose length no other file shares, since they cannot have duplicates and are never read, and for scans with `content_only`.
     ///
     /// # Examples
     /// ```
@@ -102,10 +1061,62 @@ impl Fileinfo{
     pub fn get_partial_hash(&self) -> Option<u128>{
         self.partial_hash
     }
-    fn set_partial_hash(&mut self, hash: Option<u128>) -> (){
+    fn set_partial_hash(&mut self, hash: Option<u128>){
         self.partial_hash = hash
     }
-    /// Gets a candidate name. This will be the name of the first file inserted into the collection and so can vary.
+    /// Checks whether the files in the collection were read in full, so a group of them rests on their whole contents.
+    /// Files of 4096 bytes or less are read in full by the partial hash, which then serves as their full hash. Groups under a partial `HashPolicy` root
+    /// may stop at the partial hash, and files whose length no other file shares are never read.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::fs;
+    /// use ddh::{HashPolicy, ScanOptions};
+    ///
+    /// let temp = tempfile::tempdir().unwrap();
+    /// let root = temp.path();
+    /// fs::write(root.join("small_a"), b"abc").unwrap();
+    /// fs::write(root.join("small_b"), b"abc").unwrap();
+    /// fs::write(root.join("large_a"), vec![7u8; 100_000]).unwrap();
+    /// fs::write(root.join("large_b"), vec![7u8; 100_000]).unwrap();
+    ///
+    /// let (files, _) = ddh::deduplicate_dirs(vec![&root]);
+    /// assert!(files.iter().all(|x| x.is_fully_hashed()));
+    ///
+    /// let options = ScanOptions::builder().root_policies(vec![HashPolicy::Partial]).build();
+    /// let (files, _) = ddh::deduplicate_dirs_with(vec![&root], &options);
+    /// assert!(files.iter().find(|x| x.get_length() == 3).unwrap().is_fully_hashed());
+    /// assert!(!files.iter().find(|x| x.get_length() == 100_000).unwrap().is_fully_hashed());
+    /// ```
+    pub fn is_fully_hashed(&self) -> bool{
+        self.full_hash.is_some()
+    }
+    /// Checks whether two files hold the same contents, going by their lengths and full hashes.
+    /// Files without a full hash on either side are never duplicates, as a matching partial hash only shows their sampled blocks match.
+    ///
+    /// This differs from `==`, which treats files as equal when they match on whatever is known of them, so two files of the same length are equal before either is hashed.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::path::Path;
+    /// use ddh::Fileinfo;
+    ///
+    /// let path = Path::new("./foo/bar.txt").to_path_buf();
+    /// let full = Fileinfo::new(Some(1), Some(9), 3, path.clone());
+    /// assert!(full.is_duplicate_of(&Fileinfo::new(Some(1), Some(9), 3, path.clone())));
+    /// assert!(!full.is_duplicate_of(&Fileinfo::new(Some(2), Some(9), 3, path.clone())));
+    /// assert!(!full.is_duplicate_of(&Fileinfo::new(Some(1), Some(9), 4, path.clone())));
+    ///
+    /// let partial = Fileinfo::new(None, Some(9), 3, path.clone());
+    /// assert!(partial == Fileinfo::new(None, Some(9), 3, path.clone()));
+    /// assert!(!partial.is_duplicate_of(&Fileinfo::new(None, Some(9), 3, path)));
+    /// ```
+    pub fn is_duplicate_of(&self, other: &Fileinfo) -> bool{
+        self.file_length == other.file_length
+            && self.full_hash.is_some()
+            && self.full_hash == other.full_hash
+    }
+    /// Gets a candidate name. This will be the name of the path sorting first, so it is the same whichever order the files were found in.
     ///
     /// # Examples
     /// ```
@@ -119,7 +1130,7 @@ impl Fileinfo{
     pub fn get_candidate_name(&self) -> &str{
         self.file_paths
         .iter()
-        .next()
+        .min()
         .unwrap()
         .to_str()
         .unwrap()
@@ -140,75 +1151,308 @@ impl Fileinfo{
     ///            all_files);
     /// ```
     pub fn get_paths(&self) -> &Vec<PathBuf>{
-        return &self.file_paths
+        &self.file_paths
+    }
+    /// Gets all paths in the current collection sorted lexicographically. Files are merged into collections in parallel, so `get_paths` can vary
+    /// in order from run to run while these do not. Reports list and serialize paths in this order.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::fs;
+    ///
+    /// let temp = tempfile::tempdir().unwrap();
+    /// let root = temp.path();
+    /// for name in ["c", "a", "d", "b"].iter() {
+    ///     fs::write(root.join(name), b"same").unwrap();
+    /// }
+    ///
+    /// let sorted_paths = || {
+    ///     let (files, _) = ddh::deduplicate_dirs(vec![&root]);
+    ///     files[0].get_paths_sorted().into_iter().cloned().collect::<Vec<_>>()
+    /// };
+    /// let first = sorted_paths();
+    /// assert_eq!(vec![root.join("a"), root.join("b"), root.join("c"), root.join("d")], first);
+    /// for _ in 0..10 {
+    ///     assert_eq!(first, sorted_paths());
+    /// }
+    /// ```
+    pub fn get_paths_sorted(&self) -> Vec<&PathBuf>{
+        let mut paths: Vec<&PathBuf> = self.file_paths.iter().collect();
+        paths.sort();
+        paths
+    }
+    /// Gets the index of the search directory each path was found under, in the same order as `get_paths`.
+    /// Files created with `Fileinfo::new` belong to search directory 0.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::path::Path;
+    /// use ddh::Fileinfo;
+    ///
+    /// let fi = Fileinfo::new(None, None, 3, Path::new("./foo/bar.txt").to_path_buf());
+    /// assert_eq!(&vec![0], fi.get_roots());
+    /// ```
+    pub fn get_roots(&self) -> &Vec<usize>{
+        &self.file_roots
+    }
+    /// Gets the modification time of each path when it was scanned, in the same order as `get_paths`.
+    /// Files created with `Fileinfo::new` have no known modification time.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::path::Path;
+    /// use ddh::Fileinfo;
+    ///
+    /// let fi = Fileinfo::new(None, None, 3, Path::new("./foo/bar.txt").to_path_buf());
+    /// assert_eq!(&vec![None], fi.get_modified());
+    /// ```
+    pub fn get_modified(&self) -> &Vec<Option<SystemTime>>{
+        &self.file_modified
+    }
+    /// Gets the paths in the current collection that were sparse when scanned. Holes in a sparse file read as zeros, so hashing one can be slow.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::path::Path;
+    /// use ddh::Fileinfo;
+    ///
+    /// let fi = Fileinfo::new(None, None, 3, Path::new("./foo/bar.txt").to_path_buf());
+    /// assert!(fi.get_sparse_paths().is_empty());
+    /// ```
+    pub fn get_sparse_paths(&self) -> &Vec<PathBuf>{
+        &self.sparse_paths
+    }
+    /// Gets the symlinks folded into the current collection by `ScanOptions::symlink_targets_once`, each with the path in the collection it leads to.
+    /// Aliases are not copies, so they are left out of the paths and of every count.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::path::Path;
+    /// use ddh::Fileinfo;
+    ///
+    /// let fi = Fileinfo::new(None, None, 3, Path::new("./foo/bar.txt").to_path_buf());
+    /// assert!(fi.get_aliases().is_empty());
+    /// ```
+    pub fn get_aliases(&self) -> &Vec<(PathBuf, PathBuf)>{
+        &self.aliases
+    }
+    /// Gets the distinct lowercased extensions among the paths in the current collection. Paths without an extension are not represented.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::path::Path;
+    /// use ddh::Fileinfo;
+    ///
+    /// let fi = Fileinfo::new(None, None, 3, Path::new("./foo/bar.JPG").to_path_buf());
+    /// let extensions: Vec<_> = fi.get_extension_set().into_iter().collect();
+    /// assert_eq!(vec!["jpg"], extensions);
+    /// ```
+    pub fn get_extension_set(&self) -> BTreeSet<String>{
+        self.file_paths
+        .iter()
+        .filter_map(|x| x.extension())
+        .map(|x| x.to_string_lossy().to_lowercase())
+        .collect()
     }
 
-    fn generate_hash(&mut self, mode: HashMode) -> Option<u128>{
-        let mut hasher = siphasher::sip128::SipHasher::new();
+    /// Hashes the first path, or takes its hash from a cache. Gives `Ok(None)` if the scan was stopped.
+    fn generate_hash(&mut self, mode: HashMode, options: &ScanOptions) -> Result<Option<u128>, Error>{
+        if options.is_stopped(){
+            return Ok(None)
+        }
+        let path = self.file_paths
+            .first()
+            .expect("Cannot read file path from struct")
+            .clone();
+        // Caches and manifests hold hashes of whole files, which a hash range does not match, and a manifest's hashes only match a scan using its algorithm
+        let cacheable = options.hash_range.is_none();
+        let cached = options.since_manifest.as_ref()
+            .filter(|x| mode == HashMode::Full && cacheable && x.hash_algorithm() == options.hash_algorithm)
+            .and_then(|x| x.lookup(&path, self.file_length, self.file_modified.first().copied().flatten()))
+            .or_else(|| options.cache.as_ref().filter(|_| cacheable).and_then(|x| x.lookup(&path, self.file_length, &mode, options.hash_algorithm))
+            .or_else(|| if options.xattr_cache && cacheable {cache::xattr_lookup(&path, self.file_length, &mode, options.sample_strategy, options.hash_algorithm)} else {None}));
+        if let Some(hash) = cached{
+            if let (HashMode::Full, Some(p)) = (&mode, &options.progress){
+                p.add_hashed(self.file_length);
+            }
+            return Ok(Some(hash))
+        }
+        let stage = if mode == HashMode::Partial {Stage::PartialHashing} else {Stage::FullHashing};
+        let mut retries = 0;
+        let hash = loop {
+            match profile::measure(options.profile.as_deref(), stage, || self.read_hash(&mode, options)) {
+                Err(e) if retries < options.read_retries && utils::is_transient_error(&e) && !options.is_stopped() => {
+                    thread::sleep(READ_RETRY_BACKOFF.saturating_mul(2u32.saturating_pow(retries)));
+                    retries += 1;
+                    // The retry reads the whole file again
+                    if let (HashMode::Full, Some(p)) = (&mode, &options.progress){
+                        p.add_total(self.file_length);
+                    }
+                },
+                Err(e) if retries > 0 => return Err(Error::new(e.kind(), format!("{} after {} retries", e, retries))),
+                result => break result?,
+            }
+        };
+        if let (HashMode::Full, Some(manifest), Some(_)) = (&mode, &options.since_manifest, hash){
+            manifest.record_rehashed();
+        }
+        if let (Some(cache), Some(hash), true) = (&options.cache, hash, cacheable){
+            cache.record(&path, self.file_length, &mode, options.hash_algorithm, hash);
+        }
+        if let (true, Some(hash), true) = (options.xattr_cache, hash, cacheable){
+            cache::xattr_record(&path, self.file_length, &mode, options.sample_strategy, options.hash_algorithm, hash);
+        }
+        Ok(hash)
+    }
+
+    fn read_hash(&self, mode: &HashMode, options: &ScanOptions) -> Result<Option<u128>, Error>{
+        let mut hasher = ContentHasher::new(options.hash_algorithm);
         match fs::File::open(
             self.file_paths
-            .iter()
-            .next()
+            .first()
             .expect("Cannot read file path from struct")
             ) {
             Ok(mut f) => {
+                let (start, end) = options.hash_range.map_or((0, self.file_length), |x| x.bounds(self.file_length));
+                if start == end && options.hash_range.is_some() {
+                    return Ok(None)
+                }
+                if start > 0 {
+                    f.seek(SeekFrom::Start(start))?;
+                }
+                let mut f = f.take(end - start);
+                let tracker = options.progress.as_ref()
+                    .filter(|_| *mode == HashMode::Full)
+                    .map(|p| p.track_file(&self.file_paths[0], end - start));
                 /* We want a read call to be "large" for two reasons
                 1) Force filesystem read ahead behavior
                 2) Fewer system calls for a given file.
                 Currently 16KB  */
+                if *mode == HashMode::Full && options.parallel_hash && options.hash_algorithm == HashAlgorithm::Blake3 && end - start > PARALLEL_READ_SIZE as u64{
+                    return read_hash_parallel(f, hasher, options, tracker.as_ref())
+                }
                 let mut hash_buffer = [0;BLOCK_SIZE * 4];
+                if *mode == HashMode::Partial && options.sample_strategy == SampleStrategy::Spread{
+                    let chunk_len = hash_buffer.len() as u64;
+                    let span = end - start;
+                    let sample_offsets = [
+                        0,
+                        (span/2).saturating_sub(chunk_len/2),
+                        span.saturating_sub(chunk_len)];
+                    for offset in sample_offsets.iter(){
+                        f.set_limit(span - offset);
+                        match f.get_mut().seek(SeekFrom::Start(start + offset)).and_then(|_| f.read(&mut hash_buffer)) {
+                            Ok(n) => hasher.write(&hash_buffer[..n]),
+                            Err(e) => {
+                                return Err(e)
+                            },
+                        }
+                    }
+                    return Ok(Some(hasher.finish()));
+                }
                 loop {
                     match f.read(&mut hash_buffer) {
-                        Ok(n) if n>0 => hasher.write(&hash_buffer),
-                        Ok(n) if n==0 => break,
-                        Err(_e) => {
-                            return None
+                        Ok(0) => break,
+                        Ok(n) => {
+                            hasher.write(&hash_buffer[..n]);
+                            if let Some(tracker) = &tracker{
+                                tracker.add_hashed(n as u64);
+                            }
+                            if let (HashMode::Full, Some(t)) = (mode, &options.throttle){
+                                t.acquire(n as u64);
+                            }
+                        },
+                        Err(e) => {
+                            return Err(e)
                         },
-                        _ => panic!("Negative length read in hashing"),
                         }
-                    if mode == HashMode::Partial{
-                        return Some(hasher.finish128().into());
+                    if *mode == HashMode::Partial{
+                        return Ok(Some(hasher.finish()));
+                    }
+                    if options.is_stopped(){
+                        return Ok(None)
                     }
                 }
-                return Some(hasher.finish128().into());
+                Ok(Some(hasher.finish()))
+            }
+            Err(e) => {
+                Err(e)
             }
-            Err(_e) => {
-                return None
+        }
+    }
+}
+
+/// Hashes the rest of `f` in large reads, each split across the current thread pool.
+fn read_hash_parallel<R: Read>(mut f: R, mut hasher: ContentHasher, options: &ScanOptions, tracker: Option<&FileTracker>) -> Result<Option<u128>, Error>{
+    let mut hash_buffer = vec![0; PARALLEL_READ_SIZE];
+    loop {
+        let mut filled = 0;
+        while filled < hash_buffer.len() {
+            match f.read(&mut hash_buffer[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) => return Err(e),
             }
         }
+        if filled == 0 {
+            break
+        }
+        if let Some(t) = &options.throttle{
+            t.acquire(filled as u64);
+        }
+        hasher.write_parallel(&hash_buffer[..filled]);
+        if let Some(tracker) = tracker{
+            tracker.add_hashed(filled as u64);
+        }
+        if options.is_stopped(){
+            return Ok(None)
+        }
     }
+    Ok(Some(hasher.finish()))
+}
+
+/// The most refined description of a collection's contents that is available, compared by `Fileinfo`'s equality, ordering and hashing.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum ContentKey{
+    Length,
+    Partial(u128),
+    Full(u128),
 }
 
+impl Fileinfo{
+    fn content_key(&self) -> (u64, ContentKey){
+        let key = match (self.full_hash, self.partial_hash) {
+            (Some(full), _) => ContentKey::Full(full),
+            (None, Some(partial)) => ContentKey::Partial(partial),
+            (None, None) => ContentKey::Length,
+        };
+        (self.file_length, key)
+    }
+}
+
+/// Compares files on their length and the most complete hash known of each, which orders files for grouping. Use `Fileinfo::is_duplicate_of` to test for duplicate contents.
 impl PartialEq for Fileinfo{
     fn eq(&self, other: &Fileinfo) -> bool {
-        (self.file_length==other.file_length)&&
-        (self.partial_hash==other.partial_hash)&&
-        (self.full_hash==other.full_hash)
+        self.content_key()==other.content_key()
     }
 }
 impl Eq for Fileinfo{}
 
+impl Hash for Fileinfo{
+    fn hash<H: Hasher>(&self, state: &mut H){
+        self.content_key().hash(state);
+    }
+}
+
 impl PartialOrd for Fileinfo{
     fn partial_cmp(&self, other: &Fileinfo) -> Option<Ordering>{
-         if self.full_hash.is_some() && other.full_hash.is_some(){
-            Some(self.full_hash.cmp(&other.full_hash))
-        } else if self.partial_hash.is_some() && other.partial_hash.is_some(){
-            Some(self.partial_hash.cmp(&other.partial_hash))
-        } else {
-            Some(self.file_length.cmp(&other.file_length))
-        }
+        Some(self.cmp(other))
     }
 }
 
 impl Ord for Fileinfo{
     fn cmp(&self, other: &Fileinfo) -> Ordering {
-        if self.full_hash.is_some() && other.full_hash.is_some(){
-            self.full_hash.cmp(&other.full_hash)
-        } else if self.partial_hash.is_some() && other.partial_hash.is_some(){
-            self.partial_hash.cmp(&other.partial_hash)
-        } else {
-            self.file_length.cmp(&other.file_length)
-        }
+        self.content_key().cmp(&other.content_key())
     }
 }
 
@@ -220,155 +1464,227 @@ impl Ord for Fileinfo{
 /// let (files, errors) = ddh::deduplicate_dirs(directories);
 /// ```
 pub fn deduplicate_dirs<P: AsRef<Path> + Sync>(search_dirs: Vec<P>) -> (Vec<Fileinfo>, Vec<(PathBuf, std::io::Error)>){
-    let (sender, receiver) = channel();
-    search_dirs.par_iter().for_each_with(sender, |s, search_dir| {
-            traverse_and_spawn(search_dir.as_ref(), s.clone());
-    });
-    let mut files_of_lengths: IntMap<u64, Vec<Fileinfo>> = IntMap::default();
-    let mut errors = Vec::new();
-    for pkg in receiver.iter(){
-        match pkg{
-            ChannelPackage::Success(entry) => {
-                match files_of_lengths.entry(entry.get_length()) {
-                    Entry::Vacant(e) => { e.insert(vec![entry]); },
-                    Entry::Occupied(mut e) => { e.get_mut().push(entry); }
-                }
-            },
-            ChannelPackage::Fail(entry, error) => {
-                errors.push((entry, error));
+    deduplicate_dirs_with(search_dirs, &ScanOptions::default())
+}
+
+/// Constructs a list of unique files from a list of directories using the given options.
+///
+/// # Examples
+/// ```
+/// use std::sync::Arc;
+/// use ddh::ScanOptions;
+/// use ddh::progress::Progress;
+///
+/// let progress = Arc::new(Progress::new());
+/// let options = ScanOptions{progress: Some(progress.clone()), ..ScanOptions::default()};
+/// let (files, errors) = ddh::deduplicate_dirs_with(vec!["/home/jon", "/home/doe"], &options);
+/// assert!(progress.hashed_bytes() <= progress.total_bytes());
+/// ```
+///
+/// Trees of any depth are traversed without growing the stack. Paths longer than the platform can open, 4096 bytes on Linux and 1024 on macOS,
+/// are returned as errors, so the deepest levels of a tree nested further than that are not searched.
+/// ```
+/// use std::fs;
+///
+/// let root = tempfile::tempdir().unwrap();
+/// let mut level = root.path().to_path_buf();
+/// let mut deepest = Vec::new();
+/// for depth in 1..=400 {
+///     level.push("d");
+///     if depth % 100 == 0 {
+///         fs::create_dir_all(&level).unwrap();
+///         deepest.push(level.join("f"));
+///         fs::write(level.join("f"), b"deep").unwrap();
+///     }
+/// }
+///
+/// let (files, errors) = ddh::deduplicate_dirs(vec![root.path()]);
+/// assert!(errors.is_empty());
+/// assert_eq!(1, files.len());
+/// assert!(files[0].get_paths().contains(&level.join("f")));
+/// deepest.sort();
+/// assert_eq!(deepest.iter().collect::<Vec<_>>(), files[0].get_paths_sorted());
+/// ```
+pub fn deduplicate_dirs_with<P: AsRef<Path> + Sync>(search_dirs: Vec<P>, options: &ScanOptions) -> (Vec<Fileinfo>, Vec<(PathBuf, std::io::Error)>){
+    let pool = options.thread_pool();
+    if let Some(limit) = options.low_memory {
+        return deduplicate_spilled(search_dirs, options, limit, &pool)
+    }
+    let (files_of_lengths, mut errors) = profile::measure(options.profile.as_deref(), Stage::Traversal, || scan::scan_dirs(search_dirs, options, true, &pool));
+    if let Some(stats) = &options.stats{
+        stats.record_lengths(&files_of_lengths);
+    }
+    let (mut files, hash_errors) = pool.install(|| dedup::consolidate(files_of_lengths, options));
+    errors.extend(hash_errors);
+    if options.deterministic {
+        sort_results(&mut files, &mut errors, options, &pool);
+    }
+    (files, errors)
+}
+
+// Consolidates each partition of the spilled buckets in turn, so only one partition's files are held before hashing. The results of every partition are kept
+fn deduplicate_spilled<P: AsRef<Path> + Sync>(search_dirs: Vec<P>, options: &ScanOptions, limit: usize, pool: &rayon::ThreadPool) -> (Vec<Fileinfo>, Vec<(PathBuf, std::io::Error)>){
+    let (mut spilled, mut errors) = profile::measure(options.profile.as_deref(), Stage::Traversal, || scan::scan_dirs_spilled(search_dirs, options, limit, pool));
+    if let Some(stats) = &options.stats{
+        stats.record_length_counts(spilled.length_counts());
+    }
+    let mut files = Vec::new();
+    for index in 0..spill::PARTITIONS{
+        let mut files_of_lengths = match spilled.take_partition(index) {
+            Ok(files_of_lengths) => files_of_lengths,
+            Err(e) => {
+                errors.push(e);
+                continue
             },
+        };
+        if options.symlink_targets_once {
+            scan::fold_symlinks(&mut files_of_lengths);
         }
+        let (partition_files, hash_errors) = pool.install(|| dedup::consolidate(files_of_lengths, options));
+        files.extend(partition_files);
+        errors.extend(hash_errors);
     }
-    let complete_files: Vec<Fileinfo> = files_of_lengths.into_par_iter()
-        .map(|x| differentiate_and_consolidate(x.0, x.1))
-        .flatten()
-        .collect();
-    (complete_files, errors)
+    if options.deterministic {
+        sort_results(&mut files, &mut errors, options, pool);
+    }
+    (files, errors)
 }
 
-fn traverse_and_spawn(current_path: &Path, sender: Sender<ChannelPackage>) -> (){
-    let current_path_metadata = match fs::symlink_metadata(current_path) {
-        Err(e) =>{
-            sender.send(
-            ChannelPackage::Fail(current_path.to_path_buf(), e)
-            ).expect("Error sending new ChannelPackage::Fail");
-            return
-        },
-        Ok(meta) => meta,
-    };
-
-    if current_path_metadata.file_type().is_symlink(){
-        sender.send(
-        ChannelPackage::Fail(current_path.to_path_buf(), Error::new(ErrorKind::Other, "Path is symlink"))
-        ).expect("Error sending new ChannelPackage::Fail");
-        return
-    }
-
-    if current_path_metadata.file_type().is_file(){
-            sender.send(ChannelPackage::Success(
-            Fileinfo::new(
-                None,
-                None,
-                current_path.metadata().expect("Error reading path length").len(),
-                current_path.to_path_buf()
-                ))
-            ).expect("Error sending new ChannelPackage::Success");
-        return
-    }
-
-    if current_path_metadata.file_type().is_dir(){
-        match fs::read_dir(current_path) {
-                Ok(read_dir_results) => {
-                    let good_entries: Vec<_> = read_dir_results
-                    .filter(|x| x.is_ok())
-                    .map(|x| x.unwrap())
-                    .collect();
-                    let (files, dirs): (Vec<&DirEntry>, Vec<&DirEntry>) = good_entries.par_iter().partition(|&x|
-                        x.file_type()
-                        .expect("Error reading DirEntry file type")
-                        .is_file()
-                        );
-                    files.par_iter().for_each_with(sender.clone(), |sender, x|
-                        sender.send(ChannelPackage::Success(
-                            Fileinfo::new(
-                                None,
-                                None,
-                                x.metadata().expect("Error reading path length").len(),
-                                x.path()))
-                                ).expect("Error sending new ChannelPackage::Success")
-                            );
-                    dirs.into_par_iter()
-                    .for_each_with(sender, |sender, x| {
-                        traverse_and_spawn(x.path().as_path(), sender.clone());
-                    })
-                },
-                Err(e) => {
-                    sender.send(
-                        ChannelPackage::Fail(current_path.to_path_buf(), e)
-                        ).expect("Error sending new ChannelPackage::Fail");
-                },
-            }
-    } else {}
+/// Constructs a list of files grouped by length and name alone, without reading any of their contents. Far faster than [`deduplicate_dirs_with`]
+/// and enough to catch copies made with their names intact, but files in a group are not verified to match, and copies under other names are missed.
+/// Names are compared as with `ScanOptions::require_name_match`. Files are left unhashed, so [`Fileinfo::is_fully_hashed`] is false for every group.
+///
+/// # Examples
+/// ```
+/// use std::fs;
+/// use ddh::ScanOptions;
+///
+/// let temp = tempfile::tempdir().unwrap();
+/// let root = temp.path();
+/// fs::create_dir_all(root.join("copy")).unwrap();
+/// fs::write(root.join("photo.jpg"), b"abc").unwrap();
+/// fs::write(root.join("copy/photo.jpg"), b"xyz").unwrap();
+/// fs::write(root.join("copy/other.jpg"), b"abc").unwrap();
+///
+/// let (files, _) = ddh::group_by_size_and_name_with(vec![&root], &ScanOptions::default());
+/// let shared: Vec<_> = files.iter().filter(|x| x.get_paths().len() > 1).collect();
+/// // Grouped on name and length although the contents differ, while the true copy under another name is missed
+/// assert_eq!(1, shared.len());
+/// assert!(shared[0].get_paths().contains(&root.join("copy/photo.jpg")));
+/// assert!(!shared[0].is_fully_hashed());
+/// ```
+pub fn group_by_size_and_name_with<P: AsRef<Path> + Sync>(search_dirs: Vec<P>, options: &ScanOptions) -> (Vec<Fileinfo>, Vec<(PathBuf, std::io::Error)>){
+    let pool = options.thread_pool();
+    let (files_of_lengths, mut errors) = profile::measure(options.profile.as_deref(), Stage::Traversal, || scan::scan_dirs(search_dirs, options, false, &pool));
+    if let Some(stats) = &options.stats{
+        stats.record_lengths(&files_of_lengths);
+    }
+    let mut files = profile::measure(options.profile.as_deref(), Stage::Grouping, || pool.install(|| dedup::consolidate_by_name(files_of_lengths, options)));
+    if options.deterministic {
+        sort_results(&mut files, &mut errors, options, &pool);
+    }
+    (files, errors)
 }
 
-fn differentiate_and_consolidate(file_length: u64, mut files: Vec<Fileinfo>) -> Vec<Fileinfo>{
-    if file_length==0{
-        return files
-    }
-    if files.len()<=0{
-        panic!("Invalid length vector");
-    }
-    match files.len(){
-        1 => return files,
-        n if n>1 => {
-            files.par_iter_mut().for_each(|file_ref| {
-                let hash = file_ref.generate_hash(HashMode::Partial);
-                file_ref.set_partial_hash(hash);
-            });
-            if file_length<=4096{
-                files.par_iter_mut().for_each(|x|{
-                    x.set_full_hash(x.get_partial_hash()) ;
-                });
-                return dedupe(files)
-            }
-            let mut partial_hashes: HashMap<u128, u64> = HashMap::new();
-            for file in files.iter(){
-                match partial_hashes.entry(file.get_partial_hash().unwrap()){
-                    Entry::Vacant(e) => { e.insert(0); },
-                    Entry::Occupied(mut e) => {*e.get_mut()+=1;}
-                }
-            }
-            let dedupe_hashes: Vec<_> = partial_hashes
-                .into_iter()
-                .filter(|x| x.1>0)
-                .map(|y| y.0)
-                .collect();
-            files.par_iter_mut().for_each(|x|
-                if dedupe_hashes.contains(&x.get_partial_hash().unwrap()){
-                    let hash = x.generate_hash(HashMode::Full);
-                    x.set_full_hash(hash);
-                }
-            );
-        },
-        _ => {panic!("Somehow a vector of negative length was created. Please report this as a bug");}
+/// The unique files and read errors found under one search directory.
+pub type DirResults = (Vec<Fileinfo>, Vec<(PathBuf, std::io::Error)>);
+
+/// Constructs a list of unique files under each directory, deduplicating every directory on its own instead of pooling their files.
+/// Returns the files and read errors of each directory, in the order given. Directories are traversed together,
+/// so a directory nested inside another search directory is only scanned under whichever reaches it first.
+///
+/// # Examples
+/// ```
+/// use std::fs;
+/// use ddh::ScanOptions;
+///
+/// let temp = tempfile::tempdir().unwrap();
+/// let root = temp.path();
+/// fs::create_dir_all(root.join("first")).unwrap();
+/// fs::create_dir_all(root.join("second")).unwrap();
+/// fs::write(root.join("first/a"), b"abc").unwrap();
+/// fs::write(root.join("first/b"), b"abc").unwrap();
+/// fs::write(root.join("second/a"), b"abc").unwrap();
+///
+/// let results = ddh::deduplicate_each_dir_with(vec![root.join("first"), root.join("second")], &ScanOptions::default());
+/// assert_eq!(vec![2], results[0].0.iter().map(|x| x.get_paths().len()).collect::<Vec<_>>());
+/// assert_eq!(vec![1], results[1].0.iter().map(|x| x.get_paths().len()).collect::<Vec<_>>());
+/// ```
+pub fn deduplicate_each_dir_with<P: AsRef<Path> + Sync>(search_dirs: Vec<P>, options: &ScanOptions) -> Vec<DirResults>{
+    let pool = options.thread_pool();
+    let roots: Vec<PathBuf> = search_dirs.iter().map(|x| x.as_ref().to_path_buf()).collect();
+    let (files_of_lengths, errors) = profile::measure(options.profile.as_deref(), Stage::Traversal, || scan::scan_dirs(search_dirs, options, true, &pool));
+    if let Some(stats) = &options.stats{
+        stats.record_lengths(&files_of_lengths);
     }
-    dedupe(files)
+    let mut files_of_roots: Vec<scan::LengthBuckets> = roots.iter().map(|_| scan::LengthBuckets::default()).collect();
+    for (length, files) in files_of_lengths.into_iter(){
+        for file in files.into_iter(){
+            files_of_roots[file.file_roots[0]].entry(length).or_default().push(file);
+        }
+    }
+    let mut errors_of_roots: Vec<Vec<(PathBuf, std::io::Error)>> = roots.iter().map(|_| Vec::new()).collect();
+    for error in errors.into_iter(){
+        let root = roots.iter().position(|x| error.0.starts_with(x)).unwrap_or_default();
+        errors_of_roots[root].push(error);
+    }
+    files_of_roots.into_iter().zip(errors_of_roots)
+        .map(|(files_of_lengths, mut errors)| {
+            let (mut files, hash_errors) = pool.install(|| dedup::consolidate(files_of_lengths, options));
+            errors.extend(hash_errors);
+            if options.deterministic {
+                sort_results(&mut files, &mut errors, options, &pool);
+            }
+            (files, errors)
+        })
+        .collect()
 }
 
-fn dedupe(mut files: Vec<Fileinfo>) -> Vec<Fileinfo>{
-    let mut cache: HashMap<(Option<u128>, Option<u128>), &mut Fileinfo> = HashMap::new();
-    for file in files.iter_mut(){
-        match cache.entry((file.get_partial_hash(), file.get_full_hash())){
-                    Entry::Vacant(e) => {
-                        e.insert(file);
-                    },
-                    Entry::Occupied(mut e) => {
-                        e.get_mut()
-                        .file_paths
-                        .append(&mut file.file_paths);
-                    }
-                }
+fn sort_results(files: &mut [Fileinfo], errors: &mut [(PathBuf, std::io::Error)], options: &ScanOptions, pool: &rayon::ThreadPool){
+    profile::measure(options.profile.as_deref(), Stage::Grouping, || pool.install(|| {
+        files.par_iter_mut().for_each(|x| x.sort_paths());
+        files.par_sort_by(|a, b| b.file_length.cmp(&a.file_length).then_with(|| a.file_paths.cmp(&b.file_paths)));
+    }));
+    errors.sort_by(|a, b| a.0.cmp(&b.0));
+}
+
+/// An upper bound on duplication, found by grouping files on length alone. Files of a unique length cannot have duplicates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub struct Estimate{
+    /// Lengths shared by more than one non-empty file. Each is at most one duplicate group per distinct content.
+    pub groups: u64,
+    /// Files sharing their length with another file.
+    pub files: u64,
+    /// Bytes that could be reclaimed if every file sharing a length were a duplicate.
+    pub duplicate_bytes: u64,
+}
+
+/// Estimates duplication under a list of directories without hashing any files.
+///
+/// # Examples
+/// ```
+/// use std::fs;
+/// use ddh::ScanOptions;
+///
+/// let temp = tempfile::tempdir().unwrap();
+/// let root = temp.path();
+/// fs::write(root.join("a"), b"abc").unwrap();
+/// fs::write(root.join("b"), b"xyz").unwrap();
+/// fs::write(root.join("c"), b"abcd").unwrap();
+///
+/// let (estimate, _) = ddh::estimate_dirs_with(vec![&root], &ScanOptions::default());
+/// assert_eq!((1, 2, 3), (estimate.groups, estimate.files, estimate.duplicate_bytes));
+/// ```
+pub fn estimate_dirs_with<P: AsRef<Path> + Sync>(search_dirs: Vec<P>, options: &ScanOptions) -> (Estimate, Vec<(PathBuf, std::io::Error)>){
+    let (files_of_lengths, errors) = profile::measure(options.profile.as_deref(), Stage::Traversal, || scan::scan_dirs(search_dirs, options, false, &options.thread_pool()));
+    if let Some(stats) = &options.stats{
+        stats.record_lengths(&files_of_lengths);
+    }
+    let mut estimate = Estimate::default();
+    for (length, files) in files_of_lengths.iter().filter(|x| *x.0>0 && x.1.len()>1){
+        estimate.groups += 1;
+        estimate.files += files.len() as u64;
+        estimate.duplicate_bytes += (files.len() as u64 - 1)*length;
     }
-    files.retain(|x| x.get_paths().len()>0);
-    files
+    (estimate, errors)
 }
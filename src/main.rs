@@ -1,26 +1,46 @@
-use std::io::{stdin};
+#[cfg(feature = "tui")]
+mod tui;
+pub mod report;
+
 use std::fs::{self};
-use std::io::prelude::*;
-use clap::{Arg, App};
+use clap::{Arg, ArgGroup, ArgMatches, App, AppSettings, ErrorKind};
 use rayon::prelude::*;
-use ddh::{Fileinfo};
-use std::path::{PathBuf};
-
-#[derive(Debug, Copy, Clone)]
-pub enum PrintFmt{
-    Standard,
-    Json,
-    Off,
-}
-
-pub enum Verbosity{
-    Quiet,
-    Duplicates,
-    All,
-}
+use ddh::{Fileinfo, ScanOptions, SampleStrategy, HashAlgorithm, HashPolicy, EmptyFilePolicy, DirResults};
+use ddh::progress::Progress;
+use ddh::profile::{Profile, Stage};
+use ddh::stats::ScanStats;
+use ddh::cache::HashCache;
+use ddh::treecache::TreeCache;
+use ddh::throttle::Throttle;
+use ddh::budget::ScanBudget;
+use ddh::range::HashRange;
+use ddh::actions::{action_targets, prune_empty_dirs, Action, Retention, RetentionPolicy};
+use ddh::script::{Script, Shell};
+use ddh::manifest::Manifest;
+use ddh::directories::{find_duplicate_directories, find_overlapping_directories, find_redundant_directories};
+use ddh::text::find_near_duplicate_text;
+use ddh::compressed::find_compressed_copies;
+use ddh::config::Config;
+use ddh::names::find_similar_names;
+use ddh::compare::{compare_trees, files_present_in};
+use ddh::watch::WatchIndex;
+use notify::Watcher;
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use report::{print_action_plan, print_broken_symlinks, print_compressed_copies, print_duplicate_directories, print_estimate, print_largest_duplicates, print_locked_files, print_manifest_matches, print_near_duplicate_text, print_new_duplicate, print_overlapping_directories, print_present_files, print_profile, print_redundant_directories, print_scan_stats, print_similar_names, print_symlink_aliases, print_tree_differences, process_full_output, root_destination, print_format, print_note, same_directory, writes_json_file, PrintFmt};
 
 fn main() {
-    let arguments = App::new("Directory Difference hTool")
+    // Rust ignores SIGPIPE, which turns a closed pipe into a panic on the next print. Restoring the default ends ddh quietly, as when piped to head
+    #[cfg(target_os = "linux")]
+    unsafe {
+        libc::signal(libc::SIGPIPE, libc::SIG_DFL);
+    }
+    let app = App::new("Directory Difference hTool")
                         .version(env!("CARGO_PKG_VERSION"))
                         .author(env!("CARGO_PKG_AUTHORS"))
                         .about("Compare and contrast directories.\nExample invocation: ddh /home/jon/downloads /home/jon/documents -f duplicates\nExample pipe: ddh ~/Downloads/ -o no -v all -f json | someJsonParser.bin")
@@ -30,9 +50,32 @@ fn main() {
                                .value_name("Directories")
                                .help("Directories to parse")
                                .min_values(1)
-                               .required(true)
+                               .required_unless_one(&["Root", "DirsFrom0", "SelfTest", "Config"])
                                .takes_value(true)
                                .index(1))
+                        .arg(Arg::with_name("Root")
+                               .long("root")
+                               .value_name("Directory:Policy")
+                               .takes_value(true)
+                               .multiple(true)
+                               .number_of_values(1)
+                               .validator(|x| parse_root(&x).map(|_| ()))
+                               .help("Searches a directory with its own hash policy, 'full' or 'partial'. Partial roots trust partial hash matches unless a file may duplicate one under a full root. May be given more than once."))
+                        .arg(Arg::with_name("SelfTest")
+                               .long("self-test")
+                               .help("Hashes built in vectors with the selected --hash-algorithm and checks them against known hashes, then exits. Exits with 1 on any mismatch. Worth running before trusting a new system with --delete."))
+                        .arg(Arg::with_name("DirsFrom0")
+                               .long("dirs-from0")
+                               .value_name("File")
+                               .takes_value(true)
+                               .max_values(1)
+                               .help("Reads directories to search from a file of NUL separated paths, such as the output of find -print0."))
+                        .arg(Arg::with_name("Config")
+                               .long("config")
+                               .value_name("File")
+                               .takes_value(true)
+                               .max_values(1)
+                               .help("Reads settings from a TOML file of long option names and their values, with the directories to search as roots. Options given on the command line take precedence."))
                         .arg(Arg::with_name("Blocksize")
                                .short("bs")
                                .long("blocksize")
@@ -41,6 +84,13 @@ fn main() {
                                .max_values(1)
                                .possible_values(&["B", "K", "M", "G"])
                                .help("Sets the display blocksize to Bytes, Kilobytes, Megabytes or Gigabytes. Default is Kilobytes."))
+                        .arg(Arg::with_name("Locale")
+                                .long("locale")
+                                .value_name("Locale")
+                                .takes_value(true)
+                                .max_values(1)
+                                .validator(|x| ddh::utils::NumberFormat::from_locale(&x).map(|_| ()).ok_or_else(|| format!("Unknown locale {}. Use a language such as en, de or fr, or C for plain numbers", x)))
+                                .help("Writes numbers in the printed summary and listing with the separators of this locale, such as de_DE or fr. 'C' leaves them plain. Default is commas between thousands."))
                         .arg(Arg::with_name("Verbosity")
                                 .short("v")
                                 .long("verbosity")
@@ -54,6 +104,29 @@ fn main() {
                                 .takes_value(true)
                                 .max_values(1)
                                 .help("Sets file to save all output. Use 'no' for no file output."))
+                        .arg(Arg::with_name("OutputNullOnEmpty")
+                                .long("output-null-on-empty")
+                                .help("Writes no output file when there are no duplicates, unless singletons are printed with -v all."))
+                        .arg(Arg::with_name("Append")
+                                .long("append")
+                                .help("Appends to the output file under a timestamp header instead of overwriting it, without prompting. Only for the standard format."))
+                        .arg(Arg::with_name("AssumeYes")
+                                .long("assume-yes")
+                                .conflicts_with("AssumeNo")
+                                .help("Answers yes to every prompt, such as whether to overwrite the output file, without asking."))
+                        .arg(Arg::with_name("AssumeNo")
+                                .long("assume-no")
+                                .help("Answers no to every prompt without asking. Prompts are also answered no when stdin is not a terminal."))
+                        .arg(Arg::with_name("OutputEncoding")
+                                .long("output-encoding")
+                                .value_name("Encoding")
+                                .takes_value(true)
+                                .max_values(1)
+                                .possible_values(&["utf8", "utf16le"])
+                                .help("Sets the encoding of a standard output file. utf16le writes UTF-16 with a byte order mark, as some Windows applications expect. Default is utf8. JSON is always UTF-8."))
+                        .arg(Arg::with_name("ReportChecksum")
+                                .long("report-checksum")
+                                .help("Writes a SHA-256 of the output file to a sidecar named with .sha256 appended, in the format sha256sum -c checks."))
                         .arg(Arg::with_name("Format")
                                 .short("f")
                                 .long("format")
@@ -61,139 +134,1234 @@ fn main() {
                                 .takes_value(true)
                                 .max_values(1)
                                 .help("Sets output format."))
-                        .get_matches();
+                        .arg(Arg::with_name("Deterministic")
+                                .long("deterministic")
+                                .help("Sorts groups, paths and errors so the same tree always gives byte identical reports. Adds a sort after hashing."))
+                        .arg(Arg::with_name("NormalizeText")
+                                .long("normalize-text")
+                                .help("Also lists text files that match once line endings are unified, but are not exact duplicates. Only files with common text extensions are read."))
+                        .arg(Arg::with_name("TrimTrailingWhitespace")
+                                .long("trim-trailing-whitespace")
+                                .requires("NormalizeText")
+                                .help("Ignores spaces and tabs at the end of each line when using --normalize-text."))
+                        .arg(Arg::with_name("DetectCompressed")
+                                .long("detect-compressed")
+                                .help("Also lists gzip files whose decompressed contents match plain files, such as foo and foo.gz. Only gzip files with a plain file of their uncompressed size are read. Only reports."))
+                        .arg(Arg::with_name("FuzzyNames")
+                                .long("fuzzy-names")
+                                .value_name("Distance")
+                                .takes_value(true)
+                                .max_values(1)
+                                .validator(|x| match x.parse::<usize>() {
+                                    Ok(distance) if distance > 0 => Ok(()),
+                                    _ => Err("Distance must be a positive number".to_string()),
+                                })
+                                .help("Also lists files in one directory whose names are within this many character edits of each other, such as manually numbered versions."))
+                        .arg(Arg::with_name("DirsEqual")
+                                .long("dirs-equal")
+                                .conflicts_with_all(&["Estimate", "SeparateRoots", "Tui", "Delete", "Hardlink", "ReflinkDedupe"])
+                                .help("Checks that two directories hold the same file contents instead of reporting duplicates. Lists files only under either and files that differ, and exits with 1 if there are any."))
+                        .arg(Arg::with_name("GroupsJsonLines")
+                                .long("print-groups-json-lines")
+                                .conflicts_with_all(&["Estimate", "SeparateRoots", "Tui", "Delete", "Hardlink", "ReflinkDedupe", "DirsEqual", "CompareWith"])
+                                .help("Prints each duplicate group as one line of JSON with the copy a --keep policy would retain, the copies to delete, the size and the hash, instead of reporting duplicates. For external tools acting on the plan."))
+                        .arg(Arg::with_name("CompareWith")
+                                .long("compare-with")
+                                .value_name("Directory")
+                                .takes_value(true)
+                                .max_values(1)
+                                .conflicts_with_all(&["Estimate", "SeparateRoots", "Tui", "Delete", "Hardlink", "ReflinkDedupe", "DirsEqual"])
+                                .help("Lists files under the search directories that already have a copy anywhere under this directory, instead of reporting duplicates. Useful for deciding what is safe to delete."))
+                        .arg(Arg::with_name("DuplicateDirs")
+                                .long("duplicate-dirs")
+                                .help("Also lists directories whose whole trees hold the same files, regardless of names."))
+                        .arg(Arg::with_name("DirOverlap")
+                                .long("dir-overlap")
+                                .value_name("Percent")
+                                .takes_value(true)
+                                .max_values(1)
+                                .validator(|x| match x.parse::<u64>() {
+                                    Ok(percent) if percent > 0 && percent <= 100 => Ok(()),
+                                    _ => Err("Percent must be a number from 1 to 100".to_string()),
+                                })
+                                .help("Also lists pairs of directories with the same name whose trees share at least this percentage of their contents, as likely copies of one backup."))
+                        .arg(Arg::with_name("GroupByParent")
+                                .long("group-by-parent")
+                                .help("Also lists directories holding copies of their own files, ranked by the number of extra copies and then by bytes."))
+                        .arg(Arg::with_name("Estimate")
+                                .long("estimate")
+                                .conflicts_with_all(&["Delete", "Hardlink", "ReflinkDedupe", "Tui"])
+                                .help("Reports an upper bound on duplicates by grouping files on length alone, without hashing. Near instant."))
+                        .arg(Arg::with_name("FormatFromExtension")
+                                .long("output-format-from-extension")
+                                .help("Picks the output file's format from its extension, json for .json and standard for .txt. --format takes precedence."))
+                        .arg(Arg::with_name("Profile")
+                                .long("profile")
+                                .help("Prints the time spent traversing, hashing, grouping, comparing and reporting on stderr."))
+                        .arg(Arg::with_name("Stats")
+                                .long("stats")
+                                .help("Lists how many files share each length. Every length shared by several files is hashed, so a few large buckets can dominate a scan. Also counts files deleted before they could be fully hashed."))
+                        .arg(Arg::with_name("Progress")
+                                .short("p")
+                                .long("progress")
+                                .help("Shows progress and an estimated time remaining for the full hash stage on stderr, with the progress of any file over a gigabyte."))
+                        .arg(Arg::with_name("LargestDuplicates")
+                                .long("report-largest-duplicates")
+                                .value_name("N")
+                                .takes_value(true)
+                                .max_values(1)
+                                .validator(|x| x.parse::<usize>().map(|_| ()).map_err(|e| e.to_string()))
+                                .help("Also lists the N duplicate groups with the largest files, whatever the verbosity. Deleting their copies reclaims the most space per file."))
+                        .arg(Arg::with_name("Limit")
+                                .short("l")
+                                .long("limit")
+                                .takes_value(true)
+                                .max_values(1)
+                                .validator(|x| x.parse::<usize>().map(|_| ()).map_err(|e| e.to_string()))
+                                .help("Lists only the N largest duplicate groups. Summary totals still cover every group."))
+                        .arg(Arg::with_name("MinFiles")
+                                .long("min-files")
+                                .value_name("N")
+                                .takes_value(true)
+                                .max_values(1)
+                                .validator(|x| x.parse::<u64>().map(|_| ()).map_err(|e| e.to_string()))
+                                .help("Exits with an error if fewer than N files are found. Guards against mistyped paths and overly broad excludes."))
+                        .arg(Arg::with_name("SingletonsUnder")
+                                .long("print-singletons-only-under")
+                                .value_name("Directory")
+                                .takes_value(true)
+                                .max_values(1)
+                                .help("Lists only files found under this search directory and under no other. Useful for finding files missing from a backup."))
+                        .arg(Arg::with_name("SampleStrategy")
+                                .long("sample-strategy")
+                                .possible_values(&["prefix", "spread"])
+                                .takes_value(true)
+                                .max_values(1)
+                                .help("Sets which parts of a file the partial hash reads. 'spread' samples the start, middle and end of each file. Default is prefix."))
+                        .arg(Arg::with_name("EmptyFiles")
+                                .long("empty-files")
+                                .value_name("Policy")
+                                .possible_values(&["unique", "group", "skip"])
+                                .conflicts_with_all(&["Tar", "Watch"])
+                                .takes_value(true)
+                                .max_values(1)
+                                .help("Sets how empty files are reported. unique lists each as a single instance file, group lists them all as one group of shared instance files, and skip leaves them out of the results and every count. Default is unique."))
+                        .arg(Arg::with_name("HashAlgorithm")
+                                .long("hash-algorithm")
+                                .possible_values(&["sip128", "blake3"])
+                                .takes_value(true)
+                                .max_values(1)
+                                .help("Sets the hash used to identify file contents. Default is sip128."))
+                        .arg(Arg::with_name("SkipHeader")
+                                .long("skip-header")
+                                .value_name("Bytes")
+                                .takes_value(true)
+                                .max_values(1)
+                                .validator(|x| x.parse::<u64>().map(|_| ()).map_err(|e| e.to_string()))
+                                .conflicts_with_all(&["HashRange", "ExportManifest", "CompareManifest", "SinceManifest"])
+                                .help("Ignores the first N bytes of each file when hashing and comparing, for formats with a volatile fixed size header. Files no longer than N are never grouped. Files matched this way may differ in their headers, so check before deleting."))
+                        .arg(Arg::with_name("HashRange")
+                                .long("hash-range")
+                                .value_name("Start:End")
+                                .takes_value(true)
+                                .number_of_values(1)
+                                .allow_hyphen_values(true)
+                                .validator(|x| HashRange::parse(&x).map(|_| ()))
+                                .conflicts_with_all(&["ExportManifest", "CompareManifest", "SinceManifest"])
+                                .help("Hashes and compares only these bytes of each file, such as 1024: or :-512, so files differing elsewhere count as duplicates. Negative offsets count from the end of the file."))
+                        .arg(Arg::with_name("ParallelHash")
+                                .long("parallel-hash")
+                                .help("Splits the full hash of each large file across threads. Speeds up scans of a few huge files such as disk images. Requires --hash-algorithm blake3."))
+                        .arg(Arg::with_name("Threads")
+                                .long("threads")
+                                .value_name("N")
+                                .takes_value(true)
+                                .max_values(1)
+                                .validator(|x| match x.parse::<usize>() {
+                                    Ok(threads) if threads > 0 => Ok(()),
+                                    _ => Err("Threads must be a positive number".to_string()),
+                                })
+                                .help("Scans and hashes with N threads. Default is one per CPU."))
+                        .arg(Arg::with_name("MinSize")
+                                .long("min-size")
+                                .value_name("Bytes")
+                                .takes_value(true)
+                                .max_values(1)
+                                .validator(|x| x.parse::<u64>().map(|_| ()).map_err(|e| e.to_string()))
+                                .help("Skips files shorter than this many bytes."))
+                        .arg(Arg::with_name("MaxSize")
+                                .long("max-size")
+                                .value_name("Bytes")
+                                .takes_value(true)
+                                .max_values(1)
+                                .validator(|x| x.parse::<u64>().map(|_| ()).map_err(|e| e.to_string()))
+                                .help("Skips files longer than this many bytes."))
+                        .arg(Arg::with_name("MaxTotalSize")
+                                .long("max-total-size")
+                                .value_name("Bytes")
+                                .takes_value(true)
+                                .max_values(1)
+                                .validator(|x| x.parse::<u64>().map(|_| ()).map_err(|e| e.to_string()))
+                                .help("Stops taking in files once their combined length would pass this many bytes. Results are marked as partial. Useful for sampling a large volume."))
+                        .arg(Arg::with_name("Exclude")
+                                .short("e")
+                                .long("exclude")
+                                .takes_value(true)
+                                .multiple(true)
+                                .number_of_values(1)
+                                .help("Skips files and directories whose name or path matches this glob. May be given more than once."))
+                        .arg(Arg::with_name("PathFilter")
+                                .long("path-filter")
+                                .value_name("Regex")
+                                .takes_value(true)
+                                .multiple(true)
+                                .number_of_values(1)
+                                .help("Only scans files whose full path matches this regular expression. Directories are still traversed. May be given more than once."))
+                        .arg(Arg::with_name("PathFilterOut")
+                                .long("path-filter-out")
+                                .value_name("Regex")
+                                .takes_value(true)
+                                .multiple(true)
+                                .number_of_values(1)
+                                .help("Skips files and directories whose full path matches this regular expression. May be given more than once."))
+                        .arg(Arg::with_name("IncludeExt")
+                                .long("include-ext")
+                                .value_name("Pattern")
+                                .takes_value(true)
+                                .multiple(true)
+                                .number_of_values(1)
+                                .help("Only scans files whose extension matches this glob, such as jp*g or tar.gz. Case insensitive. May be given more than once."))
+                        .arg(Arg::with_name("ExcludeExt")
+                                .long("exclude-ext")
+                                .value_name("Pattern")
+                                .takes_value(true)
+                                .multiple(true)
+                                .number_of_values(1)
+                                .help("Skips files whose extension matches this glob. Case insensitive. May be given more than once."))
+                        .arg(Arg::with_name("ExportManifest")
+                                .long("export-manifest")
+                                .value_name("File")
+                                .takes_value(true)
+                                .max_values(1)
+                                .help("Writes the hash, length and paths of every file to a manifest for use with --compare-manifest elsewhere or --since-manifest later. Implies --compare-content-only."))
+                        .arg(Arg::with_name("CompareManifest")
+                                .long("compare-manifest")
+                                .value_name("File")
+                                .takes_value(true)
+                                .max_values(1)
+                                .help("Lists files whose contents appear in a manifest written by --export-manifest. Implies --compare-content-only."))
+                        .arg(Arg::with_name("SinceManifest")
+                                .long("since-manifest")
+                                .value_name("File")
+                                .takes_value(true)
+                                .max_values(1)
+                                .help("Reuses hashes from a manifest written by --export-manifest for files whose size and modification time are unchanged, hashing only new and changed files. Implies --compare-content-only."))
+                        .arg(Arg::with_name("ExcludeFrom")
+                                .long("exclude-from")
+                                .value_name("File")
+                                .takes_value(true)
+                                .max_values(1)
+                                .help("Reads exclude globs from a file, one per line. Lines starting with '#' are comments."))
+                        .arg(Arg::with_name("ExactBytes")
+                                .long("exact-bytes")
+                                .help("Follows each size in the summary with its exact byte count, as sizes in the display blocksize are rounded."))
+                        .arg(Arg::with_name("ByExtension")
+                                .long("by-extension")
+                                .help("Breaks down duplicated bytes by file extension."))
+                        .arg(Arg::with_name("SeparateRoots")
+                                .long("separate-roots")
+                                .conflicts_with("Estimate")
+                                .help("Deduplicates each search directory on its own instead of pooling their files, with a report per directory. Output files are numbered by directory, as in Results.1.txt."))
+                        .arg(Arg::with_name("Tar")
+                                .long("tar")
+                                .conflicts_with_all(&["Estimate", "SeparateRoots", "Tui", "Delete", "Hardlink", "ReflinkDedupe", "EmitScript", "DirsEqual", "CompareWith", "HashRange", "SkipHeader", "Root", "NormalizeText", "DetectCompressed", "SinceManifest", "Resume"])
+                                .help("Reads each search path as a tar archive and finds duplicate members inside them without extracting, reporting paths as archive.tar!member. Only reports, and takes no actions."))
+                        .arg(Arg::with_name("Watch")
+                                .long("watch")
+                                .conflicts_with_all(&["Estimate", "SeparateRoots", "Tui", "Tar", "ReflinkDedupe", "EmitScript", "DirsEqual", "GroupsJsonLines", "CompareWith", "Resume", "Progress"])
+                                .help("Keeps watching the search directories after indexing them, reporting each new or changed file that duplicates one already there until Ctrl-C. With --delete or --hardlink, acts on each new duplicate as it is found."))
+                        .arg(Arg::with_name("WatchInterval")
+                                .long("watch-interval")
+                                .value_name("Seconds")
+                                .takes_value(true)
+                                .max_values(1)
+                                .requires("Watch")
+                                .validator(|x| match x.parse::<u64>() {
+                                    Ok(seconds) if seconds > 0 => Ok(()),
+                                    _ => Err("Seconds must be a positive number".to_string()),
+                                })
+                                .help("Sets how often --watch checks for changes, at least 1 second. Default is 2 seconds."))
+                        .arg(Arg::with_name("WatchPoll")
+                                .long("watch-poll")
+                                .requires("Watch")
+                                .help("Has --watch rescan the search directories at each interval instead of following filesystem events. Needed on network shares, where changes made by other machines raise no events."))
+                        .arg(Arg::with_name("ByRoot")
+                                .long("by-root")
+                                .help("Breaks down scanned files and duplicated bytes by search directory, sharing each group's reclaimable bytes among the directories holding its copies."))
+                        .arg(Arg::with_name("Resume")
+                                .long("resume")
+                                .value_name("File")
+                                .takes_value(true)
+                                .max_values(1)
+                                .help("Saves hashing state to this file if the scan is interrupted with Ctrl-C, and resumes from it on the next run."))
+                        .arg(Arg::with_name("TreeCache")
+                                .long("tree-cache")
+                                .value_name("File")
+                                .takes_value(true)
+                                .max_values(1)
+                                .conflicts_with_all(&["Tar", "Watch"])
+                                .help("Saves directory listings to this file and reuses them on the next run for directories whose modification time is unchanged, skipping reading them."))
+                        .arg(Arg::with_name("ReadRetries")
+                                .long("read-retries")
+                                .value_name("N")
+                                .takes_value(true)
+                                .max_values(1)
+                                .validator(|x| x.parse::<u32>().map(|_| ()).map_err(|e| e.to_string()))
+                                .help("Retries reads failing with transient errors, such as timeouts on network storage, up to N times with a doubling wait. Default is 0."))
+                        .arg(Arg::with_name("RequireNameMatch")
+                                .long("require-name-match")
+                                .help("Only treats files as duplicates if their names match as well as their contents."))
+                        .arg(Arg::with_name("CompareMode")
+                                .long("compare-mode")
+                                .value_name("Mode")
+                                .takes_value(true)
+                                .max_values(1)
+                                .possible_values(&["content", "size-then-name"])
+                                .help("Sets how files are matched. Default is content, hashing and comparing files of the same length. size-then-name groups files of the same length and name without reading them, which is fast but unverified."))
+                        .arg(Arg::with_name("JsonShape")
+                                .long("json-shape")
+                                .value_name("Shape")
+                                .takes_value(true)
+                                .max_values(1)
+                                .possible_values(&["groups", "map"])
+                                .help("Sets the shape of JSON results. 'map' is an object from each duplicate group's hash to its paths. Default is groups."))
+                        .arg(Arg::with_name("CommonPrefix")
+                                .long("common-prefix")
+                                .help("Names the longest directory shared by each duplicate group's paths in its heading and lists the paths relative to it."))
+                        .arg(Arg::with_name("GroupSeparator")
+                                .long("group-separator")
+                                .value_name("Line")
+                                .takes_value(true)
+                                .max_values(1)
+                                .help("Prints this line between groups in the printed duplicates listing, for line based parsers. Pass '' for a blank line. By default groups follow one another directly."))
+                        .arg(Arg::with_name("ResolveHardlinks")
+                                .long("resolve-hardlinks-in-report")
+                                .hidden(cfg!(not(unix)))
+                                .help("Marks each path in the duplicates listing with its inode and lists hard links to one file together, with the number of distinct files in each group."))
+                        .arg(Arg::with_name("ReportFormat")
+                                .long("report-format")
+                                .value_name("Layout")
+                                .takes_value(true)
+                                .max_values(1)
+                                .possible_values(&["listing", "compact"])
+                                .conflicts_with("Template")
+                                .help("Sets the layout of the printed duplicates listing. 'compact' prints each group on one line as '<count>x <size> <hash> <first path> [+N more]'. Default is listing."))
+                        .arg(Arg::with_name("Template")
+                                .long("template")
+                                .value_name("Template")
+                                .takes_value(true)
+                                .max_values(1)
+                                .validator(|x| ddh::template::Template::parse(&x).map(|_| ()))
+                                .help("Lists each duplicate path on its own line in this layout, such as '{size}\\t{path}'. Fields are size, path, group, root and mtime."))
+                        .arg(Arg::with_name("Throttle")
+                                .long("throttle")
+                                .value_name("MB/s")
+                                .takes_value(true)
+                                .max_values(1)
+                                .validator(|x| match x.parse::<f64>() {
+                                    Ok(rate) if rate > 0.0 => Ok(()),
+                                    _ => Err("Throttle must be a positive number of megabytes per second".to_string()),
+                                })
+                                .help("Limits total read bandwidth during full hashing to this many megabytes per second."))
+                        .arg(Arg::with_name("FollowSymlinks")
+                                .long("follow-symlinks")
+                                .help("Follows symlinks to files and directories instead of skipping them."))
+                        .arg(Arg::with_name("HashSymlinkTargetsOnce")
+                                .long("hash-symlink-targets-once")
+                                .requires("FollowSymlinks")
+                                .help("Hashes and counts a file reached through symlinks once, listing the symlinks as aliases of it rather than as duplicates."))
+                        .arg(Arg::with_name("OneFileSystem")
+                                .short("x")
+                                .long("one-file-system")
+                                .help("Stays on the filesystem of each directory. Skips mount points and symlinks leading to other filesystems."))
+                        .arg(Arg::with_name("BrokenSymlinks")
+                                .long("follow-broken-symlinks-report")
+                                .help("Lists symlinks whose targets do not exist."))
+                        .arg(Arg::with_name("FollowMountPoints")
+                                .long("follow-mount-points")
+                                .requires("OneFileSystem")
+                                .help("Descends into mount points despite --one-file-system. Symlinks must still stay on one filesystem."))
+                        .arg(Arg::with_name("ContentOnly")
+                                .long("compare-content-only")
+                                .help("Fully hashes every file and groups by content alone. Slow, but useful for auditing the default size based grouping."))
+                        .arg(Arg::with_name("HashOnlyFull")
+                                .long("hash-only-full")
+                                .help("Fully hashes every candidate without a partial hash stage first. Faster for trees of many small files, slower for large ones."))
+                        .arg(Arg::with_name("Confidence")
+                                .long("confidence")
+                                .help("Adds the chance of a hash collision among the files scanned to the summary, estimated by the birthday bound."))
+                        .arg(Arg::with_name("TrustFullHash")
+                                .long("trust-full-hash")
+                                .help("Groups files whose full hashes match without comparing their bytes. Faster, but a hash collision would group different files."))
+                        .arg(Arg::with_name("LowMemory")
+                                .long("low-memory")
+                                .conflicts_with_all(&["SeparateRoots", "Tar", "Watch"])
+                                .help("Spills the files found to temporary files once there are too many to hold in memory, hashing them a partition at a time. Slower, but bounds the memory taken before hashing on scans of tens of millions of files. The results are still held in memory."))
+                        .arg(Arg::with_name("SkipSparse")
+                                .long("skip-sparse")
+                                .help("Skips sparse files, whose unallocated ranges would be hashed as zeros. Only detected on Unix."))
+                        .arg(Arg::with_name("SkipLocked")
+                                .long("skip-locked")
+                                .help("Skips files locked by another process, or on Windows open for writing by one, listing them separately. Only detected on Linux and Windows."))
+                        .arg(Arg::with_name("Color")
+                                .long("color")
+                                .help("Colors the duplicates listing, with the copy a --keep policy would retain in green and the rest in red. Disabled when output is not a terminal or NO_COLOR is set."))
+                        .arg(Arg::with_name("Delete")
+                                .long("delete")
+                                .requires("Keep")
+                                .conflicts_with_all(&["Hardlink", "ReflinkDedupe", "Tui"])
+                                .help("Deletes every copy in each duplicate group except the one chosen by a --keep policy."))
+                        .arg(Arg::with_name("PruneEmptyAfterDelete")
+                                .long("prune-empty-after-delete")
+                                .requires("Delete")
+                                .conflicts_with("EmitScript")
+                                .help("Removes directories left empty by --delete, working up from the deepest, and lists each. Search directories themselves are never removed."))
+                        .arg(Arg::with_name("Hardlink")
+                                .long("hardlink")
+                                .requires("Keep")
+                                .conflicts_with_all(&["ReflinkDedupe", "Tui"])
+                                .help("Replaces every copy in each duplicate group with a hard link to the one chosen by a --keep policy."))
+                        .arg(Arg::with_name("ReflinkDedupe")
+                                .long("reflink-dedupe")
+                                .conflicts_with("Tui")
+                                .hidden(cfg!(not(target_os = "linux")))
+                                .help("Makes every copy in each duplicate group share storage with one copy, leaving each path an independent file. Needs a filesystem with reflinks, such as Btrfs or XFS."))
+                        .arg(Arg::with_name("DryRun")
+                                .long("dry-run")
+                                .help("Prints what --delete, --hardlink or --reflink-dedupe would do without changing any files."))
+                        .arg(Arg::with_name("DedupeThresholdBytes")
+                                .long("dedupe-threshold-bytes")
+                                .value_name("Bytes")
+                                .takes_value(true)
+                                .max_values(1)
+                                .validator(|x| x.parse::<u64>().map(|_| ()).map_err(|e| e.to_string()))
+                                .help("Only deletes or links duplicates of at least this many bytes. Smaller duplicates are still reported, unlike with --min-size."))
+                        .arg(Arg::with_name("EmitScript")
+                                .long("emit-script")
+                                .value_name("File")
+                                .takes_value(true)
+                                .max_values(1)
+                                .conflicts_with_all(&["ReflinkDedupe", "DryRun"])
+                                .help("Writes the commands --delete or --hardlink would run to a script for review instead of changing any files."))
+                        .arg(Arg::with_name("ScriptShell")
+                                .long("script-shell")
+                                .takes_value(true)
+                                .max_values(1)
+                                .possible_values(&["bash", "powershell"])
+                                .requires("EmitScript")
+                                .help("Sets the shell --emit-script writes for. Default is powershell for .ps1 files and bash otherwise."))
+                        .arg(Arg::with_name("Paranoid")
+                                .long("paranoid")
+                                .help("Compares every copy byte for byte with the retained copy before deleting or linking it, and skips any that differ."))
+                        .arg(Arg::with_name("KeepNewest")
+                                .long("keep-newest")
+                                .help("Keeps the most recently modified copy in each duplicate group."))
+                        .arg(Arg::with_name("KeepOldest")
+                                .long("keep-oldest")
+                                .help("Keeps the least recently modified copy in each duplicate group."))
+                        .arg(Arg::with_name("KeepShallowest")
+                                .long("keep-shallowest")
+                                .help("Keeps the copy with the shortest path in each duplicate group."))
+                        .arg(Arg::with_name("ExcludeOlderBackups")
+                                .long("exclude-older-backups")
+                                .help("Keeps the copy under the most recently dated directory, such as backup-2021-01-01, in each duplicate group. Falls back to the most recently modified copy where no date is found."))
+                        .arg(Arg::with_name("KeepUnder")
+                                .long("keep-under")
+                                .value_name("Directory")
+                                .takes_value(true)
+                                .max_values(1)
+                                .help("Keeps a copy under this directory when a duplicate group has one. Other --keep policies choose among those copies."))
+                        .group(ArgGroup::with_name("Retention")
+                                .args(&["KeepNewest", "KeepOldest", "KeepShallowest", "ExcludeOlderBackups"]))
+                        .group(ArgGroup::with_name("Keep")
+                                .args(&["KeepNewest", "KeepOldest", "KeepShallowest", "ExcludeOlderBackups", "KeepUnder"])
+                                .multiple(true))
+                        .arg(Arg::with_name("XattrCache")
+                                .long("xattr-cache")
+                                .help("Stores hashes in each file's extended attributes and reuses them while the file is unchanged. Ignored on filesystems without extended attributes."))
+                        .arg(Arg::with_name("Tui")
+                                .long("tui")
+                                .hidden(cfg!(not(feature = "tui")))
+                                .help("Browses duplicate groups interactively and marks copies to delete or hard link."));
+    let command_line: Vec<OsString> = std::env::args_os().collect();
+    let (arguments, config_roots) = match config_file(&command_line) {
+        Some(config_file) => with_config(app, &config_file, &command_line),
+        None => (app.get_matches_from(command_line), Vec::new()),
+    };
 
-    //let (sender, receiver) = channel();
-    let search_dirs: Vec<_> = arguments.values_of("directories").unwrap()
-    .collect();
+    if arguments.is_present("Append") && writes_json_file(&arguments){
+        eprintln!("--append cannot be used with JSON output, which is written as a single document.");
+        std::process::exit(1);
+    }
 
-    let (complete_files, read_errors): (Vec<Fileinfo>, Vec<(_, _)>) = ddh::deduplicate_dirs(search_dirs);
-    let (shared_files, unique_files): (Vec<&Fileinfo>, Vec<&Fileinfo>) = complete_files.par_iter().partition(|&x| x.get_paths().len()>1);
-    process_full_output(&shared_files, &unique_files, &complete_files, &read_errors, &arguments);
-}
+    let hash_algorithm = match arguments.value_of("HashAlgorithm").unwrap_or(""){
+        "blake3" => HashAlgorithm::Blake3,
+        _ => HashAlgorithm::Sip128};
+    if arguments.is_present("SelfTest"){
+        run_self_test(hash_algorithm);
+    }
 
-fn process_full_output(shared_files: &Vec<&Fileinfo>, unique_files: &Vec<&Fileinfo>, complete_files: &Vec<Fileinfo>, error_paths: &Vec<(PathBuf, std::io::Error)>, arguments: &clap::ArgMatches) ->(){
-    let blocksize = match arguments.value_of("Blocksize").unwrap_or(""){"B" => "Bytes", "K" => "Kilobytes", "M" => "Megabytes", "G" => "Gigabytes", _ => "Megabytes"};
-    let display_power = match blocksize{"Bytes" => 0, "Kilobytes" => 1, "Megabytes" => 2, "Gigabytes" => 3, _ => 2};
-    let display_divisor =  1024u64.pow(display_power);
-    let fmt = match arguments.value_of("Format").unwrap_or(""){
-        "standard" => PrintFmt::Standard,
-        "json" => PrintFmt::Json,
-        _ => PrintFmt::Standard};
-    let verbosity = match arguments.value_of("Verbosity").unwrap_or(""){
-        "quiet" => Verbosity::Quiet,
-        "duplicates" => Verbosity::Duplicates,
-        "all" => Verbosity::All,
-        _ => Verbosity::Quiet};
-
-    println!("{} Total files (with duplicates): {} {}", complete_files.par_iter()
-    .map(|x| x.get_paths().len() as u64)
-    .sum::<u64>(),
-    complete_files.par_iter()
-    .map(|x| (x.get_paths().len() as u64)*x.get_length())
-    .sum::<u64>()/(display_divisor),
-    blocksize);
-    println!("{} Total files (without duplicates): {} {}", complete_files.len(), complete_files.par_iter()
-    .map(|x| x.get_length())
-    .sum::<u64>()/(display_divisor),
-    blocksize);
-    println!("{} Single instance files: {} {}",unique_files.len(), unique_files.par_iter()
-    .map(|x| x.get_length())
-    .sum::<u64>()/(display_divisor),
-    blocksize);
-    println!("{} Shared instance files: {} {} ({} instances)", shared_files.len(), shared_files.par_iter()
-    .map(|x| x.get_length())
-    .sum::<u64>()/(display_divisor),
-    blocksize, shared_files.par_iter()
-    .map(|x| x.get_paths().len() as u64)
-    .sum::<u64>());
-
-    match (fmt, verbosity) {
-        (_, Verbosity::Quiet) => {},
-        (PrintFmt::Standard, Verbosity::Duplicates) => {
-            println!("Shared instance files and instance locations"); shared_files.iter().for_each(|x| {
-            println!("instances of {} with file length {}:", x.get_candidate_name(), x.get_length());
-            x.get_paths().par_iter().for_each(|y| println!("\t{}", y.canonicalize().unwrap().to_str().unwrap()));})
+    //let (sender, receiver) = channel();
+    let listed_dirs: Vec<String> = match arguments.value_of("DirsFrom0") {
+        Some(list_file) => match ddh::utils::read_nul_separated_file(list_file) {
+            Ok(dirs) => dirs,
+            Err(e) => {
+                eprintln!("Error reading directory list {}. Err: {}", list_file, e);
+                std::process::exit(1);
+            },
         },
-        (PrintFmt::Standard, Verbosity::All) => {
-            println!("Single instance files"); unique_files.par_iter()
-            .for_each(|x| println!("{}", x.get_paths().iter().next().unwrap().canonicalize().unwrap().to_str().unwrap()));
-            println!("Shared instance files and instance locations"); shared_files.iter().for_each(|x| {
-            println!("instances of {} with file length {}:", x.get_candidate_name(), x.get_length());
-            x.get_paths().par_iter().for_each(|y| println!("\t{}", y.canonicalize().unwrap().to_str().unwrap()));});
-            error_paths.iter().for_each(|x|{
-                println!("Could not process {:#?} due to error {:#?}", x.0, x.1.kind());
+        None => Vec::new(),
+    };
+    let mut search_dirs: Vec<&str> = arguments.values_of("directories")
+        .map(|x| x.collect())
+        .unwrap_or_default();
+    search_dirs.extend(listed_dirs.iter().map(|x| x.as_str()));
+    if search_dirs.is_empty() && arguments.values_of("Root").is_none(){
+        search_dirs.extend(config_roots.iter().map(|x| x.as_str()));
+    }
+    if search_dirs.is_empty() && arguments.values_of("Root").is_none(){
+        eprintln!("No directories to search.");
+        std::process::exit(1);
+    }
+    let mut root_policies = vec![HashPolicy::Full; search_dirs.len()];
+    for (dir, policy) in arguments.values_of("Root").into_iter().flatten().map(|x| parse_root(x).unwrap()){
+        search_dirs.push(dir);
+        root_policies.push(policy);
+    }
+    if let Some(compare_with) = arguments.value_of("CompareWith"){
+        if search_dirs.iter().any(|x| same_directory(x, compare_with)){
+            eprintln!("{} is already a searched directory.", compare_with);
+            std::process::exit(1);
+        }
+        search_dirs.push(compare_with);
+        root_policies.push(HashPolicy::Full);
+    }
+    if arguments.is_present("EmitScript") && !arguments.is_present("Delete") && !arguments.is_present("Hardlink"){
+        eprintln!("--emit-script writes the commands of --delete or --hardlink. Give one of them.");
+        std::process::exit(1);
+    }
+    if arguments.value_of("CompareMode") == Some("size-then-name"){
+        let content_flags = [("Delete", "--delete"), ("Hardlink", "--hardlink"), ("ReflinkDedupe", "--reflink-dedupe"), ("Tui", "--tui"), ("GroupsJsonLines", "--print-groups-json-lines"),
+            ("DirsEqual", "--dirs-equal"), ("CompareWith", "--compare-with"), ("SeparateRoots", "--separate-roots"), ("Estimate", "--estimate"), ("Tar", "--tar"), ("Watch", "--watch"),
+            ("ContentOnly", "--compare-content-only"), ("NormalizeText", "--normalize-text"), ("DetectCompressed", "--detect-compressed"), ("ExportManifest", "--export-manifest"), ("CompareManifest", "--compare-manifest"), ("SinceManifest", "--since-manifest")];
+        if let Some((_, flag)) = content_flags.iter().find(|x| arguments.is_present(x.0)){
+            eprintln!("--compare-mode size-then-name does not read file contents, so it cannot be combined with {}.", flag);
+            std::process::exit(1);
+        }
+        if arguments.value_of("JsonShape") == Some("map"){
+            eprintln!("--json-shape map keys groups by hash, which --compare-mode size-then-name does not compute.");
+            std::process::exit(1);
+        }
+    }
+    if arguments.is_present("DedupeThresholdBytes") && !arguments.is_present("Delete") && !arguments.is_present("Hardlink") && !arguments.is_present("ReflinkDedupe"){
+        eprintln!("--dedupe-threshold-bytes limits what --delete, --hardlink or --reflink-dedupe act on. Give one of them.");
+        std::process::exit(1);
+    }
+    if arguments.is_present("DirsEqual") && (search_dirs.len() != 2 || arguments.is_present("Root")){
+        eprintln!("--dirs-equal compares exactly two directories.");
+        std::process::exit(1);
+    }
+    if let Some(only_under) = arguments.value_of("SingletonsUnder"){
+        if !search_dirs.iter().any(|x| same_directory(x, only_under)){
+            eprintln!("{} is not one of the searched directories.", only_under);
+            std::process::exit(1);
+        }
+    }
+
+    let mut exclude_patterns: Vec<String> = arguments.values_of("Exclude")
+        .map(|x| x.map(|y| y.to_string()).collect())
+        .unwrap_or_default();
+    if let Some(exclude_file) = arguments.value_of("ExcludeFrom"){
+        match ddh::utils::read_pattern_file(exclude_file) {
+            Ok(mut patterns) => exclude_patterns.append(&mut patterns),
+            Err(e) => {
+                eprintln!("Error reading exclude file {}. Err: {}", exclude_file, e);
+                std::process::exit(1);
+            },
+        }
+    }
+    let excludes: Vec<glob::Pattern> = exclude_patterns.iter()
+        .map(|x| glob::Pattern::new(x).unwrap_or_else(|e| {
+            eprintln!("Invalid exclude pattern {}. Err: {}", x, e);
+            std::process::exit(1);
+        }))
+        .collect();
+
+    let path_filters = |name: &str| -> Vec<regex::Regex> {
+        arguments.values_of(name).into_iter().flatten()
+            .map(|x| regex::Regex::new(x).unwrap_or_else(|e| {
+                eprintln!("Invalid path filter {}. Err: {}", x, e);
+                std::process::exit(1);
+            }))
+            .collect()
+    };
+    let (path_filters_in, path_filters_out) = (path_filters("PathFilter"), path_filters("PathFilterOut"));
+
+    let extension_patterns = |name: &str| -> Vec<String> {
+        arguments.values_of(name)
+            .map(|x| x.map(|y| y.trim_start_matches('.').to_lowercase()).collect())
+            .unwrap_or_default()
+    };
+    let (include_exts, exclude_exts) = (extension_patterns("IncludeExt"), extension_patterns("ExcludeExt"));
+    if let Some(both) = include_exts.iter().find(|x| exclude_exts.contains(x)){
+        eprintln!("Extension {} is given to both --include-ext and --exclude-ext.", both);
+        std::process::exit(1);
+    }
+    let compile_extensions = |patterns: &[String]| -> Vec<glob::Pattern> {
+        patterns.iter()
+            .map(|x| glob::Pattern::new(x).unwrap_or_else(|e| {
+                eprintln!("Invalid extension pattern {}. Err: {}", x, e);
+                std::process::exit(1);
+            }))
+            .collect()
+    };
+
+    let sample_strategy = match arguments.value_of("SampleStrategy").unwrap_or(""){
+        "spread" => SampleStrategy::Spread,
+        _ => SampleStrategy::Prefix};
+    let stop = Arc::new(AtomicBool::new(false));
+    let cache = arguments.value_of("Resume").map(|resume_file| {
+        let cache = if Path::new(resume_file).exists() {
+            HashCache::load(resume_file, sample_strategy).unwrap_or_else(|e| {
+                eprintln!("Error reading resume file {}. Err: {}", resume_file, e);
+                std::process::exit(1);
             })
-        },
-        (PrintFmt::Json, Verbosity::Duplicates) => {
-            println!("{}", serde_json::to_string(shared_files).unwrap_or("".to_string()));
-        },
-        (PrintFmt::Json, Verbosity::All) => {
-            println!("{}", serde_json::to_string(complete_files).unwrap_or("".to_string()));
-        },
-        _ => {},
-    }
-
-    match arguments.value_of("Output").unwrap_or("Results.txt"){
-        "no" => {},
-        destination_string => {
-            match fs::File::open(destination_string) {
-                    Ok(_f) => {
-                    println!("---");
-                    println!("File {} already exists.", destination_string);
-                    println!("Overwrite? Y/N");
-                    let mut input = String::new();
-                    match stdin().read_line(&mut input) {
-                        Ok(_n) => {
-                            match input.chars().next().unwrap_or(' ') {
-                                'n' | 'N' => {println!("Exiting."); return;}
-                                'y' | 'Y' => {println!("Over writing {}", destination_string);}
-                                _ => {println!("Exiting."); return;}
-                            }
-                        }
-                        Err(_e) => {println!("Error encountered reading user input. Err: {}", _e);},
-                    }
-                },
-                Err(_e) => {
-                    match fs::File::create(destination_string) {
-                        Ok(_f) => {},
-                        Err(_e) => {
-                            println!("Error encountered opening file {}. Err: {}", destination_string, _e);
-                            println!("Exiting."); return;
-                        }
+        } else {
+            HashCache::new(sample_strategy)
+        };
+        Arc::new(cache)
+    });
+    if arguments.is_present("Resume") || arguments.is_present("Watch"){
+        let handler_stop = stop.clone();
+        ctrlc::set_handler(move || {
+            if handler_stop.swap(true, Ordering::Relaxed){
+                std::process::exit(130);
+            }
+            eprintln!("\nInterrupted. Finishing in-flight reads, press Ctrl-C again to quit immediately.");
+        }).expect("Error setting Ctrl-C handler");
+    }
+
+    let manifest = arguments.value_of("CompareManifest").map(|manifest_file| {
+        Manifest::load(manifest_file).unwrap_or_else(|e| {
+            eprintln!("Error reading manifest {}. Err: {}", manifest_file, e);
+            std::process::exit(1);
+        })
+    });
+
+    let since_manifest = arguments.value_of("SinceManifest").map(|manifest_file| {
+        Arc::new(Manifest::load(manifest_file).unwrap_or_else(|e| {
+            eprintln!("Error reading manifest {}. Err: {}", manifest_file, e);
+            std::process::exit(1);
+        }))
+    });
+
+    let tree_cache = arguments.value_of("TreeCache").map(|cache_file| {
+        let cache = if Path::new(cache_file).exists() {
+            TreeCache::load(cache_file).unwrap_or_else(|e| {
+                eprintln!("Error reading tree cache {}. Err: {}", cache_file, e);
+                std::process::exit(1);
+            })
+        } else {
+            TreeCache::new()
+        };
+        Arc::new(cache)
+    });
+
+    let empty_files = match arguments.value_of("EmptyFiles").unwrap_or(""){
+        "group" => EmptyFilePolicy::Group,
+        "skip" => EmptyFilePolicy::Skip,
+        _ => EmptyFilePolicy::Unique};
+    if let (Some(manifest_file), Some(manifest)) = (arguments.value_of("CompareManifest"), &manifest){
+        if manifest.hash_algorithm() != hash_algorithm{
+            eprintln!("Manifest {} was hashed with {:?}. Run again with a matching --hash-algorithm.", manifest_file, manifest.hash_algorithm());
+            std::process::exit(1);
+        }
+    }
+    if let (Some(manifest_file), Some(manifest)) = (arguments.value_of("SinceManifest"), &since_manifest){
+        if manifest.hash_algorithm() != hash_algorithm{
+            eprintln!("Manifest {} was hashed with {:?}. Run again with a matching --hash-algorithm.", manifest_file, manifest.hash_algorithm());
+            std::process::exit(1);
+        }
+    }
+
+    if arguments.is_present("ParallelHash") && hash_algorithm != HashAlgorithm::Blake3{
+        eprintln!("--parallel-hash requires --hash-algorithm blake3, as SipHash cannot be split across threads.");
+        std::process::exit(1);
+    }
+
+    let progress = if arguments.is_present("Progress") {Some(Arc::new(Progress::new()))} else {None};
+    let profile = if arguments.is_present("Profile") {Some(Arc::new(Profile::new()))} else {None};
+    let stats = if arguments.is_present("Stats") {Some(Arc::new(ScanStats::new()))} else {None};
+    let started = Instant::now();
+    let mut builder = ScanOptions::builder()
+        .content_only(arguments.is_present("ContentOnly") || arguments.is_present("ExportManifest") || manifest.is_some() || since_manifest.is_some())
+        .sample_strategy(sample_strategy)
+        .excludes(excludes)
+        .path_filters(path_filters_in)
+        .path_filters_out(path_filters_out)
+        .include_extensions(compile_extensions(&include_exts))
+        .exclude_extensions(compile_extensions(&exclude_exts))
+        .require_name_match(arguments.is_present("RequireNameMatch"))
+        .follow_symlinks(arguments.is_present("FollowSymlinks"))
+        .symlink_targets_once(arguments.is_present("HashSymlinkTargetsOnce"))
+        .one_file_system(arguments.is_present("OneFileSystem"))
+        .follow_mount_points(arguments.is_present("FollowMountPoints"))
+        .stop(stop.clone())
+        .root_policies(root_policies)
+        .skip_sparse(arguments.is_present("SkipSparse"))
+        .skip_locked(arguments.is_present("SkipLocked"))
+        .xattr_cache(arguments.is_present("XattrCache"))
+        .hash_algorithm(hash_algorithm)
+        .empty_files(empty_files)
+        .deterministic(arguments.is_present("Deterministic"))
+        .hash_only_full(arguments.is_present("HashOnlyFull"))
+        .trust_full_hash(arguments.is_present("TrustFullHash"))
+        .parallel_hash(arguments.is_present("ParallelHash"));
+    if let Some(progress) = &progress{
+        builder = builder.progress(progress.clone());
+    }
+    if let Some(cache) = &cache{
+        builder = builder.cache(cache.clone());
+    }
+    if let Some(profile) = &profile{
+        builder = builder.profile(profile.clone());
+    }
+    if let Some(stats) = &stats{
+        builder = builder.stats(stats.clone());
+    }
+    if arguments.is_present("LowMemory"){
+        builder = builder.low_memory(ddh::LOW_MEMORY_FILES);
+    }
+    if let Some(manifest) = &since_manifest{
+        builder = builder.since_manifest(manifest.clone());
+    }
+    if let Some(tree_cache) = &tree_cache{
+        builder = builder.tree_cache(tree_cache.clone());
+    }
+    let budget = arguments.value_of("MaxTotalSize").map(|x| Arc::new(ScanBudget::new(x.parse().unwrap())));
+    if let Some(budget) = &budget{
+        builder = builder.budget(budget.clone());
+    }
+    if let Some(rate) = arguments.value_of("Throttle"){
+        builder = builder.throttle(Arc::new(Throttle::new((rate.parse::<f64>().unwrap()*1024.0*1024.0) as u64)));
+    }
+    if let Some(range) = arguments.value_of("HashRange"){
+        builder = builder.hash_range(HashRange::parse(range).unwrap());
+    }
+    if let Some(header) = arguments.value_of("SkipHeader"){
+        builder = builder.hash_range(HashRange::after(header.parse().unwrap()));
+    }
+    if let Some(retries) = arguments.value_of("ReadRetries"){
+        builder = builder.read_retries(retries.parse().unwrap());
+    }
+    if let Some(threads) = arguments.value_of("Threads"){
+        builder = builder.threads(threads.parse().unwrap());
+    }
+    if let Some(min_size) = arguments.value_of("MinSize"){
+        builder = builder.min_size(min_size.parse().unwrap());
+    }
+    if let Some(max_size) = arguments.value_of("MaxSize"){
+        builder = builder.max_size(max_size.parse().unwrap());
+    }
+    let options = builder.build();
+    if arguments.is_present("Watch"){
+        run_watch(&search_dirs, &options, &stop, &arguments);
+        return
+    }
+    if arguments.is_present("Estimate"){
+        let (estimate, read_errors) = ddh::estimate_dirs_with(search_dirs, &options);
+        let output_started = Instant::now();
+        print_estimate(&estimate, &read_errors, &arguments);
+        if let Some(stats) = &stats{
+            print_scan_stats(stats, &arguments);
+        }
+        if let Some(profile) = &profile{
+            profile.record(Stage::Output, output_started.elapsed());
+            print_profile(profile, started.elapsed());
+        }
+        return
+    }
+    let done = AtomicBool::new(false);
+    let separate_roots = arguments.is_present("SeparateRoots");
+    let results: Vec<DirResults> = thread::scope(|scope| {
+        if let Some(p) = &progress{
+            let done = &done;
+            scope.spawn(move || render_progress(p, done));
+        }
+        let results = if arguments.is_present("Tar") {
+            vec![ddh::archive::deduplicate_archives(search_dirs.clone(), &options)]
+        } else if arguments.value_of("CompareMode") == Some("size-then-name") {
+            vec![ddh::group_by_size_and_name_with(search_dirs.clone(), &options)]
+        } else if separate_roots {
+            ddh::deduplicate_each_dir_with(search_dirs.clone(), &options)
+        } else {
+            vec![ddh::deduplicate_dirs_with(search_dirs.clone(), &options)]
+        };
+        done.store(true, Ordering::Relaxed);
+        results
+    });
+    // Results of each root end at these offsets into the pooled files and errors
+    let mut root_ends: Vec<(usize, usize)> = Vec::new();
+    let mut complete_files: Vec<Fileinfo> = Vec::new();
+    let mut read_errors: Vec<(PathBuf, std::io::Error)> = Vec::new();
+    for (files, errors) in results.into_iter(){
+        complete_files.extend(files);
+        read_errors.extend(errors);
+        root_ends.push((complete_files.len(), read_errors.len()));
+    }
+    if let (Some(resume_file), Some(cache)) = (arguments.value_of("Resume"), &cache){
+        if stop.load(Ordering::Relaxed){
+            match cache.save(resume_file) {
+                Ok(()) => eprintln!("Saved {} hashed files to {}. Run again with --resume {} to continue.", cache.len(), resume_file, resume_file),
+                Err(e) => eprintln!("Error encountered saving resume file {}. Err: {}", resume_file, e),
+            }
+            std::process::exit(130);
+        }
+        let _ = fs::remove_file(resume_file);
+    }
+    if let (Some(cache_file), Some(tree_cache)) = (arguments.value_of("TreeCache"), &tree_cache){
+        if !stop.load(Ordering::Relaxed){
+            eprintln!("Reused {} unchanged directories from tree cache {}, read {} new or changed directories", tree_cache.reused_dirs(), cache_file, tree_cache.read_dirs());
+            if let Err(e) = tree_cache.save(cache_file){
+                eprintln!("Error encountered saving tree cache {}. Err: {}", cache_file, e);
+            }
+        }
+    }
+    if let (Some(manifest_file), Some(manifest)) = (arguments.value_of("SinceManifest"), &since_manifest){
+        eprintln!("Reused {} hashes from manifest {}, hashed {} new or changed files", manifest.reused_files(), manifest_file, manifest.rehashed_files());
+    }
+    if let Some(min_files) = arguments.value_of("MinFiles").map(|x| x.parse::<u64>().unwrap()){
+        let found = complete_files.iter().map(|x| x.get_paths().len() as u64).sum::<u64>();
+        if found < min_files{
+            eprintln!("Found {} files, fewer than --min-files {}. Check the search directories and exclude patterns.", found, min_files);
+            std::process::exit(1);
+        }
+    }
+    if arguments.is_present("DirsEqual"){
+        let differences = compare_trees(&complete_files, Path::new(search_dirs[0]), Path::new(search_dirs[1]));
+        print_tree_differences(&differences, &read_errors, &search_dirs, &arguments);
+        std::process::exit(if differences.is_empty() && read_errors.is_empty() {0} else {1});
+    }
+    if arguments.is_present("GroupsJsonLines"){
+        let mut shared_files: Vec<&Fileinfo> = complete_files.iter().filter(|x| x.get_paths().len()>1).collect();
+        shared_files.sort_by(|a, b| b.get_length().cmp(&a.get_length()).then_with(|| a.get_paths().cmp(b.get_paths())));
+        print_action_plan(&shared_files, &retention_policy(&arguments));
+        return
+    }
+    if let Some(compare_with) = arguments.value_of("CompareWith"){
+        print_present_files(&files_present_in(&complete_files, search_dirs.len() - 1), compare_with, &arguments);
+        return
+    }
+    let shared_files: Vec<&Fileinfo> = complete_files.par_iter().filter(|x| x.get_paths().len()>1).collect();
+    if arguments.is_present("Tui"){
+        run_tui(&shared_files, arguments.is_present("Paranoid"));
+        return
+    }
+    let output_started = Instant::now();
+    let destination = arguments.value_of("Output").unwrap_or("Results.txt");
+    if separate_roots {
+        let mut starts = (0, 0);
+        for (root, &ends) in root_ends.iter().enumerate(){
+            let root_files = &complete_files[starts.0..ends.0];
+            print_note(print_format(&arguments), format_args!("=== {} ===", search_dirs[root]));
+            process_full_output(root_files, &read_errors[starts.1..ends.1], &search_dirs, &root_destination(destination, root), budget.as_deref(), &arguments);
+            starts = ends;
+        }
+    } else {
+        process_full_output(&complete_files, &read_errors, &search_dirs, destination, budget.as_deref(), &arguments);
+    }
+    if let Some(stats) = &stats{
+        print_scan_stats(stats, &arguments);
+    }
+    if let Some(count) = arguments.value_of("LargestDuplicates"){
+        print_largest_duplicates(&shared_files, count.parse().unwrap(), &arguments);
+    }
+    if arguments.is_present("BrokenSymlinks"){
+        print_broken_symlinks(&read_errors, &arguments);
+    }
+    if arguments.is_present("SkipLocked"){
+        print_locked_files(&read_errors, &arguments);
+    }
+    if arguments.is_present("HashSymlinkTargetsOnce"){
+        print_symlink_aliases(&complete_files, &arguments);
+    }
+    if arguments.is_present("DuplicateDirs"){
+        print_duplicate_directories(&find_duplicate_directories(&complete_files, &search_dirs), &arguments);
+    }
+    if let Some(percent) = arguments.value_of("DirOverlap"){
+        print_overlapping_directories(&find_overlapping_directories(&complete_files, &search_dirs, percent.parse::<f64>().unwrap()/100.0), &arguments);
+    }
+    if arguments.is_present("GroupByParent"){
+        print_redundant_directories(&find_redundant_directories(&complete_files), &arguments);
+    }
+    if arguments.is_present("NormalizeText"){
+        print_near_duplicate_text(&find_near_duplicate_text(&complete_files, arguments.is_present("TrimTrailingWhitespace")), &arguments);
+    }
+    if arguments.is_present("DetectCompressed"){
+        print_compressed_copies(&find_compressed_copies(&complete_files, &options), &arguments);
+    }
+    if let Some(distance) = arguments.value_of("FuzzyNames"){
+        print_similar_names(&find_similar_names(&complete_files, distance.parse().unwrap()), &arguments);
+    }
+    if let Some(manifest) = &manifest{
+        print_manifest_matches(&complete_files, manifest, &arguments);
+    }
+    if let Some(manifest_file) = arguments.value_of("ExportManifest"){
+        let mut exported = Manifest::new(hash_algorithm);
+        complete_files.iter().for_each(|x| exported.insert(x));
+        match exported.save(manifest_file) {
+            Ok(()) => eprintln!("Wrote {} distinct files to manifest {}", exported.len(), manifest_file),
+            Err(e) => eprintln!("Error encountered writing manifest {}. Err: {}", manifest_file, e),
+        }
+    }
+    if let Some(profile) = &profile{
+        profile.record(Stage::Output, output_started.elapsed());
+        print_profile(profile, started.elapsed());
+    }
+    let action = match (arguments.is_present("Delete"), arguments.is_present("Hardlink"), arguments.is_present("ReflinkDedupe")) {
+        (true, _, _) => Action::Delete,
+        (_, true, _) => Action::Hardlink,
+        (_, _, true) => Action::Reflink,
+        _ => return,
+    };
+    let mut script = arguments.value_of("EmitScript").map(|x| Script::new(match arguments.value_of("ScriptShell") {
+        Some("bash") => Shell::Bash,
+        Some("powershell") => Shell::PowerShell,
+        _ => Shell::from_path(Path::new(x)),
+    }));
+    let threshold = arguments.value_of("DedupeThresholdBytes").map_or(0, |x| x.parse::<u64>().unwrap());
+    let (actionable, small): (Vec<&Fileinfo>, Vec<&Fileinfo>) = shared_files.iter().partition(|x| x.get_length() >= threshold);
+    if !small.is_empty(){
+        eprintln!("Leaving {} duplicate groups of files under {} bytes alone", small.len(), threshold);
+    }
+    let deleted = apply_retention(&actionable, &retention_policy(&arguments), action, arguments.is_present("DryRun"), arguments.is_present("Paranoid"), script.as_mut(), print_format(&arguments));
+    if arguments.is_present("PruneEmptyAfterDelete"){
+        prune_empty(&deleted, &search_dirs, arguments.is_present("DryRun"), print_format(&arguments));
+    }
+    if let (Some(script_file), Some(script)) = (arguments.value_of("EmitScript"), &script){
+        match fs::write(script_file, script.as_str()) {
+            Ok(()) => eprintln!("Wrote the commands to {}. Nothing was changed.", script_file),
+            Err(e) => {
+                eprintln!("Error encountered writing script {}. Err: {}", script_file, e);
+                std::process::exit(1);
+            },
+        }
+    }
+}
+
+// Finds the --config file on the command line before it is parsed, as the command line may lack options the config supplies
+fn config_file(command_line: &[OsString]) -> Option<String>{
+    let options = command_line.iter().skip(1).take_while(|x| *x != "--").map(|x| x.to_string_lossy());
+    let mut previous = None;
+    for option in options{
+        if previous.as_deref() == Some("--config") {
+            return Some(option.into_owned())
+        }
+        if let Some(config_file) = option.strip_prefix("--config=") {
+            return Some(config_file.to_string())
+        }
+        previous = Some(option);
+    }
+    None
+}
+
+// Parses the command line after the settings of a config file, leaving out any setting the command line already gives or that conflicts with
+// an option on it, so options given on the command line take the place of the config's. Lists such as exclude add to those in the config instead.
+// The config's roots are returned for when the command line names no directories
+fn with_config<'a, 'b>(app: App<'a, 'b>, config_file: &str, command_line: &[OsString]) -> (ArgMatches<'a>, Vec<String>){
+    let config = Config::load(config_file).unwrap_or_else(|e| {
+        eprintln!("Error reading config {}. Err: {}", config_file, e);
+        std::process::exit(1);
+    });
+    let app = app.setting(AppSettings::AllArgsOverrideSelf);
+    let given = app.clone().get_matches_from_safe(command_line).unwrap_or_else(|e| e.exit());
+    let mut merged = command_line[..1].to_vec();
+    for option in config.options(){
+        let key = option.trim_start_matches('-').split('=').next().unwrap_or_default();
+        if given.is_present(config_arg_name(key)) && !Config::LISTS.contains(&key) {
+            continue
+        }
+        let mut with_option = command_line[..1].to_vec();
+        with_option.push(OsString::from(&option));
+        with_option.extend_from_slice(&command_line[1..]);
+        match app.clone().get_matches_from_safe(with_option) {
+            Err(e) if e.kind == ErrorKind::ArgumentConflict => {},
+            _ => merged.push(OsString::from(option)),
+        }
+    }
+    merged.extend_from_slice(&command_line[1..]);
+    (app.get_matches_from(merged), config.roots)
+}
+
+// Config keys are the long names of options, whose argument names are the same words capitalized
+fn config_arg_name(key: &str) -> String{
+    key.split('-').map(|word| {
+        let mut letters = word.chars();
+        letters.next().map(|x| x.to_ascii_uppercase().to_string() + letters.as_str()).unwrap_or_default()
+    }).collect()
+}
+
+fn retention_policy(arguments: &clap::ArgMatches) -> RetentionPolicy{
+    let retention = if arguments.is_present("KeepNewest") {
+        Some(Retention::Newest)
+    } else if arguments.is_present("KeepOldest") {
+        Some(Retention::Oldest)
+    } else if arguments.is_present("KeepShallowest") {
+        Some(Retention::Shallowest)
+    } else if arguments.is_present("ExcludeOlderBackups") {
+        Some(Retention::NewestBackup)
+    } else {
+        None
+    };
+    RetentionPolicy::new(retention, arguments.value_of("KeepUnder").map(PathBuf::from))
+}
+
+// Returns the paths deleted, or with `dry_run` those that would be
+fn apply_retention(shared_files: &[&Fileinfo], policy: &RetentionPolicy, action: Action, dry_run: bool, paranoid: bool, mut script: Option<&mut Script>, fmt: PrintFmt) -> Vec<PathBuf>{
+    let mut deleted = Vec::new();
+    let mut rejected = 0;
+    let mut reflinked: (u64, u64) = (0, 0);
+    for file in shared_files.iter(){
+        let (keep, targets) = match policy.choose(file) {
+            Some(keep) => (&file.get_paths()[keep], action_targets(file, keep)),
+            None => continue,
+        };
+        let mut targets: Vec<&PathBuf> = targets.into_iter().map(|x| &file.get_paths()[x]).collect();
+        targets.sort();
+        for target in targets.into_iter(){
+            if paranoid && !confirm_identical(keep, target){
+                rejected += 1;
+                continue
+            }
+            if dry_run {
+                print_note(fmt, format_args!("Would {:?} {}, keeping {}", action, target.display(), keep.display()));
+                if action == Action::Delete {
+                    deleted.push(target.clone());
+                }
+                continue
+            }
+            if let Some(script) = script.as_mut() {
+                // Scripts link to the file the kept path resolves to, as applying the action would, and name each copy by an absolute path so they can run from any directory
+                script.add(action, &fs::canonicalize(keep).unwrap_or_else(|_e| keep.to_path_buf()), &std::path::absolute(target).unwrap_or_else(|_e| target.to_path_buf()));
+                continue
+            }
+            match action.apply(keep, target) {
+                Ok(()) => {
+                    print_note(fmt, format_args!("{:?} {}, keeping {}", action, target.display(), keep.display()));
+                    match action {
+                        Action::Delete => deleted.push(target.clone()),
+                        Action::Reflink => reflinked = (reflinked.0 + 1, reflinked.1 + file.get_length()),
+                        Action::Hardlink => {},
                     }
                 },
+                Err(e) => eprintln!("Could not {:?} {}. Err: {}", action, target.display(), e),
             }
-            write_results_to_file(fmt, &shared_files, &unique_files, &complete_files, destination_string);
-        },
+        }
     }
+    if action == Action::Reflink && !dry_run && script.is_none() {
+        print_note(fmt, format_args!("Deduplicated {} bytes across {} copies", reflinked.1, reflinked.0));
+    }
+    if rejected > 0 {
+        eprintln!("{} copies matched by hash were left alone after byte comparison. They may have changed since the scan or collided on hash.", rejected);
+    }
+    deleted
 }
 
-fn write_results_to_file(fmt: PrintFmt, shared_files: &Vec<&Fileinfo>, unique_files: &Vec<&Fileinfo>, complete_files: &Vec<Fileinfo>, file: &str) {
-    let mut output = fs::File::create(file).expect("Error opening output file for writing");
-    match fmt {
-        PrintFmt::Standard => {
-            output.write_fmt(format_args!("Duplicates:\n")).unwrap();
-            for file in shared_files.into_iter(){
-                let title = file.get_paths().get(0).unwrap().file_name().unwrap().to_str().unwrap();
-                output.write_fmt(format_args!("{}\n", title)).unwrap();
-                for entry in file.get_paths().iter(){
-                    output.write_fmt(format_args!("\t{}\n", entry.as_path().to_str().unwrap())).unwrap();
-                }
+fn prune_empty(deleted: &[PathBuf], search_dirs: &[&str], dry_run: bool, fmt: PrintFmt){
+    for (dir, result) in prune_empty_dirs(deleted, search_dirs, dry_run).into_iter(){
+        match result {
+            Ok(()) if dry_run => print_note(fmt, format_args!("Would remove empty directory {}", dir.display())),
+            Ok(()) => print_note(fmt, format_args!("Removed empty directory {}", dir.display())),
+            Err(e) => eprintln!("Could not remove empty directory {}. Err: {}", dir.display(), e),
+        }
+    }
+}
+
+// Exits with the outcome, so a failing build is never trusted with a scan
+fn run_self_test(algorithm: HashAlgorithm){
+    let checks = ddh::selftest::run(algorithm).unwrap_or_else(|e| {
+        eprintln!("Error writing self-test files. Err: {}", e);
+        std::process::exit(1);
+    });
+    for check in checks.iter(){
+        match check.passed() {
+            true => println!("ok   {}: {}", check.name, check.found),
+            false => println!("FAIL {}: expected {}, found {}", check.name, check.expected, check.found),
+        }
+    }
+    let failed = checks.iter().filter(|x| !x.passed()).count();
+    if failed > 0 {
+        eprintln!("Self-test failed: {} of {} {:?} checks gave the wrong hash. Do not trust this build's results.", failed, checks.len(), algorithm);
+        std::process::exit(1);
+    }
+    println!("Self-test passed: {} {:?} checks", checks.len(), algorithm);
+    std::process::exit(0);
+}
+
+// Follows filesystem events under the search directories where the platform reports them, rescanning only the paths they name.
+// Otherwise, or when the events may have missed changes, every search directory is rescanned
+fn run_watch(search_dirs: &[&str], options: &ScanOptions, stop: &AtomicBool, arguments: &clap::ArgMatches){
+    let interval = Duration::from_secs(arguments.value_of("WatchInterval").map_or(2, |x| x.parse().unwrap()));
+    let action = match (arguments.is_present("Delete"), arguments.is_present("Hardlink")) {
+        (true, _) => Some(Action::Delete),
+        (_, true) => Some(Action::Hardlink),
+        _ => None,
+    };
+    let policy = retention_policy(arguments);
+    let threshold = arguments.value_of("DedupeThresholdBytes").map_or(0, |x| x.parse::<u64>().unwrap());
+    let (sender, events) = mpsc::channel();
+    // Watching starts before indexing, so files added while the index is built are not missed
+    let watcher = match arguments.is_present("WatchPoll") {
+        true => None,
+        false => watch_events(search_dirs, sender).map_err(|e| eprintln!("Could not follow filesystem events, so the search directories are rescanned instead. Err: {}", e)).ok(),
+    };
+    let (mut index, errors) = WatchIndex::new(search_dirs.to_vec(), options);
+    if stop.load(Ordering::Relaxed){
+        return
+    }
+    eprintln!("Watching {} files, {} paths could not be scanned. Press Ctrl-C to stop.", index.len(), errors.len());
+    let mut found = 0;
+    let mut changed = HashSet::new();
+    let mut rescan = false;
+    'watching: loop {
+        // Waits in short steps so Ctrl-C is answered promptly, gathering the paths events name meanwhile
+        let woken = Instant::now();
+        while woken.elapsed() < interval {
+            if stop.load(Ordering::Relaxed){
+                break 'watching
+            }
+            match events.recv_timeout(Duration::from_millis(100).min(interval)) {
+                Ok(Ok(event)) => {
+                    rescan |= event.need_rescan();
+                    // Reads, including those of hashing, raise access events of their own
+                    if !event.kind.is_access() {
+                        changed.extend(event.paths);
+                    }
+                },
+                Ok(Err(e)) => {
+                    eprintln!("Filesystem events may have been missed, so the search directories are rescanned. Err: {}", e);
+                    rescan = true;
+                },
+                Err(mpsc::RecvTimeoutError::Timeout) => {},
+                Err(mpsc::RecvTimeoutError::Disconnected) => thread::sleep(Duration::from_millis(100).min(interval)),
             }
-            output.write_fmt(format_args!("Singletons:\n")).unwrap();
-            for file in unique_files.into_iter(){
-                let title = file.get_paths().get(0).unwrap().file_name().unwrap().to_str().unwrap();
-                output.write_fmt(format_args!("{}\n", title)).unwrap();
-                for entry in file.get_paths().iter(){
-                    output.write_fmt(format_args!("\t{}\n", entry.as_path().to_str().unwrap())).unwrap();
+        }
+        let (duplicates, errors) = if watcher.is_none() || rescan {
+            index.poll(options)
+        } else if !changed.is_empty() || index.is_settling() {
+            index.poll_changed(&changed.iter().collect::<Vec<&PathBuf>>(), options)
+        } else {
+            continue
+        };
+        rescan = false;
+        changed.clear();
+        errors.iter().for_each(|(path, e)| eprintln!("Could not read {}. Err: {}", path.display(), e));
+        for duplicate in duplicates.iter(){
+            found += 1;
+            print_new_duplicate(duplicate, arguments);
+            if let Some(action) = action.filter(|_| duplicate.length >= threshold) {
+                let deleted = apply_retention(&[&duplicate.group()], &policy, action, arguments.is_present("DryRun"), arguments.is_present("Paranoid"), None, print_format(arguments));
+                if arguments.is_present("PruneEmptyAfterDelete"){
+                    prune_empty(&deleted, search_dirs, arguments.is_present("DryRun"), print_format(arguments));
                 }
             }
+        }
+    }
+    eprintln!("Stopped watching after finding {} new duplicates.", found);
+}
+
+fn watch_events(search_dirs: &[&str], sender: mpsc::Sender<notify::Result<notify::Event>>) -> notify::Result<notify::RecommendedWatcher>{
+    let mut watcher = notify::recommended_watcher(sender)?;
+    for dir in search_dirs.iter(){
+        watcher.watch(Path::new(dir), notify::RecursiveMode::Recursive)?;
+    }
+    Ok(watcher)
+}
+
+fn confirm_identical(keep: &Path, target: &Path) -> bool{
+    match ddh::actions::same_contents(keep, target) {
+        Ok(true) => true,
+        Ok(false) => {
+            eprintln!("Hash matched but contents differ: {} and {}. Skipping.", keep.display(), target.display());
+            false
         },
-        PrintFmt::Json => {
-            output.write_fmt(format_args!("{}", serde_json::to_string(complete_files).unwrap_or("Error deserializing".to_string()))).unwrap();
+        Err(e) => {
+            eprintln!("Could not compare {} with {}. Skipping. Err: {}", target.display(), keep.display(), e);
+            false
         },
-        PrintFmt::Off =>{return},
     }
-    println!("{:#?} results written to {}", fmt, file);
+}
+
+fn parse_root(root: &str) -> Result<(&str, HashPolicy), String>{
+    let (dir, policy) = root.rsplit_once(':')
+        .ok_or_else(|| format!("{} should be given as Directory:Policy", root))?;
+    match policy {
+        "full" => Ok((dir, HashPolicy::Full)),
+        "partial" => Ok((dir, HashPolicy::Partial)),
+        _ => Err(format!("Unknown hash policy {}. Use 'full' or 'partial'", policy)),
+    }
+}
+
+#[cfg(feature = "tui")]
+fn run_tui(shared_files: &[&Fileinfo], paranoid: bool){
+    tui::browse(shared_files, paranoid).unwrap_or_else(|e| {
+        eprintln!("Error encountered in interactive mode. Err: {}", e);
+        std::process::exit(1);
+    });
+}
+
+#[cfg(not(feature = "tui"))]
+fn run_tui(_shared_files: &[&Fileinfo], _paranoid: bool){
+    eprintln!("This build of ddh does not include the interactive browser. Rebuild with --features tui.");
+    std::process::exit(1);
+}
+
+fn render_progress(progress: &Progress, done: &AtomicBool){
+    let mut rendered = false;
+    let mut width = 0;
+    while !done.load(Ordering::Relaxed){
+        let total = progress.total_bytes();
+        if total > 0 {
+            let hashed = progress.hashed_bytes().min(total);
+            let eta = match progress.eta() {
+                Some(eta) => {
+                    let secs = eta.as_secs();
+                    format!("{:02}:{:02}:{:02}", secs/3600, (secs/60)%60, secs%60)
+                },
+                None => "--:--:--".to_string(),
+            };
+            // Of the large files being hashed, the one with the most left to read is shown, as it holds up the stage longest
+            let large_file = progress.large_files().into_iter()
+                .max_by_key(|x| x.length().saturating_sub(x.hashed_bytes()))
+                .map(|x| format!(", {}: {} of {} Megabytes",
+                    x.path().file_name().unwrap_or(x.path().as_os_str()).to_string_lossy(),
+                    x.hashed_bytes()/(1024*1024),
+                    x.length()/(1024*1024)))
+                .unwrap_or_default();
+            let line = format!("Full hashing {} of {} Megabytes ({}%), ETA {}{}",
+                hashed/(1024*1024),
+                total/(1024*1024),
+                hashed*100/total,
+                eta,
+                large_file);
+            // Pads over whatever remains of a longer previous line
+            eprint!("\r{:<width$}", line, width = width);
+            width = line.len();
+            rendered = true;
+        }
+        thread::sleep(Duration::from_millis(250));
+    }
+    if rendered {
+        eprintln!();
+    }
 }
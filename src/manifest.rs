@@ -0,0 +1,197 @@
+//! A portable list of file contents, used to find local files that already exist elsewhere without transferring them, and to skip rehashing unchanged files on a later scan.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Error;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde_derive::{Serialize, Deserialize};
+use crate::{Fileinfo, HashAlgorithm};
+
+/// Version of the manifest file shape. Manifests written by a newer version are refused.
+pub const MANIFEST_SCHEMA_VERSION: u32 = 2;
+
+#[derive(Serialize, Deserialize)]
+struct ManifestPath{
+    path: PathBuf,
+    modified: (u64, u32),
+}
+
+#[derive(Serialize, Deserialize)]
+struct ManifestEntry{
+    full_hash: u128,
+    length: u64,
+    // Absent from schema 1 manifests, whose hashes cannot be reused
+    #[serde(default)]
+    paths: Vec<ManifestPath>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct PathEntry{
+    full_hash: u128,
+    length: u64,
+    modified: (u64, u32),
+}
+
+#[derive(Serialize, Deserialize)]
+struct ManifestFile{
+    schema: u32,
+    #[serde(default)]
+    hash_algorithm: HashAlgorithm,
+    files: Vec<ManifestEntry>,
+}
+
+/// Contents identified by full hash and length, along with the paths and modification times they were found at.
+/// Only files with a full hash can be added or matched, so manifests are built from scans with `content_only` set.
+#[derive(Debug, Default)]
+pub struct Manifest{
+    hash_algorithm: HashAlgorithm,
+    entries: HashSet<(u128, u64)>,
+    paths: HashMap<PathBuf, PathEntry>,
+    reused: AtomicU64,
+    rehashed: AtomicU64,
+}
+
+impl Manifest{
+    /// Creates an empty manifest for contents hashed with the given algorithm.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::path::Path;
+    /// use ddh::{Fileinfo, HashAlgorithm};
+    /// use ddh::manifest::Manifest;
+    ///
+    /// let mut manifest = Manifest::new(HashAlgorithm::Sip128);
+    /// manifest.insert(&Fileinfo::new(Some(123), None, 3, Path::new("./foo/bar.txt").to_path_buf()));
+    /// assert!(manifest.contains(&Fileinfo::new(Some(123), None, 3, Path::new("./other.txt").to_path_buf())));
+    /// assert!(!manifest.contains(&Fileinfo::new(None, None, 3, Path::new("./foo/bar.txt").to_path_buf())));
+    /// ```
+    pub fn new(hash_algorithm: HashAlgorithm) -> Self{
+        Manifest{hash_algorithm, ..Manifest::default()}
+    }
+    /// Loads a manifest written by `save`.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::path::Path;
+    /// use ddh::{Fileinfo, HashAlgorithm};
+    /// use ddh::manifest::Manifest;
+    ///
+    /// let temp = tempfile::tempdir().unwrap();
+    /// let manifest_file = temp.path().join("manifest.json");
+    /// let mut manifest = Manifest::new(HashAlgorithm::Blake3);
+    /// manifest.insert(&Fileinfo::new(Some(123), None, 3, Path::new("./foo/bar.txt").to_path_buf()));
+    /// manifest.save(&manifest_file).unwrap();
+    /// let loaded = Manifest::load(&manifest_file).unwrap();
+    /// assert_eq!((1, HashAlgorithm::Blake3), (loaded.len(), loaded.hash_algorithm()));
+    /// ```
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error>{
+        let manifest_file: ManifestFile = serde_json::from_str(&fs::read_to_string(path)?)?;
+        if manifest_file.schema > MANIFEST_SCHEMA_VERSION {
+            return Err(Error::other(format!("Manifest schema {} is newer than the supported schema {}", manifest_file.schema, MANIFEST_SCHEMA_VERSION)))
+        }
+        let mut manifest = Manifest::new(manifest_file.hash_algorithm);
+        for entry in manifest_file.files.into_iter(){
+            manifest.entries.insert((entry.full_hash, entry.length));
+            for path in entry.paths.into_iter(){
+                manifest.paths.insert(path.path, PathEntry{full_hash: entry.full_hash, length: entry.length, modified: path.modified});
+            }
+        }
+        Ok(manifest)
+    }
+    /// Writes the manifest to a file as JSON.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error>{
+        let mut paths: HashMap<(u128, u64), Vec<ManifestPath>> = HashMap::new();
+        for (path, entry) in self.paths.iter(){
+            paths.entry((entry.full_hash, entry.length)).or_default().push(ManifestPath{path: path.clone(), modified: entry.modified});
+        }
+        let manifest_file = ManifestFile{
+            schema: MANIFEST_SCHEMA_VERSION,
+            hash_algorithm: self.hash_algorithm,
+            files: self.entries.iter().map(|&(full_hash, length)| {
+                ManifestEntry{full_hash, length, paths: paths.remove(&(full_hash, length)).unwrap_or_default()}
+            }).collect(),
+        };
+        fs::write(path, serde_json::to_string(&manifest_file)?)
+    }
+    /// Gets the algorithm the manifest's hashes were computed with. Only scans using the same algorithm can be compared against it.
+    pub fn hash_algorithm(&self) -> HashAlgorithm{
+        self.hash_algorithm
+    }
+    /// Adds the contents of `file`, and each of its paths with a known modification time. Files without a full hash are ignored.
+    pub fn insert(&mut self, file: &Fileinfo){
+        if let Some(hash) = file.get_full_hash() {
+            self.entries.insert((hash, file.get_length()));
+            for (path, modified) in file.get_paths().iter().zip(file.get_modified()){
+                if let Some(modified) = modified.and_then(since_epoch) {
+                    self.paths.insert(path.clone(), PathEntry{full_hash: hash, length: file.get_length(), modified});
+                }
+            }
+        }
+    }
+    /// Checks whether the manifest holds the contents of `file`. Files without a full hash never match.
+    pub fn contains(&self, file: &Fileinfo) -> bool{
+        file.get_full_hash().is_some_and(|x| self.entries.contains(&(x, file.get_length())))
+    }
+    /// Gets the number of distinct contents in the manifest.
+    pub fn len(&self) -> usize{
+        self.entries.len()
+    }
+    /// Checks whether the manifest holds no contents.
+    pub fn is_empty(&self) -> bool{
+        self.entries.is_empty()
+    }
+    /// Gets the number of paths recorded with their modification times, whose hashes a scan can reuse.
+    pub fn paths_len(&self) -> usize{
+        self.paths.len()
+    }
+    pub(crate) fn lookup(&self, path: &Path, length: u64, modified: Option<SystemTime>) -> Option<u128>{
+        let modified = modified.and_then(since_epoch)?;
+        let entry = self.paths.get(path).filter(|x| x.length == length && x.modified == modified)?;
+        self.reused.fetch_add(1, Ordering::Relaxed);
+        Some(entry.full_hash)
+    }
+    pub(crate) fn record_rehashed(&self){
+        self.rehashed.fetch_add(1, Ordering::Relaxed);
+    }
+    /// Gets the number of files a scan with this manifest in `ScanOptions::since_manifest` took the full hash of from the manifest, as their length and modification time were unchanged.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::fs;
+    /// use std::sync::Arc;
+    /// use ddh::{HashAlgorithm, ScanOptions};
+    /// use ddh::manifest::Manifest;
+    ///
+    /// let temp = tempfile::tempdir().unwrap();
+    /// let root = temp.path();
+    /// fs::write(root.join("kept"), b"unchanged").unwrap();
+    /// fs::write(root.join("edited"), b"first draft").unwrap();
+    ///
+    /// let options = ScanOptions::builder().content_only(true).build();
+    /// let (files, _) = ddh::deduplicate_dirs_with(vec![&root], &options);
+    /// let mut prior = Manifest::new(HashAlgorithm::Sip128);
+    /// files.iter().for_each(|x| prior.insert(x));
+    ///
+    /// fs::write(root.join("edited"), b"second draft, longer").unwrap();
+    /// fs::write(root.join("added"), b"new").unwrap();
+    /// let prior = Arc::new(prior);
+    /// let options = ScanOptions::builder().content_only(true).since_manifest(prior.clone()).build();
+    /// let (files, _) = ddh::deduplicate_dirs_with(vec![&root], &options);
+    /// assert_eq!(3, files.len());
+    /// assert_eq!((1, 2), (prior.reused_files(), prior.rehashed_files()));
+    /// ```
+    pub fn reused_files(&self) -> u64{
+        self.reused.load(Ordering::Relaxed)
+    }
+    /// Gets the number of files a scan with this manifest in `ScanOptions::since_manifest` read for their full hash, as they were new or changed.
+    pub fn rehashed_files(&self) -> u64{
+        self.rehashed.load(Ordering::Relaxed)
+    }
+}
+
+fn since_epoch(time: SystemTime) -> Option<(u64, u32)>{
+    let since_epoch = time.duration_since(UNIX_EPOCH).ok()?;
+    Some((since_epoch.as_secs(), since_epoch.subsec_nanos()))
+}
@@ -0,0 +1,126 @@
+//! Detection of files with nearly the same name in one directory, such as manually numbered versions, built on the files from a completed scan.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use rayon::prelude::*;
+use serde_derive::Serialize;
+use crate::{utils, Fileinfo};
+
+/// Files in one directory whose names are each within the distance of another name in the group.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SimilarNames{
+    /// The directory holding the files.
+    pub directory: PathBuf,
+    /// The file names, sorted.
+    pub names: Vec<String>,
+}
+
+/// Counts the characters inserted, deleted or replaced to turn `a` into `b`.
+///
+/// # Examples
+/// ```
+/// use ddh::names::edit_distance;
+///
+/// assert_eq!(1, edit_distance("report_final.doc", "report_final2.doc"));
+/// assert_eq!(2, edit_distance("draft.txt", "dratf.txt"));
+/// assert_eq!(3, edit_distance("", "abc"));
+/// assert_eq!(0, edit_distance("café", "café"));
+/// ```
+pub fn edit_distance(a: &str, b: &str) -> usize{
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+    for (i, x) in a.chars().enumerate(){
+        current[0] = i + 1;
+        for (j, &y) in b.iter().enumerate(){
+            let replace = previous[j] + if x == y {0} else {1};
+            current[j + 1] = replace.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()]
+}
+
+/// Finds files whose names are within `max_distance` edits of another file's name in the same directory.
+/// Names linked by a chain of close pairs form one group, so `v1`, `v2` and `v3` of a file are reported together. Only names within one directory are compared, which keeps the work to each directory's own pairs.
+/// Names are compared once normalized to NFC, so a composed and a decomposed spelling of one name are no edits apart, but are listed as found.
+/// Groups are sorted by directory and then by their first name.
+///
+/// # Examples
+/// ```
+/// use std::fs;
+/// use ddh::names::find_similar_names;
+///
+/// let temp = tempfile::tempdir().unwrap();
+/// let root = temp.path();
+/// fs::create_dir_all(root.join("other")).unwrap();
+/// fs::write(root.join("report_final.doc"), b"first").unwrap();
+/// fs::write(root.join("report_final2.doc"), b"second").unwrap();
+/// fs::write(root.join("report_final3.doc"), b"third").unwrap();
+/// fs::write(root.join("budget.xls"), b"sums").unwrap();
+/// fs::write(root.join("other/report_final4.doc"), b"fourth").unwrap();
+///
+/// let (files, _) = ddh::deduplicate_dirs(vec![&root]);
+/// let groups = find_similar_names(&files, 1);
+/// assert_eq!(1, groups.len());
+/// assert_eq!(root, groups[0].directory);
+/// assert_eq!(vec!["report_final.doc", "report_final2.doc", "report_final3.doc"], groups[0].names);
+///
+/// // Filesystems which normalize names themselves would store these as one file
+/// #[cfg(target_os = "linux")] {
+///     fs::create_dir_all(root.join("accents")).unwrap();
+///     fs::write(root.join("accents/caf\u{e9}.doc"), b"composed").unwrap();
+///     fs::write(root.join("accents/cafe\u{301}.doc"), b"decomposed").unwrap();
+///     let (files, _) = ddh::deduplicate_dirs(vec![root.join("accents")]);
+///     let groups = find_similar_names(&files, 1);
+///     assert_eq!(1, groups.len());
+///     assert_eq!(vec!["cafe\u{301}.doc", "caf\u{e9}.doc"], groups[0].names);
+/// }
+/// ```
+pub fn find_similar_names(files: &[Fileinfo], max_distance: usize) -> Vec<SimilarNames>{
+    let mut by_directory: HashMap<&Path, Vec<(String, String)>> = HashMap::new();
+    for path in files.iter().flat_map(|x| x.get_paths()){
+        if let (Some(directory), Some(name), Some(normalized)) = (path.parent(), path.file_name(), utils::normalized_file_name(path)) {
+            by_directory.entry(directory).or_default().push((name.to_string_lossy().into_owned(), normalized));
+        }
+    }
+    let mut groups: Vec<SimilarNames> = by_directory.into_par_iter()
+        .flat_map_iter(|(directory, mut names)| {
+            names.sort();
+            names.dedup();
+            similar_groups(names, max_distance).into_iter()
+                .map(move |names| SimilarNames{directory: directory.to_path_buf(), names})
+        })
+        .collect();
+    groups.sort_by(|a, b| a.directory.cmp(&b.directory).then_with(|| a.names.cmp(&b.names)));
+    groups
+}
+
+// Joins close names with a union find, skipping pairs whose lengths alone differ by more than the distance. Each name is paired with
+// its normalized form, which is what is compared
+fn similar_groups(names: Vec<(String, String)>, max_distance: usize) -> Vec<Vec<String>>{
+    let lengths: Vec<usize> = names.iter().map(|x| x.1.chars().count()).collect();
+    let mut parents: Vec<usize> = (0..names.len()).collect();
+    fn find(parents: &mut [usize], mut x: usize) -> usize{
+        while parents[x] != x {
+            parents[x] = parents[parents[x]];
+            x = parents[x];
+        }
+        x
+    }
+    for i in 0..names.len(){
+        for j in i + 1..names.len(){
+            if lengths[i].abs_diff(lengths[j]) > max_distance || edit_distance(&names[i].1, &names[j].1) > max_distance {
+                continue
+            }
+            let (a, b) = (find(&mut parents, i), find(&mut parents, j));
+            parents[a.max(b)] = a.min(b);
+        }
+    }
+    let mut groups: HashMap<usize, Vec<String>> = HashMap::new();
+    for (i, (name, _)) in names.into_iter().enumerate(){
+        let root = find(&mut parents, i);
+        groups.entry(root).or_default().push(name);
+    }
+    groups.into_values().filter(|x| x.len() > 1).collect()
+}
@@ -0,0 +1,71 @@
+//! Time spent in each stage of a scan, accumulated across threads for deciding what to optimize.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// A stage of a scan.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage{
+    /// Walking the search directories. Partial hashes of files found to share a length are computed alongside, and counted here as well as under `PartialHashing`.
+    Traversal,
+    /// Reading and hashing the sampled blocks of files sharing a length.
+    PartialHashing,
+    /// Reading and hashing whole files whose partial hashes match.
+    FullHashing,
+    /// Grouping hashed files and sorting results.
+    Grouping,
+    /// Comparing files with matching full hashes byte for byte.
+    Comparing,
+    /// Printing and writing reports. Recorded by the caller.
+    Output,
+}
+
+impl Stage{
+    /// Every stage, in the order a scan passes through them.
+    pub const ALL: [Stage; 6] = [Stage::Traversal, Stage::PartialHashing, Stage::FullHashing, Stage::Grouping, Stage::Comparing, Stage::Output];
+}
+
+/// Nanoseconds spent in each stage. Stages run on several threads at once add up the time of each thread, so they can exceed the scan's wall clock time.
+#[derive(Debug, Default)]
+pub struct Profile{
+    nanos: [AtomicU64; 6],
+}
+
+impl Profile{
+    /// Creates a profile with no time recorded.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::time::Duration;
+    /// use ddh::profile::{Profile, Stage};
+    ///
+    /// let profile = Profile::new();
+    /// profile.record(Stage::Output, Duration::from_millis(3));
+    /// assert_eq!(Duration::from_millis(3), profile.elapsed(Stage::Output));
+    /// assert_eq!(Duration::ZERO, profile.elapsed(Stage::FullHashing));
+    /// ```
+    pub fn new() -> Self{
+        Profile::default()
+    }
+    /// Adds time spent in a stage.
+    pub fn record(&self, stage: Stage, elapsed: Duration){
+        self.nanos[stage as usize].fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+    /// Gets the time spent in a stage so far.
+    pub fn elapsed(&self, stage: Stage) -> Duration{
+        Duration::from_nanos(self.nanos[stage as usize].load(Ordering::Relaxed))
+    }
+}
+
+/// Runs `f`, adding the time it takes to `stage` when profiling.
+pub(crate) fn measure<T, F: FnOnce() -> T>(profile: Option<&Profile>, stage: Stage, f: F) -> T{
+    match profile {
+        Some(profile) => {
+            let started = Instant::now();
+            let result = f();
+            profile.record(stage, started.elapsed());
+            result
+        },
+        None => f(),
+    }
+}
@@ -0,0 +1,127 @@
+//! Byte counters shared between the hashing threads and whoever is reporting on them.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// Tracks how many bytes the full hash stage has to read and how many it has read so far.
+///
+/// The total grows as each length bucket leaves the partial hash stage, so early estimates are optimistic and settle as the scan proceeds.
+#[derive(Debug, Default)]
+pub struct Progress{
+    total_bytes: AtomicU64,
+    hashed_bytes: AtomicU64,
+    started: OnceLock<Instant>,
+    large_files: Mutex<Vec<Arc<FileProgress>>>,
+}
+
+/// Files at least this long are tracked on their own while fully hashed, so a single huge file visibly advances.
+pub const LARGE_FILE_BYTES: u64 = 1024 * 1024 * 1024;
+
+/// How far the full hash of one large file has got.
+#[derive(Debug)]
+pub struct FileProgress{
+    path: PathBuf,
+    length: u64,
+    hashed_bytes: AtomicU64,
+}
+
+impl FileProgress{
+    /// Gets the path being hashed.
+    pub fn path(&self) -> &Path{
+        &self.path
+    }
+    /// Gets the number of bytes to hash.
+    pub fn length(&self) -> u64{
+        self.length
+    }
+    /// Gets the number of bytes hashed so far.
+    pub fn hashed_bytes(&self) -> u64{
+        self.hashed_bytes.load(Ordering::Relaxed)
+    }
+}
+
+/// Records reads of one large file into both its own and the overall counts. Stops tracking the file when dropped.
+pub(crate) struct FileTracker<'a>{
+    progress: &'a Progress,
+    file: Option<Arc<FileProgress>>,
+}
+
+impl FileTracker<'_>{
+    pub(crate) fn add_hashed(&self, bytes: u64){
+        self.progress.add_hashed(bytes);
+        if let Some(file) = &self.file {
+            file.hashed_bytes.fetch_add(bytes, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Drop for FileTracker<'_>{
+    fn drop(&mut self){
+        if let Some(file) = &self.file {
+            self.progress.large_files.lock().unwrap().retain(|x| !Arc::ptr_eq(x, file));
+        }
+    }
+}
+
+impl Progress{
+    /// Creates a new Progress tracker with no bytes recorded.
+    ///
+    /// # Examples
+    /// ```
+    /// use ddh::progress::Progress;
+    ///
+    /// let progress = Progress::new();
+    /// assert_eq!(0, progress.total_bytes());
+    /// assert_eq!(None, progress.eta());
+    /// ```
+    pub fn new() -> Self{
+        Progress::default()
+    }
+    pub(crate) fn add_total(&self, bytes: u64){
+        self.started.get_or_init(Instant::now);
+        self.total_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+    pub(crate) fn add_hashed(&self, bytes: u64){
+        self.hashed_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+    // Files shorter than LARGE_FILE_BYTES only count towards the overall bytes hashed
+    pub(crate) fn track_file(&self, path: &Path, length: u64) -> FileTracker<'_>{
+        let file = (length >= LARGE_FILE_BYTES).then(|| {
+            let file = Arc::new(FileProgress{path: path.to_path_buf(), length, hashed_bytes: AtomicU64::new(0)});
+            self.large_files.lock().unwrap().push(file.clone());
+            file
+        });
+        FileTracker{progress: self, file}
+    }
+    /// Gets the files of at least `LARGE_FILE_BYTES` being fully hashed right now, in the order they were started.
+    ///
+    /// # Examples
+    /// ```
+    /// use ddh::progress::Progress;
+    ///
+    /// assert!(Progress::new().large_files().is_empty());
+    /// ```
+    pub fn large_files(&self) -> Vec<Arc<FileProgress>>{
+        self.large_files.lock().unwrap().clone()
+    }
+    /// Gets the number of bytes of duplicate candidates which have entered the full hash stage.
+    pub fn total_bytes(&self) -> u64{
+        self.total_bytes.load(Ordering::Relaxed)
+    }
+    /// Gets the number of bytes read by the full hash stage so far.
+    pub fn hashed_bytes(&self) -> u64{
+        self.hashed_bytes.load(Ordering::Relaxed)
+    }
+    /// Estimates the time remaining in the full hash stage from the read rate so far. Returns `None` until some bytes have been hashed.
+    pub fn eta(&self) -> Option<Duration>{
+        let elapsed = self.started.get()?.elapsed();
+        let hashed = self.hashed_bytes();
+        if hashed == 0 {
+            return None
+        }
+        let remaining = self.total_bytes().saturating_sub(hashed);
+        Some(elapsed.mul_f64(remaining as f64 / hashed as f64))
+    }
+}
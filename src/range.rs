@@ -0,0 +1,56 @@
+//! Byte ranges restricting hashing and comparison to part of each file, for files which differ only in volatile regions such as embedded timestamps.
+
+/// A range of bytes taken from each file, written `start:end`. Either side may be left out, and negative offsets count back from the end of the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HashRange{
+    start: i64,
+    end: Option<i64>,
+}
+
+impl HashRange{
+    /// Parses a range such as `1024:`, `:-512` or `16:-16`.
+    ///
+    /// # Examples
+    /// ```
+    /// use ddh::range::HashRange;
+    ///
+    /// assert_eq!((1024, 4096), HashRange::parse("1024:").unwrap().bounds(4096));
+    /// assert_eq!((0, 3584), HashRange::parse(":-512").unwrap().bounds(4096));
+    /// assert_eq!((4000, 4096), HashRange::parse("-96:").unwrap().bounds(4096));
+    /// assert_eq!((16, 16), HashRange::parse("16:-16").unwrap().bounds(20));
+    /// assert!(HashRange::parse("1024").is_err());
+    /// assert!(HashRange::parse("512:256").is_err());
+    /// ```
+    pub fn parse(range: &str) -> Result<Self, String>{
+        let (start, end) = range.split_once(':')
+            .ok_or_else(|| format!("{} should be given as start:end, with either side optional", range))?;
+        let offset = |x: &str| x.parse::<i64>().map_err(|e| format!("Invalid offset {} in range {}. Err: {}", x, range, e));
+        let start = if start.is_empty() {0} else {offset(start)?};
+        let end = if end.is_empty() {None} else {Some(offset(end)?)};
+        match end {
+            Some(end) if (start >= 0) == (end >= 0) && end <= start => Err(format!("Range {} is empty", range)),
+            _ => Ok(HashRange{start, end}),
+        }
+    }
+    /// Creates a range skipping the first `offset` bytes of each file, as given to `--skip-header`, for formats with a fixed size header.
+    /// Files no longer than the header have nothing left to compare.
+    ///
+    /// # Examples
+    /// ```
+    /// use ddh::range::HashRange;
+    ///
+    /// assert_eq!(HashRange::parse("512:").unwrap(), HashRange::after(512));
+    /// assert_eq!((512, 4096), HashRange::after(512).bounds(4096));
+    /// assert_eq!((100, 100), HashRange::after(512).bounds(100));
+    /// ```
+    pub fn after(offset: u64) -> Self{
+        HashRange{start: offset.min(i64::MAX as u64) as i64, end: None}
+    }
+    /// Gets the offsets the range starts and ends at in a file of `length` bytes, clamped to the file. The range is empty where the file is too short to hold any of it.
+    pub fn bounds(&self, length: u64) -> (u64, u64){
+        let resolve = |x: i64| if x < 0 {length.saturating_sub(x.unsigned_abs())} else {(x as u64).min(length)};
+        let start = resolve(self.start);
+        let end = self.end.map_or(length, resolve).max(start);
+        (start, end)
+    }
+}
@@ -0,0 +1,1116 @@
+//! Summaries and listings of scan results, printed to the terminal and written to the output file.
+
+use std::io::{stdin, BufWriter, IsTerminal};
+use std::fs::{self};
+use std::io::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use rayon::prelude::*;
+use serde_derive::Serialize;
+use ddh::{Estimate, Fileinfo, HashAlgorithm};
+use ddh::manifest::Manifest;
+use ddh::budget::ScanBudget;
+use ddh::directories::{DirectoryGroup, OverlappingDirectories, RedundantDirectory};
+use ddh::text::TextGroup;
+use ddh::compressed::CompressedCopy;
+use ddh::names::SimilarNames;
+use ddh::compare::{PresentFile, TreeDifferences};
+use ddh::template::Template;
+use ddh::stats::{collision_probability, files_by_root, LengthStats, ScanStats};
+use ddh::utils::{format_number, format_scaled, NumberFormat, TextEncoding};
+use ddh::actions::{action_targets, RetentionPolicy};
+use ddh::clones::{storage_sets, StorageSets};
+use ddh::profile::{Profile, Stage};
+use ddh::watch::NewDuplicate;
+
+const GREEN: &str = "\x1b[32m";
+const RED: &str = "\x1b[31m";
+const RESET: &str = "\x1b[0m";
+/// Bytes buffered before each write of a report, so a report of millions of paths takes few system calls.
+const OUTPUT_BUFFER_SIZE: usize = 1024*1024;
+
+#[derive(Debug, Copy, Clone)]
+pub enum PrintFmt{
+    Standard,
+    Json,
+    Off,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum JsonShape{
+    Groups,
+    Map,
+}
+
+#[derive(Serialize)]
+struct JsonReport<'a>{
+    schema: u32,
+    generated_by: String,
+    duplicated_ratio: f64,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    partial: bool,
+    groups: Vec<JsonGroup<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonGroup<'a>{
+    #[serde(flatten)]
+    file: &'a Fileinfo,
+    fully_hashed: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    content_unverified: bool,
+}
+
+// Every report but the duplicates listing, led by the schema and generator, with the report's own fields flattened after them
+#[derive(Serialize)]
+struct Envelope<T: serde::Serialize>{
+    schema: u32,
+    generated_by: String,
+    #[serde(flatten)]
+    fields: T,
+}
+
+#[derive(Serialize)]
+struct StatsFields<'a>{
+    length_stats: &'a LengthStats,
+    vanished_files: u64,
+}
+
+#[derive(Serialize)]
+struct TreeDifferenceFields<'a>{
+    tree_differences: &'a TreeDifferences,
+    unread_paths: Vec<std::borrow::Cow<'a, str>>,
+}
+
+#[derive(Serialize)]
+struct PlannedAction<'a>{
+    keep_path: &'a PathBuf,
+    delete_paths: Vec<&'a PathBuf>,
+    size: u64,
+    hash: String,
+}
+
+#[derive(Serialize)]
+struct SizedPaths<'a>{
+    length: u64,
+    paths: Vec<&'a PathBuf>,
+}
+
+// How the duplicates listing is laid out
+struct Listing<'a>{
+    template: Option<Template>,
+    compact: bool,
+    policy: &'a RetentionPolicy,
+    color: bool,
+    numbers: NumberFormat,
+    inodes: bool,
+    common_prefix: bool,
+    separator: Option<&'a str>,
+}
+
+// The contents of an output file, serialized as they are written
+enum Report<'a>{
+    Json{groups: &'a [&'a Fileinfo], shape: JsonShape, duplicated_ratio: f64, partial: bool},
+    Standard(StandardReport<'a>),
+    Off,
+}
+
+struct StandardReport<'a>{
+    shared: &'a [&'a Fileinfo],
+    unique: &'a [&'a Fileinfo],
+    notes: &'a [&'a str],
+    common_prefix: bool,
+    encoding: TextEncoding,
+}
+
+pub enum Verbosity{
+    Quiet,
+    Duplicates,
+    All,
+}
+
+pub fn print_format(arguments: &clap::ArgMatches) -> PrintFmt{
+    match arguments.value_of("Format").unwrap_or(""){
+        "standard" => PrintFmt::Standard,
+        "json" => PrintFmt::Json,
+        _ => PrintFmt::Standard}
+}
+
+// Prints a line about the scan or what was done with its results. Stdout carries nothing but JSON documents with --format json, so there it goes to stderr
+pub fn print_note(fmt: PrintFmt, line: std::fmt::Arguments){
+    match fmt {
+        PrintFmt::Json => eprintln!("{}", line),
+        _ => println!("{}", line),
+    }
+}
+
+pub fn process_full_output(complete_files: &[Fileinfo], error_paths: &[(PathBuf, std::io::Error)], search_dirs: &[&str], destination: &str, budget: Option<&ScanBudget>, arguments: &clap::ArgMatches){
+    let (blocksize, display_divisor) = display_blocksize(arguments);
+    let color = arguments.is_present("Color") && std::env::var_os("NO_COLOR").is_none() && std::io::stdout().is_terminal();
+    let policy = crate::retention_policy(arguments);
+    let fmt = print_format(arguments);
+    let verbosity = match arguments.value_of("Verbosity").unwrap_or(""){
+        "quiet" => Verbosity::Quiet,
+        "duplicates" => Verbosity::Duplicates,
+        "all" => Verbosity::All,
+        _ => Verbosity::Quiet};
+    let json_shape = match arguments.value_of("JsonShape").unwrap_or(""){
+        "map" => JsonShape::Map,
+        _ => JsonShape::Groups};
+    let listing = Listing{
+        template: arguments.value_of("Template").map(|x| Template::parse(x).unwrap()),
+        compact: arguments.value_of("ReportFormat") == Some("compact"),
+        policy: &policy,
+        color,
+        numbers: number_format(arguments),
+        inodes: arguments.is_present("ResolveHardlinks"),
+        common_prefix: arguments.is_present("CommonPrefix"),
+        separator: arguments.value_of("GroupSeparator"),
+    };
+    let (shared_files, unique_files): (Vec<&Fileinfo>, Vec<&Fileinfo>) = complete_files.par_iter().partition(|&x| x.get_paths().len()>1);
+    let budget_note = budget.filter(|x| x.is_exhausted()).map(|x| {
+        format!("Partial results: the scan stopped taking in files at its budget of {}", display_size(x.limit(), arguments))
+    });
+    let partial = budget_note.is_some();
+    if let Some(note) = &budget_note {
+        print_note(fmt, format_args!("{}", note));
+    }
+    let skip_empty_output = arguments.is_present("OutputNullOnEmpty") && shared_files.is_empty() && !matches!(verbosity, Verbosity::All);
+
+    let total_bytes = complete_files.par_iter()
+    .map(|x| (x.get_paths().len() as u64)*x.get_length())
+    .sum::<u64>();
+    let storage: Vec<StorageSets> = shared_files.par_iter().map(|x| storage_sets(x)).collect();
+    let reclaimable_bytes = shared_files.iter().zip(storage.iter())
+    .map(|(x, y)| (y.distinct as u64 - 1)*x.get_length())
+    .sum::<u64>();
+    let duplicated_ratio = if total_bytes == 0 {0.0} else {reclaimable_bytes as f64/total_bytes as f64};
+    print_note(fmt, format_args!("{} Total files (with duplicates): {}", display_count(complete_files.par_iter()
+    .map(|x| x.get_paths().len() as u64)
+    .sum::<u64>(), arguments),
+    display_size(total_bytes, arguments)));
+    print_note(fmt, format_args!("{} Total files (without duplicates): {}", display_count(complete_files.len() as u64, arguments), display_size(complete_files.par_iter()
+    .map(|x| x.get_length())
+    .sum::<u64>(), arguments)));
+    print_note(fmt, format_args!("{} Single instance files: {}", display_count(unique_files.len() as u64, arguments), display_size(unique_files.par_iter()
+    .map(|x| x.get_length())
+    .sum::<u64>(), arguments)));
+    print_note(fmt, format_args!("{} Shared instance files: {} ({} instances)", display_count(shared_files.len() as u64, arguments), display_size(shared_files.par_iter()
+    .map(|x| x.get_length())
+    .sum::<u64>(), arguments), display_count(shared_files.par_iter()
+    .map(|x| x.get_paths().len() as u64)
+    .sum::<u64>(), arguments)));
+    print_note(fmt, format_args!("{:.0}% of scanned bytes are duplicated", duplicated_ratio*100.0));
+    let root_files = files_by_root(complete_files, search_dirs.len());
+    if search_dirs.len() > 1 && !arguments.is_present("SeparateRoots"){
+        search_dirs.iter().zip(root_files.iter()).filter(|x| *x.1 == 0).for_each(|x| {
+            print_note(fmt, format_args!("0 Files under {}, which is empty or had every file filtered out", x.0));
+        });
+    }
+    if arguments.is_present("Confidence"){
+        let hash_bits = match arguments.value_of("HashAlgorithm") {
+            Some("blake3") => HashAlgorithm::Blake3,
+            _ => HashAlgorithm::Sip128,
+        }.bits();
+        match collision_probability(complete_files, hash_bits) {
+            0.0 => print_note(fmt, format_args!("No two different files share a length, so none could share a {} bit hash", hash_bits)),
+            p => print_note(fmt, format_args!("Chance that two different files share a {} bit hash: about {:.1e}", hash_bits, p)),
+        }
+        // Only groups matched by full hash are compared, so those matched on a partial hash or by name rest on less
+        let unverified: Vec<&&Fileinfo> = shared_files.iter().filter(|x| !x.is_fully_hashed()).collect();
+        if arguments.is_present("TrustFullHash") {
+            print_note(fmt, format_args!("Groups were trusted on their hashes alone with --trust-full-hash, so a collision would group different files."));
+        } else if unverified.is_empty() {
+            print_note(fmt, format_args!("Groups matched by hash were also compared byte for byte, so a collision could not group different files."));
+        } else {
+            print_note(fmt, format_args!("Groups matched by full hash were also compared byte for byte. {} groups were not fully hashed or compared, so their files may differ:", unverified.len()));
+            unverified.iter().for_each(|x| {
+                print_note(fmt, format_args!("\t{}", x.get_paths_sorted().iter().map(|y| y.display().to_string()).collect::<Vec<_>>().join(", ")));
+            });
+        }
+    }
+    if shared_files.iter().any(|x| matched_by_name_only(x)){
+        print_note(fmt, format_args!("Duplicates were matched on size and name alone without reading their contents, so files in a group may differ."));
+    }
+    let (clone_count, clone_bytes) = shared_files.iter().zip(storage.iter())
+    .flat_map(|(x, y)| y.shared.iter().map(move |z| (z.len() as u64 - 1, (z.len() as u64 - 1)*x.get_length())))
+    .fold((0, 0), |a, b| (a.0 + b.0, a.1 + b.1));
+    if clone_count > 0 {
+        print_note(fmt, format_args!("{} copies are CoW clones or hard links sharing storage with another copy. Their {} are not counted as duplicated.", clone_count, display_size(clone_bytes, arguments)));
+    }
+    let sparse_paths: Vec<&PathBuf> = complete_files.iter().flat_map(|x| x.get_sparse_paths()).collect();
+    if !sparse_paths.is_empty(){
+        eprintln!("Warning: {} sparse files were scanned. Hashing reads their holes as zeros, which may be slow. Use --skip-sparse to skip them.", sparse_paths.len());
+        sparse_paths.iter().for_each(|x| eprintln!("\t{}", x.display()));
+    }
+
+    if arguments.is_present("ByExtension"){
+        let mut duplicated_by_extension: HashMap<String, u64> = HashMap::new();
+        for (file, storage) in shared_files.iter().zip(storage.iter()){
+            let extensions: Vec<String> = file.get_extension_set().into_iter().collect();
+            let extension = if extensions.is_empty() {"(none)".to_string()} else {extensions.join("/")};
+            *duplicated_by_extension.entry(extension).or_insert(0) += (storage.distinct as u64 - 1)*file.get_length();
+        }
+        let mut duplicated_by_extension: Vec<(String, u64)> = duplicated_by_extension.into_iter().collect();
+        duplicated_by_extension.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        print_note(fmt, format_args!("Duplicated bytes by file type"));
+        duplicated_by_extension.iter().for_each(|x| {
+            print_note(fmt, format_args!("\t{}: {} duplicated", x.0, display_size(x.1, arguments)));
+        });
+    }
+
+    if arguments.is_present("ByRoot"){
+        // Each copy in a group carries an equal share of the bytes reclaimable from that group
+        let mut duplicated_by_root: Vec<(u64, u64, u64)> = vec![(0, 0, 0); search_dirs.len()];
+        for (file, storage) in shared_files.iter().zip(storage.iter()){
+            let copies = file.get_paths().len() as u128;
+            let share = |n: u128| (n*(storage.distinct as u128 - 1)*file.get_length() as u128/copies) as u64;
+            let mut copies_by_root: HashMap<usize, u128> = HashMap::new();
+            for &root in file.get_roots(){
+                *copies_by_root.entry(root).or_insert(0) += 1;
+            }
+            for (root, n) in copies_by_root.into_iter(){
+                if let Some(entry) = duplicated_by_root.get_mut(root){
+                    entry.0 += n as u64;
+                    entry.1 += n as u64*file.get_length();
+                    entry.2 += share(n);
+                }
+            }
+        }
+        let mut rows: Vec<(&str, u64, (u64, u64, u64))> = search_dirs.iter().copied().zip(root_files).zip(duplicated_by_root).map(|((x, y), z)| (x, y, z)).collect();
+        rows.sort_by(|a, b| b.2.2.cmp(&a.2.2).then_with(|| a.0.cmp(b.0)));
+        let width = rows.iter().map(|x| x.0.len()).max().unwrap_or(0).max("Directory".len());
+        let duplicated = format!("Duplicated {}", blocksize);
+        let reclaimable = format!("Reclaimable {}", blocksize);
+        print_note(fmt, format_args!("Duplicated bytes by search directory"));
+        print_note(fmt, format_args!("\t{:<width$}  {:>8}  {:>8}  {:>d$}  {:>r$}", "Directory", "Files", "Copies", duplicated, reclaimable, width = width, d = duplicated.len(), r = reclaimable.len()));
+        let numbers = number_format(arguments);
+        let scaled = |n| format_number(&format_scaled(n, display_divisor), numbers);
+        rows.iter().for_each(|(dir, files, x)| {
+            print_note(fmt, format_args!("\t{:<width$}  {:>8}  {:>8}  {:>d$}  {:>r$}", dir, format_number(&files.to_string(), numbers), format_number(&x.0.to_string(), numbers), scaled(x.1), scaled(x.2), width = width, d = duplicated.len(), r = reclaimable.len()));
+        });
+    }
+
+    let mut listed_files: Vec<&Fileinfo> = shared_files.to_vec();
+    if let Some(limit) = arguments.value_of("Limit").map(|x| x.parse::<usize>().unwrap()){
+        if arguments.is_present("Deterministic") {
+            listed_files.par_sort_by(|a, b| b.get_length().cmp(&a.get_length()));
+        } else {
+            listed_files.par_sort_unstable_by(|a, b| b.get_length().cmp(&a.get_length()));
+        }
+        listed_files.truncate(limit);
+    }
+    let truncation_note = if listed_files.len() < shared_files.len() {
+        Some(format!("Showing {} of {} duplicate groups", listed_files.len(), shared_files.len()))
+    } else {
+        None
+    };
+    let listed_all: Vec<&Fileinfo> = listed_files.iter().chain(unique_files.iter()).cloned().collect();
+
+    // Paths which are not valid UTF-8 are written lossily, so reporting errors never ends the run
+    if let PrintFmt::Json = fmt {
+        error_paths.iter().for_each(|x| {
+            eprintln!("{}", serde_json::json!({
+                "path": x.0.to_string_lossy(),
+                "kind": format!("{:?}", x.1.kind()),
+                "error": x.1.to_string(),
+            }));
+        });
+    }
+
+    if let Some(only_under) = arguments.value_of("SingletonsUnder"){
+        let root = search_dirs.iter()
+            .position(|x| same_directory(x, only_under))
+            .expect("Singleton directory should have been checked against the search directories");
+        let only_files: Vec<&Fileinfo> = complete_files.iter()
+            .filter(|x| x.get_roots().iter().all(|&y| y == root))
+            .collect();
+        match fmt {
+            PrintFmt::Json => {
+                print_json(|out| json_report(out, &only_files, duplicated_ratio, partial));
+            },
+            _ => {
+                println!("Files found only under {}", only_under);
+                only_files.iter().for_each(|x| {
+                    x.get_paths_sorted().iter().for_each(|y| println!("\t{}", y.canonicalize().unwrap_or_else(|_e| y.to_path_buf()).display()));
+                });
+            },
+        }
+    } else {
+        match (fmt, verbosity) {
+            (_, Verbosity::Quiet) => {},
+            (PrintFmt::Standard, Verbosity::Duplicates) => {
+                println!("Shared instance files and instance locations");
+                if let Some(note) = &truncation_note {println!("{}", note);}
+                print_listing(&listed_files, &listing, search_dirs);
+            },
+            (PrintFmt::Standard, Verbosity::All) => {
+                println!("Single instance files"); unique_files.iter()
+                .for_each(|x| {
+                    let path = &x.get_paths()[0];
+                    println!("{}", path.canonicalize().unwrap_or_else(|_e| path.to_path_buf()).display())
+                });
+                println!("Shared instance files and instance locations");
+                if let Some(note) = &truncation_note {println!("{}", note);}
+                print_listing(&listed_files, &listing, search_dirs);
+                if storage.iter().any(|x| !x.shared.is_empty()) {
+                    println!("CoW clones");
+                    storage.iter().flat_map(|x| x.shared.iter()).for_each(|x| {
+                        println!("copies sharing storage:");
+                        x.iter().for_each(|y| println!("\t{}", y.canonicalize().unwrap_or_else(|_e| y.to_path_buf()).display()));
+                    });
+                }
+                error_paths.iter().for_each(|x|{
+                    println!("Could not process {:#?} due to error {:#?}", x.0, x.1.kind());
+                })
+            },
+            (PrintFmt::Json, Verbosity::Duplicates) => {
+                print_json(|out| json_results(out, &listed_files, json_shape, duplicated_ratio, partial));
+            },
+            (PrintFmt::Json, Verbosity::All) => {
+                print_json(|out| json_results(out, &listed_all, json_shape, duplicated_ratio, partial));
+            },
+            _ => {},
+        }
+    }
+
+    match destination{
+        "no" => {},
+        destination_string if skip_empty_output => eprintln!("No duplicates found, {} not written", destination_string),
+        destination_string => {
+            let append = arguments.is_present("Append");
+            let file_fmt = match (arguments.value_of("Format"), arguments.is_present("FormatFromExtension")) {
+                (None, true) => format_from_extension(destination_string),
+                _ => fmt,
+            };
+            let notes: Vec<&str> = budget_note.iter().chain(truncation_note.iter()).map(|x| x.as_str()).collect();
+            let report = match file_fmt {
+                PrintFmt::Json => Report::Json{groups: &listed_all, shape: json_shape, duplicated_ratio, partial},
+                PrintFmt::Standard => Report::Standard(StandardReport{shared: &listed_files, unique: &unique_files, notes: &notes, common_prefix: arguments.is_present("CommonPrefix"),
+                    encoding: match arguments.value_of("OutputEncoding") {
+                        Some("utf16le") => TextEncoding::Utf16Le,
+                        _ => TextEncoding::Utf8,
+                    }}),
+                PrintFmt::Off => Report::Off,
+            };
+            match fs::File::open(destination_string) {
+                Ok(_f) if append => {},
+                    Ok(_f) => {
+                    print_note(fmt, format_args!("---"));
+                    print_note(fmt, format_args!("File {} already exists.", destination_string));
+                    if !confirm("Overwrite?", fmt, arguments) {
+                        print_note(fmt, format_args!("Exiting."));
+                        return;
+                    }
+                    print_note(fmt, format_args!("Over writing {}", destination_string));
+                },
+                Err(_e) => {
+                    match fs::File::create(destination_string) {
+                        Ok(_f) => {},
+                        Err(_e) => {
+                            print_note(fmt, format_args!("Error encountered opening file {}. Err: {}", destination_string, _e));
+                            print_note(fmt, format_args!("Exiting.")); return;
+                        }
+                    }
+                },
+            }
+            write_results_to_file(file_fmt, &report, append, destination_string);
+            if arguments.is_present("ReportChecksum"){
+                match ddh::checksum::write_sidecar(destination_string) {
+                    Ok(sidecar) => print_note(file_fmt, format_args!("Checksum written to {}", sidecar.display())),
+                    Err(e) => eprintln!("Error encountered writing a checksum of {}. Err: {}", destination_string, e),
+                }
+            }
+        },
+    }
+}
+
+pub fn root_destination(destination: &str, root: usize) -> String{
+    let path = Path::new(destination);
+    match (destination, path.file_stem(), path.extension()) {
+        ("no", _, _) => destination.to_string(),
+        (_, Some(stem), Some(extension)) => path.with_file_name(format!("{}.{}.{}", stem.to_string_lossy(), root + 1, extension.to_string_lossy())).to_string_lossy().into_owned(),
+        _ => format!("{}.{}", destination, root + 1),
+    }
+}
+
+// Groups are buffered and flushed as each is complete, so a pipe sees whole groups promptly without a write for every line.
+// A closed pipe, as when piped to head, ends the listing and the process quietly
+fn print_listing(files: &[&Fileinfo], listing: &Listing, search_dirs: &[&str]){
+    let mut out = BufWriter::new(std::io::stdout().lock());
+    let written = files.iter().enumerate().try_for_each(|(group, x)| {
+        if let (Some(separator), true) = (listing.separator, group > 0) {
+            writeln!(out, "{}", separator)?;
+        }
+        match &listing.template {
+            Some(template) => sorted_indices(x).into_iter().try_for_each(|i| writeln!(out, "{}", template.render(x, i, group + 1, search_dirs)))?,
+            None if listing.compact => write_compact_group(&mut out, x)?,
+            None => write_group(&mut out, x, listing)?,
+        }
+        out.flush()
+    });
+    match written {
+        Ok(()) => {},
+        Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => std::process::exit(0),
+        Err(e) => {
+            eprintln!("Error encountered writing the listing. Err: {}", e);
+            std::process::exit(1);
+        },
+    }
+}
+
+fn write_group<W: Write>(out: &mut W, file: &Fileinfo, listing: &Listing) -> std::io::Result<()>{
+    write!(out, "instances of {} with file length {}", file.get_candidate_name(), format_number(&file.get_length().to_string(), listing.numbers))?;
+    let keep = if listing.color {listing.policy.choose(file)} else {None};
+    let mut order: Vec<usize> = sorted_indices(file);
+    let inodes: Vec<Option<(u64, u64)>> = if listing.inodes {file.get_paths().iter().map(|x| ddh::actions::inode(x)).collect()} else {Vec::new()};
+    if listing.inodes {
+        // Hard links to one file are listed together, in the order each file first appears among the sorted paths
+        let sorted = order.clone();
+        let first_seen = |rank: usize| sorted.iter().position(|&x| inodes[x].is_some() && inodes[x] == inodes[sorted[rank]]).unwrap_or(rank);
+        let mut ranks: Vec<usize> = (0..sorted.len()).collect();
+        ranks.sort_by_key(|&rank| first_seen(rank));
+        order = ranks.iter().map(|&rank| sorted[rank]).collect();
+        let distinct = (0..sorted.len()).filter(|&rank| first_seen(rank) == rank).count();
+        write!(out, ", in {} distinct files", format_number(&distinct.to_string(), listing.numbers))?;
+    }
+    if matched_by_name_only(file) {
+        write!(out, ", matched on size and name only")?;
+    } else if !file.is_fully_hashed() {
+        write!(out, ", matched on partial hashes only")?;
+    }
+    let paths: Vec<PathBuf> = file.get_paths().iter().map(|y| y.canonicalize().unwrap_or_else(|_e| y.to_path_buf())).collect();
+    let prefix = if listing.common_prefix {shared_prefix(&paths)} else {None};
+    if let Some(prefix) = &prefix {
+        write!(out, ", under {}", prefix.display())?;
+    }
+    writeln!(out, ":")?;
+    for i in order.into_iter(){
+        let path = relative_to(&paths[i], prefix.as_deref());
+        let inode = match inodes.get(i) {
+            Some(Some((_, inode))) => format!("[inode {}] ", inode),
+            Some(None) => "[inode ?] ".to_string(),
+            None => String::new(),
+        };
+        match (listing.color, Some(i) == keep) {
+            (false, _) => writeln!(out, "\t{}{}", inode, path.to_str().unwrap())?,
+            (true, true) => writeln!(out, "\t{}{}{}{}", inode, GREEN, path.to_str().unwrap(), RESET)?,
+            (true, false) => writeln!(out, "\t{}{}{}{}", inode, RED, path.to_str().unwrap(), RESET)?,
+        }
+    }
+    Ok(())
+}
+
+// The directory shared by a group's paths, which are then listed relative to it. Paths sharing no more than the filesystem root or the current directory gain nothing from it
+fn shared_prefix<P: AsRef<Path>>(paths: &[P]) -> Option<PathBuf>{
+    let prefix = ddh::utils::common_prefix(paths);
+    match prefix.parent() {
+        Some(_) if paths.len() > 1 && prefix != Path::new(".") => Some(prefix),
+        _ => None,
+    }
+}
+
+fn relative_to<'a>(path: &'a Path, prefix: Option<&Path>) -> &'a Path{
+    prefix.and_then(|x| path.strip_prefix(x).ok()).unwrap_or(path)
+}
+
+// One line per group, in the form `<count>x <size> <hash> <first path> [+N more]`
+fn write_compact_group<W: Write>(out: &mut W, file: &Fileinfo) -> std::io::Result<()>{
+    let paths = file.get_paths_sorted();
+    let first = paths[0].canonicalize().unwrap_or_else(|_e| paths[0].to_path_buf());
+    let hash = file.get_full_hash().or(file.get_partial_hash()).unwrap_or_default();
+    write!(out, "{}x {} {:032x} {}", paths.len(), file.get_length(), hash, first.display())?;
+    if paths.len() > 1 {
+        write!(out, " [+{} more]", paths.len() - 1)?;
+    }
+    writeln!(out)
+}
+
+fn display_blocksize(arguments: &clap::ArgMatches) -> (&'static str, u64){
+    let blocksize = match arguments.value_of("Blocksize").unwrap_or(""){"B" => "Bytes", "K" => "Kilobytes", "M" => "Megabytes", "G" => "Gigabytes", _ => "Megabytes"};
+    let display_power = match blocksize{"Bytes" => 0, "Kilobytes" => 1, "Megabytes" => 2, "Gigabytes" => 3, _ => 2};
+    (blocksize, 1024u64.pow(display_power))
+}
+
+// Machine readable output keeps plain numbers, so only standard output is grouped
+fn number_format(arguments: &clap::ArgMatches) -> NumberFormat{
+    match (arguments.value_of("Format"), arguments.value_of("Locale")) {
+        (Some("json"), _) => NumberFormat::PLAIN,
+        (_, Some(locale)) => NumberFormat::from_locale(locale).expect("Locale should have been validated"),
+        _ => NumberFormat::default(),
+    }
+}
+
+fn display_count(count: u64, arguments: &clap::ArgMatches) -> String{
+    format_number(&count.to_string(), number_format(arguments))
+}
+
+// Sizes are rounded to two decimal places of the display blocksize, so --exact-bytes adds the exact count
+fn display_size(bytes: u64, arguments: &clap::ArgMatches) -> String{
+    let (blocksize, display_divisor) = display_blocksize(arguments);
+    let scaled = format_number(&format_scaled(bytes, display_divisor), number_format(arguments));
+    if arguments.is_present("ExactBytes") && blocksize != "Bytes" {
+        format!("{} {} ({} bytes)", scaled, blocksize, display_count(bytes, arguments))
+    } else {
+        format!("{} {}", scaled, blocksize)
+    }
+}
+
+pub fn print_estimate(estimate: &Estimate, error_paths: &[(PathBuf, std::io::Error)], arguments: &clap::ArgMatches){
+    if let Some("json") = arguments.value_of("Format"){
+        print_envelope("estimate", estimate);
+        return
+    }
+    println!("Up to {} duplicate groups of {} files could hold {} of duplicates.", display_count(estimate.groups, arguments), display_count(estimate.files, arguments), display_size(estimate.duplicate_bytes, arguments));
+    println!("This estimate groups files by length alone. Run without --estimate to hash and confirm duplicates.");
+    if !error_paths.is_empty(){
+        println!("{} paths could not be scanned.", error_paths.len());
+    }
+}
+
+pub fn print_duplicate_directories(groups: &[DirectoryGroup], arguments: &clap::ArgMatches){
+    if let Some("json") = arguments.value_of("Format"){
+        print_envelope("duplicate_directories", groups);
+        return
+    }
+    println!("{} Duplicate directory groups", groups.len());
+    groups.iter().for_each(|x| {
+        println!("{} directories each holding {} files, {}:", x.paths.len(), x.files, display_size(x.bytes, arguments));
+        x.paths.iter().for_each(|y| println!("\t{}", y.display()));
+    });
+}
+
+pub fn print_overlapping_directories(pairs: &[OverlappingDirectories], arguments: &clap::ArgMatches){
+    if let Some("json") = arguments.value_of("Format"){
+        print_envelope("overlapping_directories", pairs);
+        return
+    }
+    println!("{} Same-named directory pairs with overlapping contents", pairs.len());
+    pairs.iter().for_each(|x| {
+        println!("{:.0}% of {} contents shared:", x.overlap*100.0, display_count(x.contents as u64, arguments));
+        println!("\t{}", x.first.display());
+        println!("\t{}", x.second.display());
+    });
+}
+
+pub fn print_redundant_directories(directories: &[RedundantDirectory], arguments: &clap::ArgMatches){
+    if let Some("json") = arguments.value_of("Format"){
+        print_envelope("redundant_directories", directories);
+        return
+    }
+    println!("{} Directories holding copies of their own files", directories.len());
+    directories.iter().for_each(|x| println!("\t{} copies, {}: {}", x.duplicates, display_size(x.bytes, arguments), x.path.display()));
+}
+
+pub fn print_scan_stats(scan_stats: &ScanStats, arguments: &clap::ArgMatches){
+    let stats = scan_stats.length_stats().cloned().unwrap_or_default();
+    if let Some("json") = arguments.value_of("Format"){
+        print_fields(StatsFields{length_stats: &stats, vanished_files: scan_stats.vanished_files()});
+        return
+    }
+    if scan_stats.vanished_files() > 0 {
+        println!("{} files could not be read for their partial or full hash, and were probably deleted or moved during the scan", scan_stats.vanished_files());
+    }
+    println!("{} Length buckets", stats.buckets);
+    println!("\t{:>16}  {:>10}  {:>10}", "Files per bucket", "Buckets", "Files");
+    stats.histogram.iter().for_each(|x| {
+        let range = if x.min_files == 1 {"1".to_string()} else {format!("{}-{}", x.min_files, x.max_files)};
+        println!("\t{:>16}  {:>10}  {:>10}", range, x.buckets, x.files);
+    });
+    println!("Largest buckets");
+    stats.largest.iter().for_each(|x| println!("\t{} files of length {}", x.files, x.length));
+}
+
+pub fn print_near_duplicate_text(groups: &[TextGroup], arguments: &clap::ArgMatches){
+    if let Some("json") = arguments.value_of("Format"){
+        print_envelope("near_duplicate_text", groups);
+        return
+    }
+    println!("{} Near duplicate text groups", groups.len());
+    groups.iter().for_each(|x| {
+        println!("{} text files in {} variants matching once normalized:", x.paths.len(), x.variants);
+        x.paths.iter().for_each(|y| println!("\t{}", y.display()));
+    });
+}
+
+pub fn print_compressed_copies(copies: &[CompressedCopy], arguments: &clap::ArgMatches){
+    if let Some("json") = arguments.value_of("Format"){
+        print_envelope("compressed_copies", copies);
+        return
+    }
+    println!("{} Compressed files duplicating plain files", copies.len());
+    copies.iter().for_each(|x| {
+        println!("{} {:?} compressed and {} plain copies of {}:", x.compressed.len(), x.codec, x.plain.len(), display_size(x.length, arguments));
+        x.compressed.iter().chain(x.plain.iter()).for_each(|y| println!("\t{}", y.display()));
+    });
+}
+
+pub fn print_similar_names(groups: &[SimilarNames], arguments: &clap::ArgMatches){
+    if let Some("json") = arguments.value_of("Format"){
+        print_envelope("similar_names", groups);
+        return
+    }
+    println!("{} Groups of similar file names", groups.len());
+    groups.iter().for_each(|x| {
+        println!("{} files with similar names in {}:", x.names.len(), x.directory.display());
+        x.names.iter().for_each(|y| println!("\t{}", y));
+    });
+}
+
+pub fn print_largest_duplicates(shared_files: &[&Fileinfo], count: usize, arguments: &clap::ArgMatches){
+    let mut largest: Vec<&Fileinfo> = shared_files.to_vec();
+    largest.par_sort_by(|a, b| b.get_length().cmp(&a.get_length()).then_with(|| a.get_paths().cmp(b.get_paths())));
+    largest.truncate(count);
+    if let Some("json") = arguments.value_of("Format"){
+        print_envelope("largest_duplicates", &largest.iter().map(|x| SizedPaths{length: x.get_length(), paths: x.get_paths_sorted()}).collect::<Vec<_>>());
+        return
+    }
+    println!("{} Largest duplicates", largest.len());
+    largest.iter().for_each(|x| {
+        println!("\t{} copies of length {}:", x.get_paths().len(), x.get_length());
+        x.get_paths_sorted().iter().for_each(|y| println!("\t\t{}", y.display()));
+    });
+}
+
+// Paths which could not be read may hide differences, so the trees are only reported the same when every path was read
+pub fn print_tree_differences(differences: &TreeDifferences, error_paths: &[(PathBuf, std::io::Error)], search_dirs: &[&str], arguments: &clap::ArgMatches){
+    if let Some("json") = arguments.value_of("Format"){
+        print_fields(TreeDifferenceFields{
+            tree_differences: differences,
+            unread_paths: error_paths.iter().map(|x| x.0.to_string_lossy()).collect(),
+        });
+        return
+    }
+    if differences.is_empty() && error_paths.is_empty(){
+        println!("{} and {} hold the same contents", search_dirs[0], search_dirs[1]);
+        return
+    }
+    if !error_paths.is_empty(){
+        println!("{} paths could not be read, so the trees could not be compared in full", error_paths.len());
+        error_paths.iter().for_each(|x| println!("\t{}", x.0.display()));
+    }
+    println!("{} files only under {}", differences.only_first.len(), search_dirs[0]);
+    differences.only_first.iter().for_each(|x| println!("\t{}", x.display()));
+    println!("{} files only under {}", differences.only_second.len(), search_dirs[1]);
+    differences.only_second.iter().for_each(|x| println!("\t{}", x.display()));
+    println!("{} files differ in contents", differences.changed.len());
+    differences.changed.iter().for_each(|x| println!("\t{}", x.display()));
+}
+
+pub fn print_present_files(present: &[PresentFile], compare_dir: &str, arguments: &clap::ArgMatches){
+    if let Some("json") = arguments.value_of("Format"){
+        print_envelope("present_files", present);
+        return
+    }
+    println!("{} files already have a copy under {}", present.len(), compare_dir);
+    present.iter().for_each(|x| {
+        println!("\t{}", x.path.display());
+        x.copies.iter().for_each(|y| println!("\t\t{}", y.display()));
+    });
+}
+
+// One line per duplicate group, for external tools acting on the plan. Copies which are the kept file under another path are in neither list
+pub fn print_action_plan(shared_files: &[&Fileinfo], policy: &RetentionPolicy){
+    for file in shared_files.iter(){
+        let keep = match policy.choose(file) {
+            Some(keep) => keep,
+            None => continue,
+        };
+        let paths = file.get_paths();
+        print_json(|out| serde_json::to_writer(out, &PlannedAction{
+            keep_path: &paths[keep],
+            delete_paths: sorted_paths(action_targets(file, keep).into_iter().map(|x| &paths[x]).collect()),
+            size: file.get_length(),
+            hash: format!("{:032x}", file.get_full_hash().or(file.get_partial_hash()).unwrap_or_default()),
+        }));
+    }
+}
+
+pub fn print_new_duplicate(duplicate: &NewDuplicate, arguments: &clap::ArgMatches){
+    if let Some("json") = arguments.value_of("Format"){
+        print_json(|out| serde_json::to_writer(out, duplicate));
+        return
+    }
+    println!("New duplicate, {}: {}", display_size(duplicate.length, arguments), duplicate.path.display());
+    duplicate.copies.iter().for_each(|x| println!("\t{}", x.display()));
+}
+
+pub fn print_symlink_aliases(complete_files: &[Fileinfo], arguments: &clap::ArgMatches){
+    let mut aliases: Vec<&(PathBuf, PathBuf)> = complete_files.iter().flat_map(|x| x.get_aliases()).collect();
+    aliases.sort();
+    if let Some("json") = arguments.value_of("Format"){
+        print_envelope("symlink_aliases", &aliases.iter().map(|x| BTreeMap::from([("alias", &x.0), ("target", &x.1)])).collect::<Vec<_>>());
+        return
+    }
+    println!("{} Symlinks to scanned files, counted once as aliases", aliases.len());
+    aliases.iter().for_each(|x| println!("\t{} -> {}", x.0.display(), x.1.display()));
+}
+
+pub fn print_broken_symlinks(error_paths: &[(PathBuf, std::io::Error)], arguments: &clap::ArgMatches){
+    let broken: Vec<(&PathBuf, PathBuf)> = error_paths.iter()
+        .filter(|x| x.1.kind() == std::io::ErrorKind::NotFound)
+        .filter(|x| fs::symlink_metadata(&x.0).is_ok_and(|y| y.file_type().is_symlink()))
+        .map(|x| (&x.0, fs::read_link(&x.0).unwrap_or_default()))
+        .collect();
+    if let Some("json") = arguments.value_of("Format"){
+        print_envelope("broken_symlinks", &broken.iter().map(|x| BTreeMap::from([("path", x.0), ("target", &x.1)])).collect::<Vec<_>>());
+        return
+    }
+    println!("{} Broken symlinks", broken.len());
+    broken.iter().for_each(|x| println!("\t{} -> {}", x.0.display(), x.1.display()));
+}
+
+pub fn print_locked_files(error_paths: &[(PathBuf, std::io::Error)], arguments: &clap::ArgMatches){
+    let locked: Vec<&PathBuf> = error_paths.iter()
+        .filter(|x| x.1.kind() == std::io::ErrorKind::ResourceBusy)
+        .map(|x| &x.0)
+        .collect();
+    if let Some("json") = arguments.value_of("Format"){
+        print_envelope("locked_files", &locked);
+        return
+    }
+    println!("{} Locked files skipped", locked.len());
+    locked.iter().for_each(|x| println!("\t{}", x.display()));
+}
+
+pub fn print_manifest_matches(complete_files: &[Fileinfo], manifest: &Manifest, arguments: &clap::ArgMatches){
+    let matches: Vec<&Fileinfo> = complete_files.iter().filter(|x| manifest.contains(x)).collect();
+    if let Some("json") = arguments.value_of("Format"){
+        print_envelope("manifest_matches", &matches.iter().flat_map(|x| x.get_paths_sorted()).collect::<Vec<_>>());
+        return
+    }
+    println!("{} files found in manifest", matches.iter().map(|x| x.get_paths().len()).sum::<usize>());
+    matches.iter().for_each(|x| {
+        x.get_paths_sorted().iter().for_each(|y| println!("\t{}", y.display()));
+    });
+}
+
+pub fn writes_json_file(arguments: &clap::ArgMatches) -> bool{
+    match (arguments.value_of("Format"), arguments.value_of("Output").unwrap_or("Results.txt")) {
+        (_, "no") => false,
+        (Some(format), _) => format == "json",
+        (None, destination) => arguments.is_present("FormatFromExtension")
+            && Path::new(destination).extension().is_some_and(|x| x.eq_ignore_ascii_case("json")),
+    }
+}
+
+pub fn print_profile(profile: &Profile, total: Duration){
+    eprintln!("Time by stage. Hashing, grouping and comparing add up the time of every thread.");
+    for stage in Stage::ALL.iter(){
+        eprintln!("\t{:?}: {:.3} s", stage, profile.elapsed(*stage).as_secs_f64());
+    }
+    eprintln!("\tTotal: {:.3} s", total.as_secs_f64());
+}
+
+fn format_from_extension(file: &str) -> PrintFmt{
+    match Path::new(file).extension().map(|x| x.to_string_lossy().to_lowercase()).as_deref() {
+        Some("json") => PrintFmt::Json,
+        Some("txt") | None => PrintFmt::Standard,
+        Some(extension) => {
+            eprintln!("No output format matches .{} files. Writing the standard format.", extension);
+            PrintFmt::Standard
+        },
+    }
+}
+
+// Indices into `file.get_paths()` in the order of `get_paths_sorted`, for listings which need each path's position
+fn sorted_indices(file: &Fileinfo) -> Vec<usize>{
+    let paths = file.get_paths();
+    let mut order: Vec<usize> = (0..paths.len()).collect();
+    order.sort_by(|&a, &b| paths[a].cmp(&paths[b]));
+    order
+}
+
+fn sorted_paths(mut paths: Vec<&PathBuf>) -> Vec<&PathBuf>{
+    paths.sort();
+    paths
+}
+
+// Results which cannot be serialized, as when a path is not valid UTF-8, end the run rather than printing or writing a report missing them.
+// They are serialized into a sink first, so nothing reaches stdout before the whole report is known to serialize
+// Streams JSON to stdout, ending it with a newline as println does. A closed pipe ends the process quietly, as it does for the listing
+fn print_json<F: Fn(&mut dyn Write) -> serde_json::Result<()>>(write: F){
+    let mut out = BufWriter::with_capacity(OUTPUT_BUFFER_SIZE, std::io::stdout().lock());
+    let written = write(&mut std::io::sink()).and_then(|()| write(&mut out)).map_err(std::io::Error::from)
+        .and_then(|()| writeln!(out)).and_then(|()| out.flush());
+    match written {
+        Ok(()) => {},
+        Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => std::process::exit(0),
+        Err(e) => {
+            eprintln!("Error encountered serializing results to JSON. Err: {}", e);
+            std::process::exit(1);
+        },
+    }
+}
+
+fn print_envelope<T: serde::Serialize + ?Sized>(key: &str, value: &T){
+    print_fields(BTreeMap::from([(key, value)]));
+}
+
+fn print_fields<T: serde::Serialize>(fields: T){
+    print_json(|out| serde_json::to_writer(out, &Envelope{
+        schema: ddh::JSON_SCHEMA_VERSION,
+        generated_by: format!("ddh {}", env!("CARGO_PKG_VERSION")),
+        fields: &fields,
+    }));
+}
+
+// Groups are only formed without any hash by --compare-mode size-then-name, as grouped empty files carry the hash of no bytes
+fn matched_by_name_only(file: &Fileinfo) -> bool{
+    file.get_paths().len() > 1 && file.get_partial_hash().is_none() && !file.is_fully_hashed()
+}
+
+fn json_report<W: Write>(out: W, groups: &[&Fileinfo], duplicated_ratio: f64, partial: bool) -> serde_json::Result<()>{
+    serde_json::to_writer(out, &JsonReport{
+        schema: ddh::JSON_SCHEMA_VERSION,
+        generated_by: format!("ddh {}", env!("CARGO_PKG_VERSION")),
+        duplicated_ratio,
+        partial,
+        groups: groups.iter().map(|&x| JsonGroup{file: x, fully_hashed: x.is_fully_hashed(), content_unverified: matched_by_name_only(x)}).collect(),
+    })
+}
+
+// The map shape keys the paths of each duplicate group by hash, leaving out single files. Groups can share a hash, as when kept apart by name
+// or by a collision, so their keys are numbered in the order of their first paths to keep each group whole
+fn json_results<W: Write>(out: W, groups: &[&Fileinfo], shape: JsonShape, duplicated_ratio: f64, partial: bool) -> serde_json::Result<()>{
+    match shape {
+        JsonShape::Groups => json_report(out, groups, duplicated_ratio, partial),
+        JsonShape::Map => {
+            let mut by_hash: BTreeMap<String, Vec<Vec<&PathBuf>>> = BTreeMap::new();
+            for group in groups.iter().filter(|x| x.get_paths().len() > 1){
+                let hash = group.get_full_hash().or(group.get_partial_hash()).unwrap_or_default();
+                by_hash.entry(format!("{:032x}", hash)).or_default().push(group.get_paths_sorted());
+            }
+            let mut map: BTreeMap<String, Vec<&PathBuf>> = BTreeMap::new();
+            for (hash, mut shared) in by_hash.into_iter(){
+                if shared.len() == 1 {
+                    map.insert(hash, shared.pop().unwrap());
+                    continue
+                }
+                shared.sort();
+                for (i, paths) in shared.into_iter().enumerate(){
+                    map.insert(format!("{}-{}", hash, i + 1), paths);
+                }
+            }
+            serde_json::to_writer(out, &map)
+        },
+    }
+}
+
+/// Asks a yes or no question on the terminal. `--assume-yes` and `--assume-no` answer it without asking, and when stdin is not a terminal
+/// the answer is no, so a run in a script or pipeline never waits on a prompt.
+pub fn confirm(question: &str, fmt: PrintFmt, arguments: &clap::ArgMatches) -> bool{
+    if arguments.is_present("AssumeYes") {
+        print_note(fmt, format_args!("{} Y/N: yes, from --assume-yes", question));
+        return true
+    }
+    if arguments.is_present("AssumeNo") {
+        print_note(fmt, format_args!("{} Y/N: no, from --assume-no", question));
+        return false
+    }
+    if !stdin().is_terminal() {
+        print_note(fmt, format_args!("{} Y/N: no, as stdin is not a terminal. Pass --assume-yes to answer yes.", question));
+        return false
+    }
+    print_note(fmt, format_args!("{} Y/N", question));
+    let mut input = String::new();
+    match stdin().read_line(&mut input) {
+        Ok(_n) => matches!(input.trim_start().chars().next(), Some('y') | Some('Y')),
+        Err(e) => {
+            print_note(fmt, format_args!("Error encountered reading user input. Err: {}", e));
+            false
+        },
+    }
+}
+
+pub fn same_directory(a: &str, b: &str) -> bool{
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(x), Ok(y)) => x == y,
+        _ => a == b,
+    }
+}
+
+// Groups are written a whole group at a time, as the text must be encoded before it is written
+fn write_standard_report<W: Write>(out: &mut W, report: &StandardReport, append: bool, starts_file: bool) -> std::io::Result<()>{
+    use std::fmt::Write as _;
+    let mut text = String::new();
+    if append{
+        writeln!(text, "=== ddh report {} ===", ddh::utils::format_timestamp(SystemTime::now())).unwrap();
+    }
+    writeln!(text, "Duplicates:").unwrap();
+    for note in report.notes.iter(){
+        writeln!(text, "{}", note).unwrap();
+    }
+    // A byte order mark only opens the file, not each report appended to it
+    out.write_all(&report.encoding.encode(&text, starts_file))?;
+    for (heading, files) in [(None, report.shared), (Some("Singletons:"), report.unique)].iter(){
+        if let Some(heading) = heading {
+            out.write_all(&report.encoding.encode(&format!("{}\n", heading), false))?;
+        }
+        for file in files.iter(){
+            text.clear();
+            let paths = file.get_paths_sorted();
+            let title = paths.first().unwrap().file_name().unwrap().to_str().unwrap();
+            let prefix = if report.common_prefix {shared_prefix(&paths)} else {None};
+            match &prefix {
+                Some(prefix) => writeln!(text, "{} under {}", title, prefix.display()).unwrap(),
+                None => writeln!(text, "{}", title).unwrap(),
+            }
+            for entry in paths.iter(){
+                writeln!(text, "\t{}", relative_to(entry, prefix.as_deref()).to_str().unwrap()).unwrap();
+            }
+            out.write_all(&report.encoding.encode(&text, false))?;
+        }
+    }
+    Ok(())
+}
+
+// A report is streamed through a buffer rather than built whole in memory. An overwritten report is written beside the file and renamed over it,
+// and an appended one is cut back off on failure, so a failure partway through leaves any earlier report as it was
+fn write_results_to_file(fmt: PrintFmt, report: &Report, append: bool, file: &str) {
+    if let Report::Off = report {
+        return
+    }
+    let written = if append {
+        fs::OpenOptions::new().append(true).create(true).open(file).and_then(|output| {
+            let length = output.metadata()?.len();
+            let written = write_report(&output, report, true, length == 0);
+            if written.is_err() {
+                let _ = output.set_len(length);
+            }
+            written
+        })
+    } else {
+        let partial = format!("{}.partial", file);
+        let written = fs::File::create(&partial)
+            .and_then(|output| write_report(&output, report, false, true))
+            .and_then(|()| fs::rename(&partial, file));
+        if written.is_err() {
+            let _ = fs::remove_file(&partial);
+        }
+        written
+    };
+    if let Err(e) = written {
+        eprintln!("Error encountered writing results to {}. Err: {}", file, e);
+        std::process::exit(1);
+    }
+    print_note(fmt, format_args!("{:#?} results written to {}", fmt, file));
+}
+
+fn write_report(output: &fs::File, report: &Report, append: bool, starts_file: bool) -> std::io::Result<()>{
+    let mut out = BufWriter::with_capacity(OUTPUT_BUFFER_SIZE, output);
+    match report {
+        Report::Json{groups, shape, duplicated_ratio, partial} => json_results(&mut out, groups, *shape, *duplicated_ratio, *partial)?,
+        Report::Standard(standard) => write_standard_report(&mut out, standard, append, starts_file)?,
+        Report::Off => {},
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    // A group of two copies and a single file in a directory, with the directory canonicalized as listings print it
+    fn scanned() -> (tempfile::TempDir, PathBuf, Vec<Fileinfo>){
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        fs::create_dir(root.join("b")).unwrap();
+        fs::write(root.join("a.txt"), b"hello").unwrap();
+        fs::write(root.join("b").join("a.txt"), b"hello").unwrap();
+        fs::write(root.join("single.txt"), b"alone").unwrap();
+        let (files, _) = ddh::deduplicate_dirs(vec![&root]);
+        (dir, root, files)
+    }
+
+    fn listing(policy: &RetentionPolicy) -> Listing<'_>{
+        Listing{template: None, compact: false, policy, color: false, numbers: NumberFormat{thousands: Some(','), decimal: '.'}, inodes: false, common_prefix: false, separator: None}
+    }
+
+    fn shared(files: &[Fileinfo]) -> &Fileinfo{
+        files.iter().find(|x| x.get_paths().len() > 1).unwrap()
+    }
+
+    #[test]
+    fn lists_a_group_with_its_sorted_paths(){
+        let (_dir, root, files) = scanned();
+        let policy = RetentionPolicy::new(None, None);
+        let mut out = Vec::new();
+        write_group(&mut out, shared(&files), &listing(&policy)).unwrap();
+        let expected = format!("instances of {} with file length 5:\n\t{}\n\t{}\n", shared(&files).get_candidate_name(), root.join("a.txt").display(), root.join("b").join("a.txt").display());
+        assert_eq!(expected, String::from_utf8(out).unwrap());
+
+        let mut out = Vec::new();
+        write_group(&mut out, shared(&files), &Listing{common_prefix: true, ..listing(&policy)}).unwrap();
+        assert_eq!(format!("instances of {} with file length 5, under {}:\n\ta.txt\n\t{}\n", shared(&files).get_candidate_name(), root.display(), Path::new("b").join("a.txt").display()),
+            String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn lists_a_compact_group_on_one_line(){
+        let (_dir, root, files) = scanned();
+        let group = shared(&files);
+        let mut out = Vec::new();
+        write_compact_group(&mut out, group).unwrap();
+        assert_eq!(format!("2x 5 {:032x} {} [+1 more]\n", group.get_full_hash().unwrap(), root.join("a.txt").display()), String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn writes_standard_reports_with_duplicates_then_singletons(){
+        let (_dir, root, files) = scanned();
+        let (shared, unique): (Vec<&Fileinfo>, Vec<&Fileinfo>) = files.iter().partition(|x| x.get_paths().len() > 1);
+        let report = StandardReport{shared: &shared, unique: &unique, notes: &["Showing 1 of 1 duplicate groups"], common_prefix: false, encoding: TextEncoding::Utf8};
+        let mut out = Vec::new();
+        write_standard_report(&mut out, &report, false, true).unwrap();
+        let expected = format!("Duplicates:\nShowing 1 of 1 duplicate groups\na.txt\n\t{}\n\t{}\nSingletons:\nsingle.txt\n\t{}\n",
+            root.join("a.txt").display(), root.join("b").join("a.txt").display(), root.join("single.txt").display());
+        assert_eq!(expected, String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn writes_json_in_both_shapes(){
+        let (_dir, root, files) = scanned();
+        let groups: Vec<&Fileinfo> = files.iter().collect();
+        let mut out = Vec::new();
+        json_results(&mut out, &groups, JsonShape::Groups, 0.25, false).unwrap();
+        let report: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(ddh::JSON_SCHEMA_VERSION as u64, report["schema"].as_u64().unwrap());
+        assert_eq!(0.25, report["duplicated_ratio"].as_f64().unwrap());
+        assert_eq!(None, report.get("partial"));
+        assert_eq!(2, report["groups"].as_array().unwrap().len());
+        assert!(report["groups"].as_array().unwrap().iter().all(|x| x["fully_hashed"].is_boolean()));
+
+        let mut out = Vec::new();
+        json_results(&mut out, &groups, JsonShape::Map, 0.25, true).unwrap();
+        let map: BTreeMap<String, Vec<PathBuf>> = serde_json::from_slice(&out).unwrap();
+        assert_eq!(vec![vec![root.join("a.txt"), root.join("b").join("a.txt")]], map.into_values().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn keys_groups_sharing_a_hash_apart_in_the_map_shape(){
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        for tree in ["a", "b"].iter(){
+            fs::create_dir(root.join(tree)).unwrap();
+            fs::write(root.join(tree).join("config"), b"same").unwrap();
+            fs::write(root.join(tree).join("settings"), b"same").unwrap();
+        }
+        let (files, _) = ddh::deduplicate_dirs_with(vec![&root], &ddh::ScanOptions::builder().require_name_match(true).build());
+        let groups: Vec<&Fileinfo> = files.iter().collect();
+        let mut out = Vec::new();
+        json_results(&mut out, &groups, JsonShape::Map, 0.5, false).unwrap();
+        let map: BTreeMap<String, Vec<PathBuf>> = serde_json::from_slice(&out).unwrap();
+        let hash = format!("{:032x}", files[0].get_full_hash().unwrap());
+        assert_eq!(vec![format!("{}-1", hash), format!("{}-2", hash)], map.keys().cloned().collect::<Vec<_>>());
+        assert_eq!(vec![vec![root.join("a/config"), root.join("b/config")], vec![root.join("a/settings"), root.join("b/settings")]], map.into_values().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn numbers_the_output_file_of_each_root(){
+        assert_eq!("Results.1.txt", root_destination("Results.txt", 0));
+        assert_eq!(Path::new("out").join("report.3.json").to_string_lossy(), root_destination(&Path::new("out").join("report.json").to_string_lossy(), 2));
+        assert_eq!("results.2", root_destination("results", 1));
+        assert_eq!("no", root_destination("no", 4));
+    }
+}
@@ -0,0 +1,534 @@
+//! Directory traversal. Files found are sent over a channel and collected into buckets by length.
+//! Partial hashing of a length starts as soon as a second file of that length is found, overlapping with the rest of the traversal.
+//! Each directory is read by its own job in a rayon scope rather than by recursion, so the depth of a tree never grows the stack.
+
+use std::fs::{self, DirEntry};
+use std::path::{PathBuf, Path};
+use std::sync::Mutex;
+use std::sync::mpsc::{Sender, channel};
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::Entry;
+use std::io::{Error, ErrorKind};
+use std::time::SystemTime;
+use rayon::prelude::*;
+use rayon::{Scope, ThreadPool};
+use nohash_hasher::IntMap;
+use crate::{actions, dedup, Fileinfo, ScanOptions};
+use crate::spill::SpilledBuckets;
+use crate::treecache::CachedFile;
+
+enum ChannelPackage{
+    Success(Fileinfo),
+    Fail(PathBuf, std::io::Error),
+}
+
+/// Files found by a scan, bucketed by length.
+pub(crate) type LengthBuckets = IntMap<u64, Vec<Fileinfo>>;
+
+/// Traverses the search directories in `pool`. With `hash_early`, files sharing a length are partially hashed as they are found, unless symlinks are to be folded into their targets first.
+pub(crate) fn scan_dirs<P: AsRef<Path> + Sync>(search_dirs: Vec<P>, options: &ScanOptions, hash_early: bool, pool: &ThreadPool) -> (LengthBuckets, Vec<(PathBuf, std::io::Error)>){
+    let (sender, receiver) = channel();
+    let (hashed_sender, hashed_receiver) = channel();
+    let visited = Mutex::new(HashSet::new());
+    let mut files_of_lengths: LengthBuckets = IntMap::default();
+    let mut errors = Vec::new();
+    let hash_early = hash_early && !options.symlink_targets_once;
+    pool.in_place_scope(|scope| {
+        spawn_roots(&search_dirs, options, &visited, sender, scope);
+        // Holds the first file seen of each length until a second arrives, after which every file of that length is partially hashed while traversal continues
+        let mut first_of_lengths: IntMap<u64, Option<Fileinfo>> = IntMap::default();
+        let partial_hash = |file: Fileinfo| {
+            let hashed_sender = hashed_sender.clone();
+            scope.spawn(move |_| {
+                let mut file = file;
+                let pkg = match dedup::partial_hash(&mut file, options) {
+                    Some((path, e)) => ChannelPackage::Fail(path, e),
+                    None => ChannelPackage::Success(file),
+                };
+                hashed_sender.send(pkg).expect("Error sending partially hashed file");
+            });
+        };
+        for pkg in receiver.iter(){
+            match pkg{
+                ChannelPackage::Success(entry) if hash_early && entry.get_length()>0 && !options.content_only && !options.hash_only_full => {
+                    match first_of_lengths.entry(entry.get_length()) {
+                        Entry::Vacant(e) => { e.insert(Some(entry)); },
+                        Entry::Occupied(mut e) => {
+                            if let Some(first) = e.get_mut().take(){
+                                partial_hash(first);
+                            }
+                            partial_hash(entry);
+                        }
+                    }
+                },
+                ChannelPackage::Success(entry) => {
+                    add_to_bucket(&mut files_of_lengths, entry);
+                },
+                ChannelPackage::Fail(entry, error) => {
+                    errors.push((entry, error));
+                },
+            }
+        }
+        for entry in first_of_lengths.into_values().flatten(){
+            add_to_bucket(&mut files_of_lengths, entry);
+        }
+    });
+    drop(hashed_sender);
+    for pkg in hashed_receiver.iter(){
+        match pkg{
+            ChannelPackage::Success(entry) => add_to_bucket(&mut files_of_lengths, entry),
+            ChannelPackage::Fail(entry, error) => errors.push((entry, error)),
+        }
+    }
+    if options.symlink_targets_once {
+        fold_symlinks(&mut files_of_lengths);
+    }
+    (files_of_lengths, errors)
+}
+
+/// Traverses the search directories in `pool` as `scan_dirs` does, but holds at most `limit` files in memory and spills the rest to disk.
+/// Files are left unhashed, since hashing them as they are found would need every file of a length at hand.
+pub(crate) fn scan_dirs_spilled<P: AsRef<Path> + Sync>(search_dirs: Vec<P>, options: &ScanOptions, limit: usize, pool: &ThreadPool) -> (SpilledBuckets, Vec<(PathBuf, std::io::Error)>){
+    let (sender, receiver) = channel();
+    let visited = Mutex::new(HashSet::new());
+    let mut spilled = SpilledBuckets::new(limit);
+    let mut errors = Vec::new();
+    pool.in_place_scope(|scope| {
+        spawn_roots(&search_dirs, options, &visited, sender, scope);
+        for pkg in receiver.iter(){
+            match pkg{
+                ChannelPackage::Success(entry) => errors.extend(spilled.add(entry)),
+                ChannelPackage::Fail(entry, error) => errors.push((entry, error)),
+            }
+        }
+    });
+    (spilled, errors)
+}
+
+fn spawn_roots<'scope, P: AsRef<Path>>(search_dirs: &[P], options: &'scope ScanOptions, visited: &'scope Mutex<HashSet<PathBuf>>, sender: Sender<ChannelPackage>, scope: &Scope<'scope>){
+    for (root, search_dir) in search_dirs.iter().enumerate(){
+        let search_dir = search_dir.as_ref().to_path_buf();
+        let sender = sender.clone();
+        scope.spawn(move |scope| {
+            if is_pseudo_filesystem(&search_dir){
+                sender.send(
+                ChannelPackage::Fail(search_dir, Error::other("Path is on a pseudo-filesystem"))
+                ).expect("Error sending new ChannelPackage::Fail");
+                return
+            }
+            let root_device = fs::metadata(&search_dir).ok().and_then(|x| device_of(&x));
+            traverse_and_spawn(&search_dir, root, root_device, options, visited, sender, scope);
+        });
+    }
+}
+
+/// Folds symlinks into the files they lead to, as `ScanOptions::symlink_targets_once` asks, within each bucket of more than one file.
+pub(crate) fn fold_symlinks(files_of_lengths: &mut LengthBuckets){
+    files_of_lengths.par_iter_mut()
+        .filter(|x| x.1.len() > 1)
+        .for_each(|x| *x.1 = fold_symlink_aliases(std::mem::take(x.1)));
+}
+
+// Folds each symlink leading to a file found by another path into that file as an alias. Real paths are taken first, and then paths in order,
+// so a symlink is only kept as a file of its own when its target was not found, and then only the first symlink to it
+fn fold_symlink_aliases(files: Vec<Fileinfo>) -> Vec<Fileinfo>{
+    let mut files: Vec<(bool, Fileinfo)> = files.into_iter().map(|x| (actions::is_symlink(&x.get_paths()[0]), x)).collect();
+    files.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| a.1.get_paths().cmp(b.1.get_paths())));
+    let mut folded: Vec<Fileinfo> = Vec::new();
+    let mut found = HashMap::new();
+    for (symlink, mut file) in files.into_iter(){
+        let identity = actions::file_identity(&file.get_paths()[0]);
+        match identity.and_then(|x| found.get(&x).copied()) {
+            Some(index) if symlink => {
+                let target: &mut Fileinfo = &mut folded[index];
+                let alias = (file.file_paths.remove(0), target.file_paths[0].clone());
+                target.aliases.push(alias);
+            },
+            _ => {
+                if let Some(identity) = identity {
+                    found.entry(identity).or_insert(folded.len());
+                }
+                folded.push(file);
+            },
+        }
+    }
+    folded
+}
+
+fn add_to_bucket(files_of_lengths: &mut LengthBuckets, entry: Fileinfo){
+    match files_of_lengths.entry(entry.get_length()) {
+        Entry::Vacant(e) => { e.insert(vec![entry]); },
+        Entry::Occupied(mut e) => { e.get_mut().push(entry); }
+    }
+}
+
+fn traverse_and_spawn<'scope>(current_path: &Path, root: usize, parent_device: Option<u64>, options: &'scope ScanOptions, visited: &'scope Mutex<HashSet<PathBuf>>, sender: Sender<ChannelPackage>, scope: &Scope<'scope>){
+    if options.is_stopped() || options.budget.as_ref().is_some_and(|x| x.is_exhausted()){
+        return
+    }
+    let mut current_path_metadata = match fs::symlink_metadata(current_path) {
+        Err(e) =>{
+            sender.send(
+            ChannelPackage::Fail(current_path.to_path_buf(), e)
+            ).expect("Error sending new ChannelPackage::Fail");
+            return
+        },
+        Ok(meta) => meta,
+    };
+
+    if current_path_metadata.file_type().is_symlink(){
+        if !options.follow_symlinks{
+            let error = match fs::metadata(current_path) {
+                Err(e) if e.kind() == ErrorKind::NotFound => Error::new(ErrorKind::NotFound, "Symlink target does not exist"),
+                _ => Error::other("Path is symlink"),
+            };
+            sender.send(
+            ChannelPackage::Fail(current_path.to_path_buf(), error)
+            ).expect("Error sending new ChannelPackage::Fail");
+            return
+        }
+        current_path_metadata = match fs::metadata(current_path) {
+            Err(e) =>{
+                sender.send(
+                ChannelPackage::Fail(current_path.to_path_buf(), e)
+                ).expect("Error sending new ChannelPackage::Fail");
+                return
+            },
+            Ok(meta) => meta,
+        };
+        if options.one_file_system && device_of(&current_path_metadata) != parent_device{
+            sender.send(
+            ChannelPackage::Fail(current_path.to_path_buf(), Error::other("Symlink leads to another filesystem"))
+            ).expect("Error sending new ChannelPackage::Fail");
+            return
+        }
+    }
+
+    // Pseudo-filesystems are mounted, so only paths on a different device from their parent need checking
+    if device_of(&current_path_metadata) != parent_device && is_pseudo_filesystem(current_path){
+        sender.send(
+        ChannelPackage::Fail(current_path.to_path_buf(), Error::other("Path is on a pseudo-filesystem"))
+        ).expect("Error sending new ChannelPackage::Fail");
+        return
+    }
+
+    if current_path_metadata.file_type().is_file(){
+        send_file(current_path.to_path_buf(), &current_path_metadata, root, options, &sender);
+        return
+    }
+
+    if current_path_metadata.file_type().is_dir(){
+        let device = device_of(&current_path_metadata);
+        if device != parent_device && options.one_file_system && !options.follow_mount_points{
+            sender.send(
+            ChannelPackage::Fail(current_path.to_path_buf(), Error::other("Path is a mount point"))
+            ).expect("Error sending new ChannelPackage::Fail");
+            return
+        }
+        if options.follow_symlinks{
+            let first_visit = match fs::canonicalize(current_path) {
+                Ok(canonical_path) => visited.lock().unwrap().insert(canonical_path),
+                Err(_e) => true,
+            };
+            if !first_visit{
+                return
+            }
+        }
+        if let Some((files, others)) = options.tree_cache.as_ref().and_then(|x| x.lookup(current_path, &current_path_metadata)){
+            files.into_par_iter().filter(|x| !options.is_excluded(&x.path)).for_each_with(sender.clone(), |sender, x| {
+                let modified = x.modified();
+                send_found(x.path, x.length, modified, x.sparse, root, options, sender)
+            });
+            spawn_dirs(others.into_iter().filter(|x| !options.is_excluded(x)).collect(), root, device, options, visited, sender, scope);
+            return
+        }
+        match fs::read_dir(current_path) {
+                Ok(read_dir_results) => {
+                    // A recorded listing holds excluded entries too, so it serves later scans excluding less
+                    let recording = options.tree_cache.is_some();
+                    let good_entries: Vec<_> = read_dir_results
+                    .filter_map(|x| x.ok())
+                    .filter(|x| recording || !options.is_excluded(&x.path()))
+                    .collect();
+                    let (files, dirs): (Vec<&DirEntry>, Vec<&DirEntry>) = good_entries.par_iter().partition(|&x|
+                        x.file_type()
+                        .expect("Error reading DirEntry file type")
+                        .is_file()
+                        );
+                    let files: Vec<(PathBuf, fs::Metadata)> = files.par_iter().map(|x| (x.path(), x.metadata().expect("Error reading path length"))).collect();
+                    let dirs: Vec<PathBuf> = dirs.into_iter().map(|x| x.path()).collect();
+                    if let Some(tree_cache) = &options.tree_cache{
+                        let listed = files.iter().map(|(path, metadata)| CachedFile::new(path.clone(), metadata, is_sparse(metadata))).collect();
+                        tree_cache.record(current_path, &current_path_metadata, listed, dirs.clone());
+                    }
+                    files.into_par_iter().filter(|x| !recording || !options.is_excluded(&x.0)).for_each_with(sender.clone(), |sender, (path, metadata)|
+                        send_file(path, &metadata, root, options, sender)
+                        );
+                    spawn_dirs(dirs.into_iter().filter(|x| !recording || !options.is_excluded(x)).collect(), root, device, options, visited, sender, scope);
+                },
+                Err(e) => {
+                    sender.send(
+                        ChannelPackage::Fail(current_path.to_path_buf(), e)
+                        ).expect("Error sending new ChannelPackage::Fail");
+                },
+            }
+        return
+    }
+
+    // FIFOs, sockets and devices have no meaningful length, and reading some of them blocks forever
+    sender.send(
+    ChannelPackage::Fail(current_path.to_path_buf(), Error::new(ErrorKind::Unsupported, special_file_kind(&current_path_metadata.file_type())))
+    ).expect("Error sending new ChannelPackage::Fail");
+}
+
+fn spawn_dirs<'scope>(dirs: Vec<PathBuf>, root: usize, device: Option<u64>, options: &'scope ScanOptions, visited: &'scope Mutex<HashSet<PathBuf>>, sender: Sender<ChannelPackage>, scope: &Scope<'scope>){
+    for path in dirs.into_iter(){
+        let sender = sender.clone();
+        scope.spawn(move |scope| traverse_and_spawn(&path, root, device, options, visited, sender, scope));
+    }
+}
+
+fn send_file(path: PathBuf, metadata: &fs::Metadata, root: usize, options: &ScanOptions, sender: &Sender<ChannelPackage>){
+    send_found(path, metadata.len(), metadata.modified().ok(), is_sparse(metadata), root, options, sender)
+}
+
+// Sends a file found by reading its directory or from a cached listing, unless the options leave it out
+fn send_found(path: PathBuf, length: u64, modified: Option<SystemTime>, sparse: bool, root: usize, options: &ScanOptions, sender: &Sender<ChannelPackage>){
+    if options.is_size_excluded(length) || options.is_extension_excluded(&path) || options.is_path_filtered(&path){
+        return
+    }
+    if sparse && options.skip_sparse{
+        sender.send(
+        ChannelPackage::Fail(path, Error::other("Path is a sparse file"))
+        ).expect("Error sending new ChannelPackage::Fail");
+        return
+    }
+    if options.skip_locked && is_locked(&path){
+        sender.send(
+        ChannelPackage::Fail(path, Error::new(ErrorKind::ResourceBusy, "File is locked by another process"))
+        ).expect("Error sending new ChannelPackage::Fail");
+        return
+    }
+    if options.budget.as_ref().is_some_and(|x| !x.spend(length)){
+        return
+    }
+    sender.send(ChannelPackage::Success(
+        Fileinfo::new(None, None, length, path).with_root(root).with_modified(modified).with_sparse(sparse))
+        ).expect("Error sending new ChannelPackage::Success");
+}
+
+#[cfg(unix)]
+fn is_sparse(metadata: &fs::Metadata) -> bool{
+    use std::os::unix::fs::MetadataExt;
+    metadata.blocks()*512 < metadata.len()
+}
+
+#[cfg(not(unix))]
+fn is_sparse(_metadata: &fs::Metadata) -> bool{
+    false
+}
+
+#[cfg(target_os = "linux")]
+fn is_locked(path: &Path) -> bool{
+    use std::os::unix::io::AsRawFd;
+
+    let file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(_e) => return false,
+    };
+    // Asks whether another process's fcntl lock would block a read lock over the whole file
+    let mut lock: libc::flock = unsafe {std::mem::zeroed()};
+    lock.l_type = libc::F_RDLCK as libc::c_short;
+    lock.l_whence = libc::SEEK_SET as libc::c_short;
+    if unsafe {libc::fcntl(file.as_raw_fd(), libc::F_GETLK, &mut lock as *mut libc::flock)} == 0 && lock.l_type != libc::F_UNLCK as libc::c_short {
+        return true
+    }
+    // flock locks cannot be queried, so a shared lock is taken without waiting and released straight away
+    if unsafe {libc::flock(file.as_raw_fd(), libc::LOCK_SH | libc::LOCK_NB)} != 0 {
+        return Error::last_os_error().kind() == ErrorKind::WouldBlock
+    }
+    unsafe {libc::flock(file.as_raw_fd(), libc::LOCK_UN)};
+    false
+}
+
+#[cfg(windows)]
+fn is_locked(path: &Path) -> bool{
+    use std::os::windows::fs::OpenOptionsExt;
+
+    const FILE_SHARE_READ: u32 = 0x1;
+    const ERROR_SHARING_VIOLATION: i32 = 32;
+    const ERROR_LOCK_VIOLATION: i32 = 33;
+    // Opening fails while another process has the file open for writing, as only sharing with readers is allowed
+    match fs::OpenOptions::new().read(true).share_mode(FILE_SHARE_READ).open(path) {
+        Ok(_file) => false,
+        Err(e) => matches!(e.raw_os_error(), Some(ERROR_SHARING_VIOLATION) | Some(ERROR_LOCK_VIOLATION)),
+    }
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+fn is_locked(_path: &Path) -> bool{
+    false
+}
+
+#[cfg(unix)]
+fn special_file_kind(file_type: &fs::FileType) -> &'static str{
+    use std::os::unix::fs::FileTypeExt;
+    if file_type.is_fifo() {
+        "Path is a FIFO"
+    } else if file_type.is_socket() {
+        "Path is a socket"
+    } else if file_type.is_block_device() || file_type.is_char_device() {
+        "Path is a device"
+    } else {
+        "Path is not a regular file"
+    }
+}
+
+#[cfg(not(unix))]
+fn special_file_kind(_file_type: &fs::FileType) -> &'static str{
+    "Path is not a regular file"
+}
+
+// Kernel filesystems such as procfs and sysfs, whose files report lengths unrelated to their contents and may block when read
+#[cfg(target_os = "linux")]
+fn is_pseudo_filesystem(path: &Path) -> bool{
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    const PSEUDO_FILESYSTEMS: [u32; 14] = [
+        0x9fa0, // proc
+        0x62656572, // sysfs
+        0x64626720, // debugfs
+        0x74726163, // tracefs
+        0x73636673, // securityfs
+        0x27e0eb, // cgroup
+        0x63677270, // cgroup2
+        0x1cd1, // devpts
+        0xcafe4a11, // bpf
+        0x6165676c, // pstore
+        0x62656570, // configfs
+        0xde5e81e4, // efivarfs
+        0x65735543, // fusectl
+        0x42494e4d, // binfmt_misc
+    ];
+    let path = match CString::new(path.as_os_str().as_bytes()) {
+        Ok(path) => path,
+        Err(_e) => return false,
+    };
+    let mut stat: libc::statfs = unsafe {std::mem::zeroed()};
+    if unsafe {libc::statfs(path.as_ptr(), &mut stat)} != 0 {
+        return false
+    }
+    PSEUDO_FILESYSTEMS.contains(&(stat.f_type as u32))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn is_pseudo_filesystem(_path: &Path) -> bool{
+    false
+}
+
+#[cfg(unix)]
+fn device_of(metadata: &fs::Metadata) -> Option<u64>{
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.dev())
+}
+
+#[cfg(not(unix))]
+fn device_of(_metadata: &fs::Metadata) -> Option<u64>{
+    None
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn pool() -> ThreadPool{
+        rayon::ThreadPoolBuilder::new().num_threads(2).build().unwrap()
+    }
+
+    fn bucket_sizes(buckets: &LengthBuckets) -> Vec<(u64, usize)>{
+        let mut sizes: Vec<(u64, usize)> = buckets.iter().map(|x| (*x.0, x.1.len())).collect();
+        sizes.sort();
+        sizes
+    }
+
+    #[test]
+    fn buckets_files_by_length(){
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("nested/deeper")).unwrap();
+        fs::write(dir.path().join("a"), b"abc").unwrap();
+        fs::write(dir.path().join("nested/deeper/b"), b"xyz").unwrap();
+        fs::write(dir.path().join("nested/c"), b"abcde").unwrap();
+        fs::write(dir.path().join("empty"), b"").unwrap();
+        let (buckets, errors) = scan_dirs(vec![dir.path()], &ScanOptions::default(), false, &pool());
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(vec![(0, 1), (3, 2), (5, 1)], bucket_sizes(&buckets));
+        assert!(buckets.values().flatten().all(|x| x.get_partial_hash().is_none()));
+    }
+
+    #[test]
+    fn hashes_early_only_lengths_found_twice(){
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a"), b"abc").unwrap();
+        fs::write(dir.path().join("b"), b"xyz").unwrap();
+        fs::write(dir.path().join("c"), b"abcde").unwrap();
+        let (buckets, _) = scan_dirs(vec![dir.path()], &ScanOptions::default(), true, &pool());
+        assert!(buckets[&3].iter().all(|x| x.get_partial_hash().is_some()));
+        assert_ne!(buckets[&3][0].get_partial_hash(), buckets[&3][1].get_partial_hash());
+        assert_eq!(None, buckets[&5][0].get_partial_hash());
+    }
+
+    #[test]
+    fn records_roots_and_leaves_out_excluded_files(){
+        let first = tempfile::tempdir().unwrap();
+        let second = tempfile::tempdir().unwrap();
+        fs::write(first.path().join("a"), b"abc").unwrap();
+        fs::write(second.path().join("b"), b"abc").unwrap();
+        fs::write(second.path().join("b.tmp"), b"abc").unwrap();
+        let options = ScanOptions::builder().exclude(glob::Pattern::new("*.tmp").unwrap()).build();
+        let (buckets, _) = scan_dirs(vec![first.path(), second.path()], &options, false, &pool());
+        let mut roots: Vec<(PathBuf, usize)> = buckets[&3].iter().map(|x| (x.get_paths()[0].clone(), x.get_roots()[0])).collect();
+        roots.sort();
+        let mut expected = vec![(first.path().join("a"), 0), (second.path().join("b"), 1)];
+        expected.sort();
+        assert_eq!(expected, roots);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reports_symlinks_and_missing_roots_as_errors(){
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a"), b"abc").unwrap();
+        std::os::unix::fs::symlink(dir.path().join("a"), dir.path().join("link")).unwrap();
+        let missing = dir.path().join("missing");
+        let (buckets, errors) = scan_dirs(vec![dir.path().to_path_buf(), missing.clone()], &ScanOptions::default(), false, &pool());
+        assert_eq!(vec![(3, 1)], bucket_sizes(&buckets));
+        let mut failed: Vec<&PathBuf> = errors.iter().map(|x| &x.0).collect();
+        failed.sort();
+        assert_eq!(vec![&dir.path().join("link"), &missing], failed);
+    }
+
+    // Levels past the platform's path length are reported as errors rather than searched, but a tree far deeper than that is still walked
+    // without growing the stack. The tree is built relative to each level's handle, as its paths are too long to create it by name
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn walks_a_tree_ten_thousand_levels_deep(){
+        use std::ffi::CString;
+        use std::os::unix::io::{AsRawFd, FromRawFd};
+        let dir = tempfile::tempdir().unwrap();
+        let name = CString::new("d").unwrap();
+        let mut level = fs::File::open(dir.path()).unwrap();
+        for _ in 0..10_000 {
+            assert_eq!(0, unsafe {libc::mkdirat(level.as_raw_fd(), name.as_ptr(), 0o755)});
+            let fd = unsafe {libc::openat(level.as_raw_fd(), name.as_ptr(), libc::O_RDONLY | libc::O_DIRECTORY)};
+            assert!(fd >= 0);
+            level = unsafe {fs::File::from_raw_fd(fd)};
+        }
+        let shallow = dir.path().join("d/".repeat(100)).join("f");
+        fs::write(&shallow, b"abc").unwrap();
+        let (buckets, errors) = scan_dirs(vec![dir.path()], &ScanOptions::default(), false, &pool());
+        assert_eq!(vec![&shallow], buckets[&3].iter().flat_map(|x| x.get_paths()).collect::<Vec<_>>());
+        assert!(!errors.is_empty());
+        assert!(errors.iter().all(|x| x.1.raw_os_error() == Some(libc::ENAMETOOLONG)), "{:?}", &errors[..1]);
+    }
+}
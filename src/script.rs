@@ -0,0 +1,136 @@
+//! Shell scripts holding the commands an action would run, for reviewing deletions and links before running them by hand.
+
+use std::path::Path;
+use crate::actions::Action;
+
+/// A shell a script is written for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell{
+    /// Bash, using `rm` and `ln`.
+    Bash,
+    /// PowerShell, using `Remove-Item` and `New-Item`.
+    PowerShell,
+}
+
+impl Shell{
+    /// Picks PowerShell for a script file ending in `.ps1`, and Bash otherwise.
+    pub fn from_path(path: &Path) -> Self{
+        match path.extension() {
+            Some(x) if x.eq_ignore_ascii_case("ps1") => Shell::PowerShell,
+            _ => Shell::Bash,
+        }
+    }
+    /// Quotes a path as one literal argument. Bash paths holding control characters or bytes that are not UTF-8 are written in `$'...'` form with those bytes escaped.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::path::Path;
+    /// use ddh::script::Shell;
+    ///
+    /// assert_eq!("'my file.txt'", Shell::Bash.quote(Path::new("my file.txt")));
+    /// assert_eq!(r#"'it'\''s $HOME "here"'"#, Shell::Bash.quote(Path::new(r#"it's $HOME "here""#)));
+    /// assert_eq!(r"$'two\nlines\\'", Shell::Bash.quote(Path::new("two\nlines\\")));
+    /// assert_eq!("'it''s $HOME'", Shell::PowerShell.quote(Path::new("it's $HOME")));
+    /// ```
+    pub fn quote(&self, path: &Path) -> String{
+        match self {
+            Shell::Bash => {
+                let bytes = path_bytes(path);
+                match std::str::from_utf8(&bytes) {
+                    Ok(text) if !text.chars().any(|x| x.is_control()) => format!("'{}'", text.replace('\'', r"'\''")),
+                    _ => {
+                        let escaped: String = bytes.iter().map(|&x| match x {
+                            b'\\' => r"\\".to_string(),
+                            b'\'' => r"\'".to_string(),
+                            b'\n' => r"\n".to_string(),
+                            b'\t' => r"\t".to_string(),
+                            0x20..=0x7e => (x as char).to_string(),
+                            _ => format!(r"\x{:02x}", x),
+                        }).collect();
+                        format!("$'{}'", escaped)
+                    },
+                }
+            },
+            // PowerShell also ends a single quoted string at typographic single quotes, which are doubled the same way
+            Shell::PowerShell => {
+                let quoted: String = path.to_string_lossy().chars().flat_map(|x| match x {
+                    '\'' | '\u{2018}' | '\u{2019}' | '\u{201a}' | '\u{201b}' => vec![x, x],
+                    _ => vec![x],
+                }).collect();
+                format!("'{}'", quoted)
+            },
+        }
+    }
+    fn header(&self) -> String{
+        let comment = format!("# Written by ddh {}. Review every command before running this script.", env!("CARGO_PKG_VERSION"));
+        match self {
+            Shell::Bash => format!("#!/usr/bin/env bash\n{}\n", comment),
+            Shell::PowerShell => format!("{}\n", comment),
+        }
+    }
+    fn command(&self, action: Action, keep: &Path, target: &Path) -> Option<String>{
+        let (keep, target) = (self.quote(keep), self.quote(target));
+        match (self, action) {
+            (Shell::Bash, Action::Delete) => Some(format!("rm -- {}", target)),
+            (Shell::Bash, Action::Hardlink) => Some(format!("ln -f -- {} {}", keep, target)),
+            (Shell::PowerShell, Action::Delete) => Some(format!("Remove-Item -LiteralPath {}", target)),
+            (Shell::PowerShell, Action::Hardlink) => Some(format!("try {{ Remove-Item -LiteralPath {} -ErrorAction Stop; New-Item -ItemType HardLink -Path {} -Target {} | Out-Null }} catch {{ Write-Error $_ }}", target, target, keep)),
+            (_, Action::Reflink) => None,
+        }
+    }
+}
+
+/// A script of commands, built up one duplicate copy at a time.
+#[derive(Debug, Clone)]
+pub struct Script{
+    shell: Shell,
+    text: String,
+}
+
+impl Script{
+    /// Starts a script for `shell`.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::path::Path;
+    /// use ddh::actions::Action;
+    /// use ddh::script::{Script, Shell};
+    ///
+    /// let mut script = Script::new(Shell::Bash);
+    /// assert!(script.add(Action::Delete, Path::new("/photos/cat.jpg"), Path::new("/backup/cat (1).jpg")));
+    /// assert!(script.add(Action::Hardlink, Path::new("/photos/dog.jpg"), Path::new("/backup/dog.jpg")));
+    /// assert!(!script.add(Action::Reflink, Path::new("/photos/cat.jpg"), Path::new("/backup/cat.jpg")));
+    /// assert!(script.as_str().starts_with("#!/usr/bin/env bash\n"));
+    /// assert!(script.as_str().contains("\n# keep '/photos/cat.jpg'\nrm -- '/backup/cat (1).jpg'\n"));
+    /// assert!(script.as_str().ends_with("\nln -f -- '/photos/dog.jpg' '/backup/dog.jpg'\n"));
+    /// ```
+    pub fn new(shell: Shell) -> Self{
+        Script{shell, text: shell.header()}
+    }
+    /// Adds the command applying `action` to `target`, a duplicate of `keep`, after a comment naming the copy kept.
+    /// Returns false, adding nothing, for actions no shell command performs.
+    pub fn add(&mut self, action: Action, keep: &Path, target: &Path) -> bool{
+        match self.shell.command(action, keep, target) {
+            Some(command) => {
+                self.text.push_str(&format!("# keep {}\n{}\n", self.shell.quote(keep), command));
+                true
+            },
+            None => false,
+        }
+    }
+    /// Gets the script's text.
+    pub fn as_str(&self) -> &str{
+        &self.text
+    }
+}
+
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> Vec<u8>{
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+fn path_bytes(path: &Path) -> Vec<u8>{
+    path.to_string_lossy().into_owned().into_bytes()
+}
@@ -0,0 +1,111 @@
+//! Known answer checks of the hashing path, for confirming a build hashes as every other build does before trusting it with destructive actions.
+//! Each vector is hashed in memory and again from a file through the same reads a scan makes, so byte order and buffering are covered along with the algorithm.
+
+use std::fs;
+use std::io::Error;
+use std::path::Path;
+use crate::{ContentHasher, Fileinfo, HashAlgorithm, HashMode, ScanOptions, PARALLEL_READ_SIZE};
+
+/// The outcome of one check, with hashes written as the hex of their bytes, as reference implementations print them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check{
+    /// What was hashed and how.
+    pub name: String,
+    /// The hash every correct build gives.
+    pub expected: String,
+    /// The hash this build gave.
+    pub found: String,
+}
+
+impl Check{
+    /// Checks whether this build gave the expected hash.
+    pub fn passed(&self) -> bool{
+        self.expected == self.found
+    }
+}
+
+struct Vector{
+    name: &'static str,
+    literal: Option<&'static [u8]>,
+    length: usize,
+    sip128: &'static str,
+    blake3: &'static str,
+}
+
+// Inputs alongside their SipHash-2-4-128 under the zero key and their BLAKE3 digests truncated to 128 bits. The SipHash values were computed by an
+// independent implementation checked against the reference vectors. The BLAKE3 values for the empty, one byte and abc inputs are the published ones,
+// and those for the longer patterns come from the reference implementation. Patterns repeat the bytes 0 to 250, as in the BLAKE3 test vectors
+const VECTORS: [Vector; 5] = [
+    Vector{name: "empty input", literal: None, length: 0, sip128: "5049d74780a3e07d4202ab47d4cef2f4", blake3: "af1349b9f5f9a1a6a0404dea36dcc949"},
+    Vector{name: "one byte", literal: None, length: 1, sip128: "d41e12e85e4cafb831f094e57538b801", blake3: "2d3adedff11b61f14c886e35afa03673"},
+    Vector{name: "abc", literal: Some(b"abc"), length: 3, sip128: "6c95dec302962fa8ca5e69c1d5d15478", blake3: "6437b3ac38465133ffb63b75273a8db5"},
+    Vector{name: "1000 byte pattern", literal: None, length: 1000, sip128: "33ea5f1ec6f53612beecd3b89f082e9f", blake3: "b43670a52d1af24abdac5d2c3ed19ff4"},
+    Vector{name: "100000 byte pattern", literal: None, length: 100_000, sip128: "2beeed971374a7400cf9f470ac7df240", blake3: "d93c23eedaf165a7e0be908ba86f1a7a"},
+];
+
+/// Runs every check for `algorithm`, returning each outcome. Vectors are also written to temporary files and hashed as a scan would hash them,
+/// and BLAKE3 is checked to give the same hash when a large file is split across threads. Errors are those writing or reading the temporary files.
+///
+/// # Examples
+/// ```
+/// use ddh::HashAlgorithm;
+/// use ddh::selftest;
+///
+/// for algorithm in [HashAlgorithm::Sip128, HashAlgorithm::Blake3].iter() {
+///     let checks = selftest::run(*algorithm).unwrap();
+///     assert!(checks.len() >= 10);
+///     assert!(checks.iter().all(|x| x.passed()), "{:?}", checks);
+/// }
+/// ```
+pub fn run(algorithm: HashAlgorithm) -> Result<Vec<Check>, Error>{
+    let dir = std::env::temp_dir().join(format!("ddh-self-test-{}-{:?}", std::process::id(), algorithm));
+    fs::create_dir_all(&dir)?;
+    let checks = run_in(&dir, algorithm);
+    let _ = fs::remove_dir_all(&dir);
+    checks
+}
+
+fn run_in(dir: &Path, algorithm: HashAlgorithm) -> Result<Vec<Check>, Error>{
+    let options = ScanOptions::builder().hash_algorithm(algorithm).build();
+    let mut checks = Vec::new();
+    for vector in VECTORS.iter(){
+        let (name, data) = (vector.name, vector.literal.map_or_else(|| pattern(vector.length), |x| x.to_vec()));
+        let expected = match algorithm {
+            HashAlgorithm::Sip128 => vector.sip128.to_string(),
+            HashAlgorithm::Blake3 => vector.blake3.to_string(),
+        };
+        checks.push(Check{name: format!("{}, in memory", name), expected: expected.clone(), found: hex(in_memory(algorithm, &data))});
+        let path = dir.join(checks.len().to_string());
+        fs::write(&path, &data)?;
+        let mut file = Fileinfo::new(None, None, data.len() as u64, path);
+        checks.push(Check{name: format!("{}, read from a file", name), expected: expected.clone(), found: hex(file.generate_hash(HashMode::Full, &options)?.unwrap_or_default())});
+        // The partial hash reads the whole of a file this short
+        if data.len() <= 4096 {
+            checks.push(Check{name: format!("{}, partial hash", name), expected, found: hex(file.generate_hash(HashMode::Partial, &options)?.unwrap_or_default())});
+        }
+    }
+    if algorithm == HashAlgorithm::Blake3 {
+        let data = pattern(PARALLEL_READ_SIZE + 1000);
+        let path = dir.join("parallel");
+        fs::write(&path, &data)?;
+        let mut file = Fileinfo::new(None, None, data.len() as u64, path);
+        let parallel = ScanOptions::builder().hash_algorithm(algorithm).parallel_hash(true).build();
+        let found = hex(file.generate_hash(HashMode::Full, &parallel)?.unwrap_or_default());
+        checks.push(Check{name: "large pattern, split across threads".to_string(), expected: hex(in_memory(algorithm, &data)), found});
+    }
+    Ok(checks)
+}
+
+fn pattern(length: usize) -> Vec<u8>{
+    (0..length).map(|x| (x % 251) as u8).collect()
+}
+
+fn in_memory(algorithm: HashAlgorithm, data: &[u8]) -> u128{
+    let mut hasher = ContentHasher::new(algorithm);
+    hasher.write(data);
+    hasher.finish()
+}
+
+fn hex(hash: u128) -> String{
+    hash.to_le_bytes().iter().map(|x| format!("{:02x}", x)).collect()
+}
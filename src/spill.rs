@@ -0,0 +1,213 @@
+//! Length buckets spilled to temporary files, bounding the memory a scan of a very large tree takes before hashing.
+//! Files are partitioned on disk by length, so every bucket lies whole within one partition and partitions can be consolidated one at a time.
+
+use std::convert::TryInto;
+use std::fs::{self, File};
+use std::io::{Error, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use nohash_hasher::IntMap;
+use crate::Fileinfo;
+use crate::scan::LengthBuckets;
+
+/// Number of files each scan spills into. Each holds the buckets of the lengths equal to its index modulo this count.
+pub(crate) const PARTITIONS: usize = 64;
+
+static SPILLS: AtomicUsize = AtomicUsize::new(0);
+
+/// Files found by a scan, held in memory up to a limit and spilled to disk in partitions beyond it.
+pub(crate) struct SpilledBuckets{
+    limit: usize,
+    held: LengthBuckets,
+    held_files: usize,
+    counts: IntMap<u64, usize>,
+    dir: Option<PathBuf>,
+    partitions: Vec<File>,
+}
+
+impl SpilledBuckets{
+    /// Holds up to `limit` files in memory before spilling them.
+    pub(crate) fn new(limit: usize) -> Self{
+        SpilledBuckets{limit: limit.max(1), held: LengthBuckets::default(), held_files: 0, counts: IntMap::default(), dir: None, partitions: Vec::new()}
+    }
+    /// Adds a file found by the scan, spilling every held file once the limit is passed. A spill which cannot be written leaves the files held
+    /// in memory and stops further spilling, returning the path of the spill directory and the error.
+    pub(crate) fn add(&mut self, file: Fileinfo) -> Option<(PathBuf, Error)>{
+        *self.counts.entry(file.get_length()).or_insert(0) += 1;
+        self.held.entry(file.get_length()).or_default().push(file);
+        self.held_files += 1;
+        if self.held_files <= self.limit {
+            return None
+        }
+        match self.spill() {
+            Ok(()) => None,
+            Err(e) => {
+                self.limit = usize::MAX;
+                Some((self.dir.clone().unwrap_or_else(std::env::temp_dir), e))
+            },
+        }
+    }
+    /// Gets the number of files found of each length, held or spilled.
+    pub(crate) fn length_counts(&self) -> &IntMap<u64, usize>{
+        &self.counts
+    }
+    /// Takes the buckets of one partition, those held in memory along with any read back from disk. A partition which cannot be read back
+    /// returns the path of its file and the error.
+    pub(crate) fn take_partition(&mut self, index: usize) -> Result<LengthBuckets, (PathBuf, Error)>{
+        let lengths: Vec<u64> = self.held.keys().copied().filter(|x| partition_of(*x) == index).collect();
+        let mut buckets: LengthBuckets = lengths.into_iter().filter_map(|x| self.held.remove_entry(&x)).collect();
+        let dir = match &self.dir {
+            Some(dir) => dir,
+            None => return Ok(buckets),
+        };
+        let path = partition_path(dir, index);
+        let mut data = Vec::new();
+        File::open(&path).and_then(|mut x| x.read_to_end(&mut data)).map_err(|e| (path.clone(), e))?;
+        let mut rest = data.as_slice();
+        while !rest.is_empty() {
+            let file = read_record(&mut rest).map_err(|e| (path.clone(), e))?;
+            buckets.entry(file.get_length()).or_default().push(file);
+        }
+        Ok(buckets)
+    }
+    // Writes every held file to its partition. A failed write truncates each partition back to its length before the spill, so no file is left both held and spilled
+    fn spill(&mut self) -> Result<(), Error>{
+        if self.dir.is_none() {
+            let dir = spill_dir();
+            fs::create_dir(&dir)?;
+            self.dir = Some(dir);
+        }
+        let dir = self.dir.as_ref().unwrap();
+        if self.partitions.is_empty() {
+            self.partitions = (0..PARTITIONS).map(|x| File::create(partition_path(dir, x))).collect::<Result<_, _>>()?;
+        }
+        let mut records: Vec<Vec<u8>> = vec![Vec::new(); PARTITIONS];
+        for (length, files) in self.held.iter(){
+            for file in files.iter(){
+                write_record(&mut records[partition_of(*length)], file);
+            }
+        }
+        let lengths: Vec<u64> = self.partitions.iter().map(|x| x.metadata().map(|y| y.len())).collect::<Result<_, _>>()?;
+        for (partition, record) in self.partitions.iter_mut().zip(records.iter()){
+            if let Err(e) = partition.write_all(record) {
+                for (partition, &length) in self.partitions.iter().zip(lengths.iter()){
+                    let _ = partition.set_len(length);
+                }
+                return Err(e)
+            }
+        }
+        self.held.clear();
+        self.held_files = 0;
+        Ok(())
+    }
+}
+
+impl Drop for SpilledBuckets{
+    fn drop(&mut self){
+        self.partitions.clear();
+        if let Some(dir) = &self.dir {
+            let _ = fs::remove_dir_all(dir);
+        }
+    }
+}
+
+// Named for the process and a count of its scans, so concurrent scans never share a directory
+fn spill_dir() -> PathBuf{
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|x| x.subsec_nanos()).unwrap_or_default();
+    std::env::temp_dir().join(format!("ddh-spill-{}-{}-{}", std::process::id(), SPILLS.fetch_add(1, Ordering::Relaxed), nanos))
+}
+
+fn partition_of(length: u64) -> usize{
+    (length % PARTITIONS as u64) as usize
+}
+
+fn partition_path(dir: &Path, index: usize) -> PathBuf{
+    dir.join(index.to_string())
+}
+
+// Records are the length, root, modification time, sparse flag and path of a file found by traversal, which is all it holds before hashing.
+// Modification times are a tag, 0 for none, 1 after the epoch or 2 before it, then seconds and nanoseconds from the epoch
+fn write_record(out: &mut Vec<u8>, file: &Fileinfo){
+    let path = &file.file_paths[0];
+    out.extend_from_slice(&file.get_length().to_le_bytes());
+    out.extend_from_slice(&(file.file_roots[0] as u64).to_le_bytes());
+    let (tag, offset) = match file.file_modified[0] {
+        None => (0u8, Duration::ZERO),
+        Some(time) => match time.duration_since(UNIX_EPOCH) {
+            Ok(offset) => (1, offset),
+            Err(e) => (2, e.duration()),
+        },
+    };
+    out.push(tag);
+    out.extend_from_slice(&offset.as_secs().to_le_bytes());
+    out.extend_from_slice(&offset.subsec_nanos().to_le_bytes());
+    out.push(!file.sparse_paths.is_empty() as u8);
+    let path = path_bytes(path);
+    out.extend_from_slice(&(path.len() as u64).to_le_bytes());
+    out.extend_from_slice(&path);
+}
+
+fn read_record(rest: &mut &[u8]) -> Result<Fileinfo, Error>{
+    let length = read_u64(rest)?;
+    let root = read_u64(rest)? as usize;
+    let tag = take(rest, 1)?[0];
+    let offset = Duration::new(read_u64(rest)?, u32::from_le_bytes(take(rest, 4)?.try_into().unwrap()));
+    let modified = match tag {
+        0 => None,
+        1 => Some(UNIX_EPOCH + offset),
+        _ => UNIX_EPOCH.checked_sub(offset),
+    };
+    let sparse = take(rest, 1)?[0] != 0;
+    let path_length = read_u64(rest)? as usize;
+    let path = path_from_bytes(take(rest, path_length)?);
+    Ok(Fileinfo::new(None, None, length, path).with_root(root).with_modified(modified).with_sparse(sparse))
+}
+
+fn read_u64(rest: &mut &[u8]) -> Result<u64, Error>{
+    Ok(u64::from_le_bytes(take(rest, 8)?.try_into().unwrap()))
+}
+
+fn take<'a>(rest: &mut &'a [u8], n: usize) -> Result<&'a [u8], Error>{
+    if rest.len() < n {
+        return Err(Error::new(ErrorKind::UnexpectedEof, "Spill file ends partway through a record"))
+    }
+    let (taken, remainder) = rest.split_at(n);
+    *rest = remainder;
+    Ok(taken)
+}
+
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> Vec<u8>{
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf{
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+#[cfg(windows)]
+fn path_bytes(path: &Path) -> Vec<u8>{
+    use std::os::windows::ffi::OsStrExt;
+    path.as_os_str().encode_wide().flat_map(|x| x.to_le_bytes()).collect()
+}
+
+#[cfg(windows)]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf{
+    use std::os::windows::ffi::OsStringExt;
+    let wide: Vec<u16> = bytes.chunks_exact(2).map(|x| u16::from_le_bytes([x[0], x[1]])).collect();
+    PathBuf::from(std::ffi::OsString::from_wide(&wide))
+}
+
+#[cfg(not(any(unix, windows)))]
+fn path_bytes(path: &Path) -> Vec<u8>{
+    path.to_string_lossy().into_owned().into_bytes()
+}
+
+#[cfg(not(any(unix, windows)))]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf{
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
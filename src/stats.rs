@@ -0,0 +1,224 @@
+//! Statistics on how scanned files spread across length buckets, for diagnosing scans dominated by a few large buckets, on how they spread across search directories,
+//! and on the chance of a hash collision among them.
+
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use serde_derive::Serialize;
+use nohash_hasher::IntMap;
+use crate::Fileinfo;
+use crate::scan::LengthBuckets;
+
+/// Number of largest buckets kept in `LengthStats::largest`.
+pub const LARGEST_BUCKETS: usize = 10;
+
+/// The buckets holding between `min_files` and `max_files` files each.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HistogramBin{
+    /// Fewest files in a bucket counted by this bin.
+    pub min_files: usize,
+    /// Most files in a bucket counted by this bin.
+    pub max_files: usize,
+    /// Number of buckets in this bin.
+    pub buckets: usize,
+    /// Number of files across those buckets.
+    pub files: usize,
+}
+
+/// A bucket of files sharing one length.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Bucket{
+    /// Length shared by every file in the bucket.
+    pub length: u64,
+    /// Number of files in the bucket.
+    pub files: usize,
+}
+
+/// How files spread across length buckets before hashing. Every bucket of more than one file is hashed, so a few large buckets can dominate a scan.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct LengthStats{
+    /// Number of distinct file lengths.
+    pub buckets: usize,
+    /// Buckets by number of files, in bins doubling in size: 1 file, 2 to 3, 4 to 7 and so on. Empty bins are left out.
+    pub histogram: Vec<HistogramBin>,
+    /// Buckets holding the most files, largest first.
+    pub largest: Vec<Bucket>,
+}
+
+impl LengthStats{
+    pub(crate) fn from_buckets(files_of_lengths: &LengthBuckets) -> Self{
+        Self::from_counts(files_of_lengths.iter().map(|(&length, files)| (length, files.len())).collect())
+    }
+    pub(crate) fn from_counts(counts: Vec<(u64, usize)>) -> Self{
+        let mut histogram: Vec<HistogramBin> = Vec::new();
+        for &(_, files) in counts.iter(){
+            let bin = (usize::BITS - files.leading_zeros()) as usize - 1;
+            while histogram.len() <= bin {
+                let min_files = 1 << histogram.len();
+                histogram.push(HistogramBin{min_files, max_files: 2*min_files - 1, buckets: 0, files: 0});
+            }
+            histogram[bin].buckets += 1;
+            histogram[bin].files += files;
+        }
+        histogram.retain(|x| x.buckets > 0);
+        let buckets = counts.len();
+        let mut largest: Vec<Bucket> = counts.into_iter().map(|(length, files)| Bucket{length, files}).collect();
+        largest.sort_unstable_by(|a, b| b.files.cmp(&a.files).then_with(|| b.length.cmp(&a.length)));
+        largest.truncate(LARGEST_BUCKETS);
+        LengthStats{buckets, histogram, largest}
+    }
+}
+
+/// Estimates the chance that any two different contents in `files`, the results of a scan, share a hash of `hash_bits` bits, by the birthday bound.
+/// Only contents of the same length are ever compared by hash, so each length counts its own pairs, and empty files are never hashed.
+///
+/// # Examples
+/// ```
+/// use std::path::PathBuf;
+/// use ddh::Fileinfo;
+/// use ddh::stats::collision_probability;
+///
+/// let files: Vec<Fileinfo> = ["a", "b", "c"].iter()
+///     .map(|x| Fileinfo::new(None, None, 10, PathBuf::from(x)))
+///     .chain(std::iter::once(Fileinfo::new(None, None, 20, PathBuf::from("d"))))
+///     .collect();
+/// // Three contents of one length make three pairs, each colliding with probability 1/4 at two bits
+/// assert!((collision_probability(&files, 2) - (1.0 - (-0.75f64).exp())).abs() < 1e-12);
+/// assert!(collision_probability(&files, 128) < 1e-37);
+/// assert_eq!(0.0, collision_probability(&files[3..], 128));
+/// ```
+pub fn collision_probability(files: &[Fileinfo], hash_bits: u32) -> f64{
+    let mut contents: IntMap<u64, u64> = IntMap::default();
+    for file in files.iter().filter(|x| x.get_length() > 0){
+        *contents.entry(file.get_length()).or_insert(0) += 1;
+    }
+    let pairs: f64 = contents.values().map(|&x| (x as f64)*(x.saturating_sub(1) as f64)/2.0).sum();
+    -(-pairs/2f64.powi(hash_bits as i32)).exp_m1()
+}
+
+/// Counts the paths found under each of `roots` search directories in `files`, the results of a scan. Every directory gets a count, so one which was empty
+/// or entirely filtered out reports zero rather than being left out.
+///
+/// # Examples
+/// ```
+/// use std::fs;
+/// use ddh::stats::files_by_root;
+///
+/// let temp = tempfile::tempdir().unwrap();
+/// let root = temp.path();
+/// for dir in ["photos", "empty", "music"].iter() {
+///     fs::create_dir_all(root.join(dir)).unwrap();
+/// }
+/// fs::write(root.join("photos/a.jpg"), b"same").unwrap();
+/// fs::write(root.join("photos/b.jpg"), b"other").unwrap();
+/// fs::write(root.join("music/a.jpg"), b"same").unwrap();
+///
+/// let dirs = vec![root.join("photos"), root.join("empty"), root.join("music")];
+/// let (files, _) = ddh::deduplicate_dirs(dirs);
+/// assert_eq!(vec![2, 0, 1], files_by_root(&files, 3));
+/// ```
+pub fn files_by_root(files: &[Fileinfo], roots: usize) -> Vec<u64>{
+    let mut counts = vec![0; roots];
+    for &root in files.iter().flat_map(|x| x.get_roots()){
+        if let Some(count) = counts.get_mut(root) {
+            *count += 1;
+        }
+    }
+    counts
+}
+
+/// Collects statistics during a scan for reading once it completes.
+#[derive(Debug, Default)]
+pub struct ScanStats{
+    lengths: OnceLock<LengthStats>,
+    vanished: AtomicU64,
+}
+
+impl ScanStats{
+    /// Creates an empty collector.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::fs;
+    /// use std::sync::Arc;
+    /// use ddh::ScanOptions;
+    /// use ddh::stats::ScanStats;
+    ///
+    /// let temp = tempfile::tempdir().unwrap();
+    /// let root = temp.path();
+    /// for name in ["a", "b", "c", "d"].iter() {
+    ///     fs::write(root.join(name), b"page").unwrap();
+    /// }
+    /// fs::write(root.join("e"), b"other").unwrap();
+    ///
+    /// let stats = Arc::new(ScanStats::new());
+    /// let options = ScanOptions::builder().stats(stats.clone()).build();
+    /// ddh::deduplicate_dirs_with(vec![&root], &options);
+    /// let lengths = stats.length_stats().unwrap();
+    /// assert_eq!(2, lengths.buckets);
+    /// assert_eq!((4, 4), (lengths.largest[0].length, lengths.largest[0].files));
+    /// assert_eq!(vec![(1, 1), (4, 1)], lengths.histogram.iter().map(|x| (x.min_files, x.buckets)).collect::<Vec<_>>());
+    /// ```
+    pub fn new() -> Self{
+        ScanStats::default()
+    }
+    pub(crate) fn record_lengths(&self, files_of_lengths: &LengthBuckets){
+        let _ = self.lengths.set(LengthStats::from_buckets(files_of_lengths));
+    }
+    pub(crate) fn record_length_counts(&self, counts: &IntMap<u64, usize>){
+        let _ = self.lengths.set(LengthStats::from_counts(counts.iter().map(|(&length, &files)| (length, files)).collect()));
+    }
+    /// Gets the length bucket statistics, once traversal has finished.
+    pub fn length_stats(&self) -> Option<&LengthStats>{
+        self.lengths.get()
+    }
+    pub(crate) fn record_vanished(&self, files: u64){
+        self.vanished.fetch_add(files, Ordering::Relaxed);
+    }
+    /// Gets the number of files left out of the results because they could not be read for their partial or full hash, usually because they were deleted or moved during the scan.
+    /// Their groups are formed from the remaining copies alone, and each is returned with the scan's read errors.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::fs;
+    /// use std::sync::Arc;
+    /// use std::thread;
+    /// use ddh::ScanOptions;
+    /// use ddh::progress::Progress;
+    /// use ddh::stats::ScanStats;
+    /// use ddh::throttle::Throttle;
+    ///
+    /// let temp = tempfile::tempdir().unwrap();
+    /// let root = temp.path();
+    /// for name in ["a", "b", "c"].iter() {
+    ///     fs::write(root.join(name), vec![7u8; 512*1024]).unwrap();
+    /// }
+    ///
+    /// // On one thread files are fully hashed one at a time, and the throttle holds the first open while the rest are deleted
+    /// let stats = Arc::new(ScanStats::new());
+    /// let progress = Arc::new(Progress::new());
+    /// let options = ScanOptions::builder()
+    ///     .threads(1)
+    ///     .throttle(Arc::new(Throttle::new(256*1024)))
+    ///     .progress(progress.clone())
+    ///     .stats(stats.clone())
+    ///     .build();
+    /// let deleter = {
+    ///     let root = root.to_path_buf();
+    ///     thread::spawn(move || {
+    ///         while progress.total_bytes() == 0 {
+    ///             thread::yield_now();
+    ///         }
+    ///         ["a", "b", "c"].iter().for_each(|x| fs::remove_file(root.join(x)).unwrap());
+    ///     })
+    /// };
+    /// let (files, errors) = ddh::deduplicate_dirs_with(vec![&root], &options);
+    /// deleter.join().unwrap();
+    /// assert!(stats.vanished_files() >= 2);
+    /// assert_eq!(stats.vanished_files() as usize, errors.len());
+    /// assert_eq!(3, stats.vanished_files() as usize + files.iter().map(|x| x.get_paths().len()).sum::<usize>());
+    /// assert!(files.iter().all(|x| x.get_paths().len() == 1));
+    /// ```
+    pub fn vanished_files(&self) -> u64{
+        self.vanished.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,109 @@
+//! Line templates for listing duplicates in a custom layout, such as `{size}\t{path}`, for scripts that would otherwise parse the standard format.
+
+use crate::Fileinfo;
+use crate::utils::format_timestamp;
+
+/// Names of the fields a template may use.
+pub const FIELDS: &[&str] = &["size", "path", "group", "root", "mtime"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field{
+    Size,
+    Path,
+    Group,
+    Root,
+    Mtime,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Piece{
+    Text(String),
+    Field(Field),
+}
+
+/// A line layout with `{field}` placeholders, rendered once per path.
+///
+/// Fields are `size` in bytes, `path`, `group` numbering duplicate groups from 1, `root` naming the search directory the path was found under,
+/// and `mtime` as an RFC 3339 UTC timestamp or `-` where the modification time is unknown.
+/// `{{` and `}}` stand for literal braces, and `\t`, `\n` and `\\` for a tab, a newline and a backslash.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template{
+    pieces: Vec<Piece>,
+}
+
+impl Template{
+    /// Parses a template, failing on unknown fields and unclosed braces so mistakes surface before any output.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::path::PathBuf;
+    /// use ddh::Fileinfo;
+    /// use ddh::template::Template;
+    ///
+    /// let template = Template::parse("{group}\\t{size}\\t{path} {{{root}}}").unwrap();
+    /// let file = Fileinfo::new(None, None, 42, PathBuf::from("photos/cat.jpg"));
+    /// assert_eq!("3\t42\tphotos/cat.jpg {photos}", template.render(&file, 0, 3, &["photos"]));
+    ///
+    /// assert!(Template::parse("{size} {owner}").unwrap_err().contains("owner"));
+    /// assert!(Template::parse("{size} {path").is_err());
+    /// ```
+    pub fn parse(template: &str) -> Result<Self, String>{
+        let mut pieces = Vec::new();
+        let mut text = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next(){
+            match (c, chars.peek()) {
+                ('{', Some('{')) | ('}', Some('}')) => {
+                    chars.next();
+                    text.push(c);
+                },
+                ('\\', Some('t')) => {chars.next(); text.push('\t');},
+                ('\\', Some('n')) => {chars.next(); text.push('\n');},
+                ('\\', Some('\\')) => {chars.next(); text.push('\\');},
+                ('{', _) => {
+                    let mut name = String::new();
+                    let mut closed = false;
+                    for x in chars.by_ref(){
+                        if x == '}' {
+                            closed = true;
+                            break
+                        }
+                        name.push(x);
+                    }
+                    if !closed {
+                        return Err(format!("Unclosed '{{' in template {}", template))
+                    }
+                    let field = match name.as_str() {
+                        "size" => Field::Size,
+                        "path" => Field::Path,
+                        "group" => Field::Group,
+                        "root" => Field::Root,
+                        "mtime" => Field::Mtime,
+                        _ => return Err(format!("Unknown template field {{{}}}. Use one of {}", name, FIELDS.join(", "))),
+                    };
+                    if !text.is_empty() {
+                        pieces.push(Piece::Text(std::mem::take(&mut text)));
+                    }
+                    pieces.push(Piece::Field(field));
+                },
+                ('}', _) => return Err(format!("Unmatched '}}' in template {}. Use '}}}}' for a literal brace", template)),
+                _ => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            pieces.push(Piece::Text(text));
+        }
+        Ok(Template{pieces})
+    }
+    /// Renders the line for the path at `index` in `file`, the `group`th duplicate group listed. `search_dirs` are the scan's search directories, in order.
+    pub fn render(&self, file: &Fileinfo, index: usize, group: usize, search_dirs: &[&str]) -> String{
+        self.pieces.iter().map(|piece| match piece {
+            Piece::Text(text) => text.clone(),
+            Piece::Field(Field::Size) => file.get_length().to_string(),
+            Piece::Field(Field::Path) => file.get_paths()[index].display().to_string(),
+            Piece::Field(Field::Group) => group.to_string(),
+            Piece::Field(Field::Root) => file.get_roots().get(index).and_then(|&x| search_dirs.get(x)).copied().unwrap_or("-").to_string(),
+            Piece::Field(Field::Mtime) => file.get_modified().get(index).copied().flatten().map_or("-".to_string(), format_timestamp),
+        }).collect()
+    }
+}
@@ -0,0 +1,144 @@
+//! Detection of text files which differ only in line endings or trailing whitespace, built on the file groups from a completed scan.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::Error;
+use std::path::{Path, PathBuf};
+use rayon::prelude::*;
+use serde_derive::Serialize;
+use crate::{ContentHasher, Fileinfo, HashAlgorithm};
+
+/// Extensions of files treated as text. Files with other extensions are never normalized.
+pub const TEXT_EXTENSIONS: &[&str] = &[
+    "bat", "c", "cfg", "conf", "cpp", "cs", "css", "csv", "go", "h", "hpp", "htm", "html", "ini", "java", "js", "json", "kt", "log",
+    "lua", "md", "php", "pl", "properties", "ps1", "py", "rb", "rs", "rst", "sh", "sql", "swift", "tex", "toml", "ts", "tsv", "txt",
+    "xml", "yaml", "yml",
+];
+
+/// Text files with the same contents once normalized, but not byte for byte.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TextGroup{
+    /// The near duplicate files, sorted.
+    pub paths: Vec<PathBuf>,
+    /// Number of distinct byte for byte contents among the paths.
+    pub variants: usize,
+}
+
+/// Checks whether a path has one of the `TEXT_EXTENSIONS`, ignoring case.
+///
+/// # Examples
+/// ```
+/// use std::path::Path;
+/// use ddh::text::is_text_path;
+///
+/// assert!(is_text_path(Path::new("./config/app.YAML")));
+/// assert!(!is_text_path(Path::new("./photos/cat.jpg")));
+/// assert!(!is_text_path(Path::new("./Makefile")));
+/// ```
+pub fn is_text_path(path: &Path) -> bool{
+    path.extension()
+        .map(|x| x.to_string_lossy().to_lowercase())
+        .is_some_and(|x| TEXT_EXTENSIONS.contains(&x.as_str()))
+}
+
+/// Hashes a file's contents with CRLF and CR line endings replaced by LF, and with spaces and tabs before each line ending removed if `trim_trailing_whitespace` is set.
+/// Returns `None` for files holding a NUL byte, which are taken to be binary despite their extension.
+///
+/// # Examples
+/// ```
+/// use ddh::text::normalized_hash;
+///
+/// let temp = tempfile::tempdir().unwrap();
+/// let dir = temp.path();
+/// std::fs::write(dir.join("unix.txt"), "one\ntwo\n").unwrap();
+/// std::fs::write(dir.join("windows.txt"), "one \r\ntwo\r\n").unwrap();
+/// std::fs::write(dir.join("binary.txt"), "one\0two\n").unwrap();
+/// let hash = |name, trim| normalized_hash(&dir.join(name), trim).unwrap();
+/// assert_ne!(hash("unix.txt", false), hash("windows.txt", false));
+/// assert_eq!(hash("unix.txt", true), hash("windows.txt", true));
+/// assert_eq!(None, hash("binary.txt", true));
+/// ```
+pub fn normalized_hash(path: &Path, trim_trailing_whitespace: bool) -> Result<Option<u128>, Error>{
+    let contents = fs::read(path)?;
+    if contents.contains(&0) {
+        return Ok(None)
+    }
+    let mut hasher = ContentHasher::new(HashAlgorithm::Sip128);
+    let mut lines = contents.split(|&x| x == b'\n').peekable();
+    while let Some(line) = lines.next(){
+        let line = match line.split_last() {
+            Some((b'\r', rest)) if lines.peek().is_some() => rest,
+            _ => line,
+        };
+        for (i, part) in line.split(|&x| x == b'\r').enumerate(){
+            if i > 0 {
+                hasher.write(b"\n");
+            }
+            let part = if trim_trailing_whitespace {
+                let end = part.iter().rposition(|&x| x != b' ' && x != b'\t').map_or(0, |x| x + 1);
+                &part[..end]
+            } else {
+                part
+            };
+            hasher.write(part);
+        }
+        if lines.peek().is_some() {
+            hasher.write(b"\n");
+        }
+    }
+    Ok(Some(hasher.finish()))
+}
+
+/// Finds text files under the scan whose contents match once normalized by `normalized_hash`, but which are not all exact duplicates of each other.
+/// Files that cannot be read are skipped. Groups are sorted by their first path.
+///
+/// # Examples
+/// ```
+/// use std::fs;
+/// use ddh::text::find_near_duplicate_text;
+///
+/// let temp = tempfile::tempdir().unwrap();
+/// let root = temp.path();
+/// fs::write(root.join("a.conf"), "key = value\n").unwrap();
+/// fs::write(root.join("b.conf"), "key = value\r\n").unwrap();
+/// fs::write(root.join("c.conf"), "key = value\n").unwrap();
+/// fs::write(root.join("d.bin"), "key = value\r\n").unwrap();
+///
+/// let (files, _) = ddh::deduplicate_dirs(vec![&root]);
+/// let groups = find_near_duplicate_text(&files, false);
+/// assert_eq!(1, groups.len());
+/// assert_eq!(vec![root.join("a.conf"), root.join("b.conf"), root.join("c.conf")], groups[0].paths);
+/// assert_eq!(2, groups[0].variants);
+/// ```
+pub fn find_near_duplicate_text(files: &[Fileinfo], trim_trailing_whitespace: bool) -> Vec<TextGroup>{
+    // Copies of one file share their contents, so only the first text path of each is read
+    let hashed: Vec<(u128, usize, Vec<&PathBuf>)> = files.par_iter()
+        .enumerate()
+        .filter_map(|(content, file)| {
+            let paths: Vec<&PathBuf> = file.get_paths().iter().filter(|x| is_text_path(x)).collect();
+            match normalized_hash(paths.first()?, trim_trailing_whitespace) {
+                Ok(Some(hash)) => Some((hash, content, paths)),
+                _ => None,
+            }
+        })
+        .collect();
+    let mut by_hash: HashMap<u128, Vec<(usize, &PathBuf)>> = HashMap::new();
+    for (hash, content, paths) in hashed.into_iter(){
+        by_hash.entry(hash).or_default().extend(paths.into_iter().map(|x| (content, x)));
+    }
+    let mut groups: Vec<TextGroup> = by_hash.into_values()
+        .filter_map(|members| {
+            let mut variants: Vec<usize> = members.iter().map(|x| x.0).collect();
+            variants.sort_unstable();
+            variants.dedup();
+            if variants.len() < 2 {
+                return None
+            }
+            let mut paths: Vec<PathBuf> = members.into_iter().map(|x| x.1.clone()).collect();
+            paths.sort();
+            Some(TextGroup{paths, variants: variants.len()})
+        })
+        .collect();
+    groups.sort_unstable_by(|a, b| a.paths.cmp(&b.paths));
+    groups
+}
@@ -0,0 +1,58 @@
+//! A token bucket shared between hashing threads to cap total read bandwidth.
+
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+struct Bucket{
+    tokens: f64,
+    refilled: Instant,
+}
+
+/// Limits the combined rate at which threads may read. Up to one second of reads may be spent in a burst.
+#[derive(Debug)]
+pub struct Throttle{
+    bytes_per_second: f64,
+    bucket: Mutex<Bucket>,
+}
+
+impl Throttle{
+    /// Creates a throttle allowing `bytes_per_second` across all threads sharing it.
+    ///
+    /// # Examples
+    /// ```
+    /// use ddh::throttle::Throttle;
+    ///
+    /// let throttle = Throttle::new(1024*1024);
+    /// throttle.acquire(4096);
+    /// assert_eq!(1024*1024, throttle.bytes_per_second());
+    /// ```
+    pub fn new(bytes_per_second: u64) -> Self{
+        let bytes_per_second = bytes_per_second.max(1) as f64;
+        Throttle{
+            bytes_per_second,
+            bucket: Mutex::new(Bucket{tokens: bytes_per_second, refilled: Instant::now()}),
+        }
+    }
+    /// Gets the configured rate limit.
+    pub fn bytes_per_second(&self) -> u64{
+        self.bytes_per_second as u64
+    }
+    /// Takes `bytes` tokens from the bucket, blocking until the bucket has refilled enough to cover them.
+    pub fn acquire(&self, bytes: u64){
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let now = Instant::now();
+            let refill = now.duration_since(bucket.refilled).as_secs_f64()*self.bytes_per_second;
+            bucket.tokens = (bucket.tokens + refill).min(self.bytes_per_second);
+            bucket.refilled = now;
+            bucket.tokens -= bytes as f64;
+            if bucket.tokens >= 0.0 {
+                return
+            }
+            Duration::from_secs_f64(-bucket.tokens/self.bytes_per_second)
+        };
+        thread::sleep(wait);
+    }
+}
@@ -0,0 +1,192 @@
+//! A persistent store of directory listings, letting a rescan skip reading directories whose modification time is unchanged since the last run.
+//! A directory's modification time changes whenever an entry is added, removed or renamed in it, but not when a file in it is rewritten in place,
+//! so the files of a reused listing are each checked for a change of length or modification time, which has the directory read again.
+
+use std::collections::HashMap;
+use std::fs::{self, Metadata};
+use std::io::Error;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use rayon::prelude::*;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use serde_derive::{Serialize, Deserialize};
+
+/// Directories modified this recently are read but not recorded, as an entry added within the same tick of a coarse filesystem clock
+/// would leave the modification time as it was when the directory was read.
+const RACY_WINDOW: Duration = Duration::from_secs(2);
+
+/// A regular file as its directory listed it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct CachedFile{
+    pub(crate) path: PathBuf,
+    pub(crate) length: u64,
+    modified: Option<(u64, u32)>,
+    pub(crate) sparse: bool,
+}
+
+impl CachedFile{
+    pub(crate) fn new(path: PathBuf, metadata: &Metadata, sparse: bool) -> Self{
+        CachedFile{path, length: metadata.len(), modified: metadata.modified().ok().and_then(since_epoch), sparse}
+    }
+    pub(crate) fn modified(&self) -> Option<SystemTime>{
+        self.modified.map(|(secs, nanos)| UNIX_EPOCH + Duration::new(secs, nanos))
+    }
+    // Whether the file is still a regular file of the length and modification time it was listed with
+    fn unchanged(&self) -> bool{
+        fs::symlink_metadata(&self.path)
+            .is_ok_and(|x| x.is_file() && x.len() == self.length && x.modified().ok().and_then(since_epoch) == self.modified)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Snapshot{
+    modified: (u64, u32),
+    files: Vec<CachedFile>,
+    // Subdirectories, symlinks and special files, which are traversed again so changes further down the tree are still found
+    others: Vec<PathBuf>,
+    #[serde(skip)]
+    seen: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TreeCacheFile{
+    entries: Vec<(PathBuf, Snapshot)>,
+}
+
+/// Listings of directories keyed by path, each reused while the directory's modification time is unchanged.
+///
+/// Every directory is still looked up and every listed file checked, so a change anywhere in the tree is found without reading the unchanged
+/// directories around it.
+#[derive(Debug, Default)]
+pub struct TreeCache{
+    entries: Mutex<HashMap<PathBuf, Snapshot>>,
+    reused: AtomicU64,
+    read: AtomicU64,
+}
+
+impl TreeCache{
+    /// Creates an empty cache.
+    pub fn new() -> Self{
+        TreeCache::default()
+    }
+    /// Loads a cache written by `save`.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::fs;
+    /// use std::sync::Arc;
+    /// use std::time::{Duration, SystemTime};
+    /// use ddh::ScanOptions;
+    /// use ddh::treecache::TreeCache;
+    ///
+    /// let temp = tempfile::tempdir().unwrap();
+    /// let root = temp.path();
+    /// fs::create_dir_all(root.join("photos")).unwrap();
+    /// fs::write(root.join("photos").join("a.jpg"), b"same").unwrap();
+    /// fs::write(root.join("b.jpg"), b"same").unwrap();
+    /// // Directories modified within the last moments are never recorded
+    /// let past = SystemTime::now() - Duration::from_secs(60);
+    /// for dir in [root.join("photos"), root.to_path_buf()].iter() {
+    ///     fs::File::open(dir).unwrap().set_modified(past).unwrap();
+    /// }
+    /// let cache_file = tempfile::NamedTempFile::new().unwrap();
+    ///
+    /// let cache = Arc::new(TreeCache::new());
+    /// let options = ScanOptions::builder().tree_cache(cache.clone()).build();
+    /// let (files, _) = ddh::deduplicate_dirs_with(vec![&root], &options);
+    /// assert_eq!(1, files.len());
+    /// assert_eq!((0, 2), (cache.reused_dirs(), cache.read_dirs()));
+    /// cache.save(cache_file.path()).unwrap();
+    ///
+    /// // A new file changes the modification time of its directory, which alone is read again
+    /// fs::write(root.join("photos").join("c.jpg"), b"same").unwrap();
+    /// fs::File::open(root.join("photos")).unwrap().set_modified(past + Duration::from_secs(30)).unwrap();
+    /// let cache = Arc::new(TreeCache::load(cache_file.path()).unwrap());
+    /// let options = ScanOptions::builder().tree_cache(cache.clone()).build();
+    /// let (files, _) = ddh::deduplicate_dirs_with(vec![&root], &options);
+    /// assert_eq!(3, files[0].get_paths().len());
+    /// assert_eq!((1, 1), (cache.reused_dirs(), cache.read_dirs()));
+    /// cache.save(cache_file.path()).unwrap();
+    ///
+    /// // A file rewritten in place leaves its directory's modification time alone, but its own change has the directory read again
+    /// fs::write(root.join("b.jpg"), b"different").unwrap();
+    /// let cache = Arc::new(TreeCache::load(cache_file.path()).unwrap());
+    /// let options = ScanOptions::builder().tree_cache(cache.clone()).build();
+    /// let (files, _) = ddh::deduplicate_dirs_with(vec![&root], &options);
+    /// let b = files.iter().find(|x| x.get_candidate_name() == "b.jpg").unwrap();
+    /// assert_eq!((1, 9), (b.get_paths().len(), b.get_length()));
+    /// assert_eq!((1, 1), (cache.reused_dirs(), cache.read_dirs()));
+    /// ```
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error>{
+        let cache_file: TreeCacheFile = serde_json::from_str(&fs::read_to_string(path)?)?;
+        Ok(TreeCache{entries: Mutex::new(cache_file.entries.into_iter().collect()), ..TreeCache::default()})
+    }
+    /// Writes the cache to a file as JSON. Only directories reached since the cache was created or loaded are kept, so directories since removed
+    /// or no longer searched are dropped.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error>{
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, snapshot| snapshot.seen);
+        let cache_file = TreeCacheFile{entries: entries.drain().collect()};
+        let written = fs::write(path, serde_json::to_string(&cache_file)?);
+        entries.extend(cache_file.entries);
+        written
+    }
+    /// Gets the number of directories with cached listings.
+    pub fn len(&self) -> usize{
+        self.entries.lock().unwrap().len()
+    }
+    /// Checks whether the cache holds no listings.
+    pub fn is_empty(&self) -> bool{
+        self.len() == 0
+    }
+    /// Gets the number of directories a scan with this cache in `ScanOptions::tree_cache` listed from the cache, as their modification time was unchanged.
+    pub fn reused_dirs(&self) -> u64{
+        self.reused.load(Ordering::Relaxed)
+    }
+    /// Gets the number of directories a scan with this cache in `ScanOptions::tree_cache` read, as they were new or changed.
+    pub fn read_dirs(&self) -> u64{
+        self.read.load(Ordering::Relaxed)
+    }
+    /// Gets the files and other entries of a directory, if neither it nor any of its files has changed since it was recorded.
+    /// A listing with a changed file is dropped, so the directory is read and recorded again.
+    pub(crate) fn lookup(&self, path: &Path, metadata: &Metadata) -> Option<(Vec<CachedFile>, Vec<PathBuf>)>{
+        let modified = metadata.modified().ok().and_then(since_epoch)?;
+        let (files, others) = {
+            let entries = self.entries.lock().unwrap();
+            let snapshot = entries.get(path).filter(|x| x.modified == modified)?;
+            (snapshot.files.clone(), snapshot.others.clone())
+        };
+        // Files are checked without holding the lock, as other directories are looked up meanwhile
+        if !files.par_iter().all(|x| x.unchanged()) {
+            self.entries.lock().unwrap().remove(path);
+            return None
+        }
+        if let Some(snapshot) = self.entries.lock().unwrap().get_mut(path) {
+            snapshot.seen = true;
+        }
+        self.reused.fetch_add(1, Ordering::Relaxed);
+        Some((files, others))
+    }
+    /// Records the listing of a directory just read, replacing any listing it had.
+    pub(crate) fn record(&self, path: &Path, metadata: &Metadata, files: Vec<CachedFile>, others: Vec<PathBuf>){
+        self.read.fetch_add(1, Ordering::Relaxed);
+        let mut entries = self.entries.lock().unwrap();
+        let time = match metadata.modified() {
+            Ok(time) if SystemTime::now().duration_since(time).is_ok_and(|x| x >= RACY_WINDOW) => time,
+            _ => {
+                entries.remove(path);
+                return
+            },
+        };
+        match since_epoch(time) {
+            Some(modified) => { entries.insert(path.to_path_buf(), Snapshot{modified, files, others, seen: true}); },
+            None => { entries.remove(path); },
+        }
+    }
+}
+
+fn since_epoch(time: SystemTime) -> Option<(u64, u32)>{
+    let since_epoch = time.duration_since(UNIX_EPOCH).ok()?;
+    Some((since_epoch.as_secs(), since_epoch.subsec_nanos()))
+}
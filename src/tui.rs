@@ -0,0 +1,232 @@
+//! Interactive terminal browser for marking duplicate copies to delete or hard link.
+
+use std::collections::HashMap;
+use std::io::{self, Write, stdout};
+use std::path::PathBuf;
+use crossterm::{cursor, execute, queue, terminal};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use ddh::Fileinfo;
+use ddh::actions::{same_file, Action};
+
+const HELP: &str = "Up/Down move  Enter expand  d delete  l link  u unmark  a apply  q quit";
+
+#[derive(Clone, Copy)]
+enum Row{
+    Group(usize),
+    Path(usize, usize),
+}
+
+struct Browser<'a>{
+    groups: &'a [&'a Fileinfo],
+    expanded: Vec<bool>,
+    marks: HashMap<(usize, usize), Action>,
+    cursor: usize,
+    scroll: usize,
+    confirming: bool,
+    status: String,
+}
+
+impl<'a> Browser<'a>{
+    fn new(groups: &'a [&'a Fileinfo]) -> Self{
+        Browser{
+            groups,
+            expanded: vec![false; groups.len()],
+            marks: HashMap::new(),
+            cursor: 0,
+            scroll: 0,
+            confirming: false,
+            status: String::new(),
+        }
+    }
+
+    fn rows(&self) -> Vec<Row>{
+        let mut rows = Vec::new();
+        for (group, file) in self.groups.iter().enumerate(){
+            rows.push(Row::Group(group));
+            if self.expanded[group]{
+                rows.extend((0..file.get_paths().len()).map(|path| Row::Path(group, path)));
+            }
+        }
+        rows
+    }
+
+    fn mark(&mut self, action: Option<Action>){
+        let (group, path) = match self.rows().get(self.cursor) {
+            Some(Row::Path(group, path)) => (*group, *path),
+            _ => {
+                self.status = "Expand a group and select a path to mark it".to_string();
+                return
+            },
+        };
+        match action {
+            Some(action) => {
+                let marked = (0..self.groups[group].get_paths().len())
+                    .filter(|x| *x != path && self.marks.contains_key(&(group, *x)))
+                    .count();
+                if marked + 1 >= self.groups[group].get_paths().len(){
+                    self.status = "At least one copy in each group must be kept".to_string();
+                    return
+                }
+                self.marks.insert((group, path), action);
+            },
+            None => {
+                self.marks.remove(&(group, path));
+            },
+        }
+        self.status.clear();
+    }
+
+    fn plan(&self) -> Vec<(PathBuf, PathBuf, Action)>{
+        let mut plan = Vec::new();
+        for (group, file) in self.groups.iter().enumerate(){
+            let keep = match (0..file.get_paths().len()).find(|x| !self.marks.contains_key(&(group, *x))) {
+                Some(keep) => file.get_paths()[keep].clone(),
+                None => continue,
+            };
+            for (path, target) in file.get_paths().iter().enumerate(){
+                if let Some(action) = self.marks.get(&(group, path)){
+                    plan.push((keep.clone(), target.clone(), *action));
+                }
+            }
+        }
+        plan
+    }
+
+    fn draw<W: Write>(&mut self, out: &mut W) -> io::Result<()>{
+        let (width, height) = match terminal::size()? {
+            (0, _) | (_, 0) => (80, 24),
+            size => size,
+        };
+        let width = width as usize;
+        let list_height = (height as usize).saturating_sub(2).max(1);
+        let rows = self.rows();
+        if self.cursor < self.scroll {
+            self.scroll = self.cursor;
+        } else if self.cursor >= self.scroll + list_height {
+            self.scroll = self.cursor + 1 - list_height;
+        }
+        queue!(out, terminal::Clear(terminal::ClearType::All), cursor::MoveTo(0, 0))?;
+        let header = if self.confirming {
+            let plan = self.plan();
+            let deletions = plan.iter().filter(|x| x.2 == Action::Delete).count();
+            format!("Apply {} deletions and {} hard links? y/n", deletions, plan.len() - deletions)
+        } else {
+            HELP.to_string()
+        };
+        queue!(out, SetAttribute(Attribute::Bold), Print(clip(&header, width)), SetAttribute(Attribute::Reset))?;
+        for (line, row) in rows.iter().enumerate().skip(self.scroll).take(list_height){
+            let text = match row {
+                Row::Group(group) => {
+                    let file = self.groups[*group];
+                    format!("{} {} copies of {} ({} bytes)",
+                        if self.expanded[*group] {"[-]"} else {"[+]"},
+                        file.get_paths().len(),
+                        file.get_candidate_name(),
+                        file.get_length())
+                },
+                Row::Path(group, path) => {
+                    let mark = match self.marks.get(&(*group, *path)) {
+                        Some(Action::Delete) => "[D]",
+                        Some(Action::Hardlink) => "[L]",
+                        Some(Action::Reflink) => "[R]",
+                        None => "[ ]",
+                    };
+                    format!("    {} {}", mark, self.groups[*group].get_paths()[*path].display())
+                },
+            };
+            queue!(out, cursor::MoveTo(0, (line - self.scroll + 1) as u16))?;
+            if line == self.cursor {
+                queue!(out, SetAttribute(Attribute::Reverse), Print(clip(&text, width)), SetAttribute(Attribute::Reset))?;
+            } else {
+                queue!(out, Print(clip(&text, width)))?;
+            }
+        }
+        queue!(out, cursor::MoveTo(0, height.saturating_sub(1)), Print(clip(&self.status, width)))?;
+        out.flush()
+    }
+
+    fn run<W: Write>(&mut self, out: &mut W) -> io::Result<Option<Vec<(PathBuf, PathBuf, Action)>>>{
+        loop {
+            self.draw(out)?;
+            let key = match event::read()? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => key.code,
+                _ => continue,
+            };
+            if self.confirming {
+                match key {
+                    KeyCode::Char('y') | KeyCode::Char('Y') => return Ok(Some(self.plan())),
+                    _ => {
+                        self.confirming = false;
+                        self.status = "Nothing applied".to_string();
+                    },
+                }
+                continue
+            }
+            let row_count = self.rows().len();
+            match key {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(None),
+                KeyCode::Up | KeyCode::Char('k') => self.cursor = self.cursor.saturating_sub(1),
+                KeyCode::Down | KeyCode::Char('j') => self.cursor = (self.cursor + 1).min(row_count.saturating_sub(1)),
+                KeyCode::Enter | KeyCode::Char(' ') | KeyCode::Right | KeyCode::Left => {
+                    if let Some(row) = self.rows().get(self.cursor) {
+                        let group = match row {Row::Group(group) | Row::Path(group, _) => *group};
+                        self.expanded[group] = match key {
+                            KeyCode::Right => true,
+                            KeyCode::Left => false,
+                            _ => !self.expanded[group],
+                        };
+                        self.cursor = self.rows().iter().position(|x| matches!(x, Row::Group(g) if *g == group)).unwrap_or(0);
+                    }
+                },
+                KeyCode::Char('d') => self.mark(Some(Action::Delete)),
+                KeyCode::Char('l') => self.mark(Some(Action::Hardlink)),
+                KeyCode::Char('u') => self.mark(None),
+                KeyCode::Char('a') => {
+                    if self.marks.is_empty() {
+                        self.status = "No paths are marked".to_string();
+                    } else {
+                        self.confirming = true;
+                    }
+                },
+                _ => {},
+            }
+        }
+    }
+}
+
+fn clip(text: &str, width: usize) -> String{
+    text.chars().take(width).collect()
+}
+
+/// Browses duplicate groups interactively, then applies the marked actions once confirmed.
+/// With `paranoid`, each marked copy is first compared byte for byte with the copy kept in its group.
+pub fn browse(groups: &[&Fileinfo], paranoid: bool) -> io::Result<()>{
+    let mut out = stdout();
+    terminal::enable_raw_mode()?;
+    execute!(out, terminal::EnterAlternateScreen, cursor::Hide)?;
+    let result = Browser::new(groups).run(&mut out);
+    execute!(out, cursor::Show, terminal::LeaveAlternateScreen)?;
+    terminal::disable_raw_mode()?;
+    let plan = match result? {
+        Some(plan) => plan,
+        None => {
+            println!("Exiting without changes.");
+            return Ok(())
+        },
+    };
+    for (keep, target, action) in plan.iter(){
+        if same_file(keep, target){
+            eprintln!("Skipping {}, which leads to the same file as {}.", target.display(), keep.display());
+            continue
+        }
+        if paranoid && !crate::confirm_identical(keep, target){
+            continue
+        }
+        match action.apply(keep, target) {
+            Ok(()) => println!("{:?} {}", action, target.display()),
+            Err(e) => eprintln!("Could not {:?} {}. Err: {}", action, target.display(), e),
+        }
+    }
+    Ok(())
+}
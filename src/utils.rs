@@ -1,6 +1,8 @@
-use std::path::Path;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::{Read, self};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use unicode_normalization::UnicodeNormalization;
 
 pub struct ChunkIter{
     f: File,
@@ -9,7 +11,7 @@ pub struct ChunkIter{
 
 impl ChunkIter{
     pub fn new(f: File, len: usize) -> Self{
-        ChunkIter{f: f, chunk_len: len}
+        ChunkIter{f, chunk_len: len}
     }
 }
 
@@ -22,7 +24,7 @@ impl Iterator for ChunkIter{
             .read_to_end(&mut buffer){
             Ok(i) => {
                 if i == 0 {
-                    return None
+                    None
                 } else {
                     Some(Ok(buffer))}
                 },
@@ -30,3 +32,280 @@ impl Iterator for ChunkIter{
         }
     }
 }
+
+/// Gets the longest directory holding every one of `paths`, compared a whole component at a time, so `/photos/a` and `/photos/ab` share `/photos` rather than `/photos/a`.
+/// Returns an empty path when no directory is shared, such as for relative paths in different directories or paths on different Windows drives.
+///
+/// # Examples
+/// ```
+/// use std::path::{Path, PathBuf};
+/// use ddh::utils::common_prefix;
+///
+/// let paths = ["/mnt/backup/2020/photos/a/img.jpg", "/mnt/backup/2020/photos/b/img.jpg", "/mnt/backup/2020/photos/c/img.jpg"];
+/// assert_eq!(PathBuf::from("/mnt/backup/2020/photos"), common_prefix(&paths));
+/// assert_eq!(PathBuf::from("/photos"), common_prefix(&["/photos/a", "/photos/ab"]));
+/// assert_eq!(PathBuf::from("/"), common_prefix(&["/a/x", "/b/x"]));
+/// assert_eq!(PathBuf::new(), common_prefix(&["a/x", "b/x"]));
+/// assert_eq!(Path::new("/photos"), common_prefix(&["/photos/a.jpg"]));
+/// ```
+pub fn common_prefix<P: AsRef<Path>>(paths: &[P]) -> PathBuf{
+    let mut parents = paths.iter().map(|x| x.as_ref().parent().unwrap_or_else(|| Path::new("")));
+    let first = match parents.next() {
+        Some(first) => first,
+        None => return PathBuf::new(),
+    };
+    let mut shared = first.components().count();
+    for parent in parents{
+        shared = shared.min(first.components().zip(parent.components()).take_while(|(a, b)| a == b).count());
+    }
+    first.components().take(shared).collect()
+}
+
+/// Gets the file name of a path normalized to Unicode NFC, for comparing names across filesystems which store them differently (eg. NFD on macOS).
+/// Names are only ever compared this way; file contents are hashed as they are.
+///
+/// # Examples
+/// ```
+/// use std::path::Path;
+/// use ddh::utils::normalized_file_name;
+///
+/// let composed = Path::new("./foo/caf\u{e9}.jpg");
+/// let decomposed = Path::new("./bar/cafe\u{301}.jpg");
+/// assert_ne!(composed.file_name(), decomposed.file_name());
+/// assert_eq!(normalized_file_name(composed), normalized_file_name(decomposed));
+/// assert_eq!(Some("caf\u{e9}.jpg".to_string()), normalized_file_name(decomposed));
+/// ```
+pub fn normalized_file_name(path: &Path) -> Option<String>{
+    path.file_name()
+    .map(|name| name.to_string_lossy().nfc().collect())
+}
+
+/// Formats a byte count in units of `divisor` bytes, to two decimal places unless it divides evenly.
+/// Sizes too small to show as a hundredth of a unit are shown as `<0.01` rather than rounded to zero.
+///
+/// # Examples
+/// ```
+/// use ddh::utils::format_scaled;
+///
+/// assert_eq!("1.00", format_scaled(1023, 1024));
+/// assert_eq!("0.50", format_scaled(512, 1024));
+/// assert_eq!("<0.01", format_scaled(5, 1024));
+/// assert_eq!("12", format_scaled(12*1024*1024, 1024*1024));
+/// assert_eq!("0", format_scaled(0, 1024));
+/// assert_eq!("777", format_scaled(777, 1));
+/// ```
+pub fn format_scaled(bytes: u64, divisor: u64) -> String{
+    let divisor = divisor.max(1);
+    if bytes.is_multiple_of(divisor) {
+        return (bytes/divisor).to_string()
+    }
+    let scaled = bytes as f64/divisor as f64;
+    if scaled < 0.005 {
+        "<0.01".to_string()
+    } else {
+        format!("{:.2}", scaled)
+    }
+}
+
+/// The separators numbers are written with for people to read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NumberFormat{
+    /// Separates groups of three digits, or None to leave digits ungrouped.
+    pub thousands: Option<char>,
+    /// Separates the whole part of a number from its fraction.
+    pub decimal: char,
+}
+
+impl Default for NumberFormat{
+    fn default() -> Self{
+        NumberFormat{thousands: Some(','), decimal: '.'}
+    }
+}
+
+impl NumberFormat{
+    /// Writes numbers as they are parsed, without grouping.
+    pub const PLAIN: NumberFormat = NumberFormat{thousands: None, decimal: '.'};
+
+    /// Picks the separators of a locale such as `de_DE.UTF-8`, `fr` or `en-US`, by its language and region. `C` and `POSIX` leave digits ungrouped.
+    /// Returns None for languages whose conventions are not known.
+    ///
+    /// # Examples
+    /// ```
+    /// use ddh::utils::NumberFormat;
+    ///
+    /// assert_eq!(Some(NumberFormat{thousands: Some('.'), decimal: ','}), NumberFormat::from_locale("de_DE.UTF-8"));
+    /// assert_eq!(Some(NumberFormat{thousands: Some('\''), decimal: '.'}), NumberFormat::from_locale("de-CH"));
+    /// assert_eq!(Some(NumberFormat::PLAIN), NumberFormat::from_locale("C"));
+    /// assert_eq!(None, NumberFormat::from_locale("tlh"));
+    /// ```
+    pub fn from_locale(locale: &str) -> Option<Self>{
+        let tag = locale.split(['.', '@']).next().unwrap_or("").to_lowercase().replace('-', "_");
+        let (language, region) = tag.split_once('_').unwrap_or((&tag, ""));
+        let (thousands, decimal) = match (language, region) {
+            ("c", _) | ("posix", _) => return Some(NumberFormat::PLAIN),
+            ("de", "ch") | ("it", "ch") | ("fr", "ch") => ('\'', '.'),
+            ("en", _) | ("ja", _) | ("zh", _) | ("ko", _) | ("hi", _) | ("he", _) | ("th", _) => (',', '.'),
+            ("de", _) | ("nl", _) | ("it", _) | ("es", _) | ("pt", _) | ("da", _) | ("id", _) | ("tr", _) | ("el", _) => ('.', ','),
+            ("fr", _) | ("ru", _) | ("pl", _) | ("cs", _) | ("sk", _) | ("sv", _) | ("fi", _) | ("nb", _) | ("no", _) | ("uk", _) | ("hu", _) => ('\u{202f}', ','),
+            _ => return None,
+        };
+        Some(NumberFormat{thousands: Some(thousands), decimal})
+    }
+}
+
+/// Rewrites a number written with a `.` before any fraction, such as the output of `format_scaled`, with the separators of `format`.
+/// Text around the digits, such as a leading `<`, is kept.
+///
+/// # Examples
+/// ```
+/// use ddh::utils::{format_number, NumberFormat};
+///
+/// assert_eq!("12,883,456", format_number("12883456", NumberFormat::default()));
+/// assert_eq!("12.883,46", format_number("12883.46", NumberFormat{thousands: Some('.'), decimal: ','}));
+/// assert_eq!("<0,01", format_number("<0.01", NumberFormat{thousands: Some('.'), decimal: ','}));
+/// assert_eq!("12883456", format_number("12883456", NumberFormat::PLAIN));
+/// ```
+pub fn format_number(number: &str, format: NumberFormat) -> String{
+    let start = number.find(|x: char| x.is_ascii_digit()).unwrap_or(number.len());
+    let end = number[start..].find(|x: char| !x.is_ascii_digit()).map_or(number.len(), |x| start + x);
+    let digits = &number[start..end];
+    let mut formatted = number[..start].to_string();
+    for (i, digit) in digits.chars().enumerate(){
+        if let (Some(separator), true) = (format.thousands, i > 0 && (digits.len() - i).is_multiple_of(3)) {
+            formatted.push(separator);
+        }
+        formatted.push(digit);
+    }
+    match number[end..].strip_prefix('.') {
+        Some(fraction) => {
+            formatted.push(format.decimal);
+            formatted.push_str(fraction);
+        },
+        None => formatted.push_str(&number[end..]),
+    }
+    formatted
+}
+
+/// The encoding text reports are written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TextEncoding{
+    /// UTF-8 without a byte order mark.
+    #[default]
+    Utf8,
+    /// UTF-16 little endian, which some Windows applications expect, with a byte order mark opening the file.
+    Utf16Le,
+}
+
+impl TextEncoding{
+    /// Encodes `text`, starting with a byte order mark where the encoding calls for one and `starts_file` is set.
+    /// Text appended to an existing file should leave it unset, so the mark is only written once.
+    ///
+    /// # Examples
+    /// ```
+    /// use ddh::utils::TextEncoding;
+    ///
+    /// assert_eq!("a\u{e9}".as_bytes(), TextEncoding::Utf8.encode("a\u{e9}", true).as_slice());
+    /// assert_eq!(vec![0xff, 0xfe, b'a', 0, 0xe9, 0], TextEncoding::Utf16Le.encode("a\u{e9}", true));
+    /// assert_eq!(vec![b'a', 0], TextEncoding::Utf16Le.encode("a", false));
+    /// // Characters outside the basic plane take a surrogate pair
+    /// assert_eq!(vec![0x3d, 0xd8, 0x0a, 0xde], TextEncoding::Utf16Le.encode("\u{1f60a}", false));
+    /// ```
+    pub fn encode(&self, text: &str, starts_file: bool) -> Vec<u8>{
+        match self {
+            TextEncoding::Utf8 => text.as_bytes().to_vec(),
+            TextEncoding::Utf16Le => {
+                let bom: &[u16] = if starts_file {&[0xfeff]} else {&[]};
+                bom.iter().copied().chain(text.encode_utf16()).flat_map(|x| x.to_le_bytes()).collect()
+            },
+        }
+    }
+}
+
+/// Reads a pattern file with one pattern per line. Blank lines and lines starting with `#` are ignored, as is surrounding whitespace.
+///
+/// # Examples
+/// ```
+/// use ddh::utils::read_pattern_file;
+///
+/// let temp = tempfile::tempdir().unwrap();
+/// let pattern_file = temp.path().join("patterns");
+/// std::fs::write(&pattern_file, "# build output\ntarget\n\n  *.tmp\n").unwrap();
+/// assert_eq!(vec!["target", "*.tmp"], read_pattern_file(&pattern_file).unwrap());
+/// ```
+pub fn read_pattern_file<P: AsRef<Path>>(path: P) -> Result<Vec<String>, io::Error>{
+    Ok(fs::read_to_string(path)?
+    .lines()
+    .map(|x| x.trim())
+    .filter(|x| !x.is_empty() && !x.starts_with('#'))
+    .map(|x| x.to_string())
+    .collect())
+}
+
+/// Reads a file of NUL separated paths, as written by `find -print0`. Paths may hold newlines or any other character except NUL.
+/// Empty entries, such as the one after a trailing NUL, are ignored. Paths must be valid UTF-8.
+///
+/// # Examples
+/// ```
+/// use ddh::utils::read_nul_separated_file;
+///
+/// let temp = tempfile::tempdir().unwrap();
+/// let list_file = temp.path().join("dirs");
+/// std::fs::write(&list_file, "photos\0odd\nname\0").unwrap();
+/// assert_eq!(vec!["photos", "odd\nname"], read_nul_separated_file(&list_file).unwrap());
+/// ```
+pub fn read_nul_separated_file<P: AsRef<Path>>(path: P) -> Result<Vec<String>, io::Error>{
+    fs::read(path)?
+    .split(|&x| x == 0)
+    .filter(|x| !x.is_empty())
+    .map(|x| String::from_utf8(x.to_vec()).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)))
+    .collect()
+}
+
+/// Checks whether a read error may clear up if the read is tried again, as when a network filesystem times out or drops its connection.
+/// Errors such as a missing file or a denied permission are permanent and not worth retrying.
+///
+/// # Examples
+/// ```
+/// use std::io::{Error, ErrorKind};
+/// use ddh::utils::is_transient_error;
+///
+/// assert!(is_transient_error(&Error::from(ErrorKind::TimedOut)));
+/// assert!(is_transient_error(&Error::from(ErrorKind::Interrupted)));
+/// assert!(!is_transient_error(&Error::from(ErrorKind::NotFound)));
+/// assert!(!is_transient_error(&Error::from(ErrorKind::PermissionDenied)));
+/// ```
+pub fn is_transient_error(error: &io::Error) -> bool{
+    use io::ErrorKind::*;
+    match error.kind() {
+        Interrupted | TimedOut | WouldBlock | ResourceBusy | StaleNetworkFileHandle | ConnectionReset | ConnectionAborted
+            | NetworkDown | NetworkUnreachable | HostUnreachable => true,
+        // EIO, which SMB and NFS clients return when the server stops responding
+        _ => cfg!(unix) && error.raw_os_error() == Some(5),
+    }
+}
+
+/// Formats a time as a UTC timestamp in RFC 3339 form, to the second. Times before 1970 are formatted as the epoch.
+///
+/// # Examples
+/// ```
+/// use std::time::{Duration, UNIX_EPOCH};
+/// use ddh::utils::format_timestamp;
+///
+/// assert_eq!("1970-01-01T00:00:00Z", format_timestamp(UNIX_EPOCH));
+/// assert_eq!("2024-02-29T13:05:09Z", format_timestamp(UNIX_EPOCH + Duration::from_secs(1709211909)));
+/// ```
+pub fn format_timestamp(time: SystemTime) -> String{
+    let seconds = time.duration_since(UNIX_EPOCH).map(|x| x.as_secs()).unwrap_or(0);
+    let (days, seconds) = (seconds/86400, seconds%86400);
+    // Converts days since the epoch to a civil date, counting in 400 year eras starting each March
+    let days = days + 719468;
+    let era = days/146097;
+    let day_of_era = days - era*146097;
+    let year_of_era = (day_of_era - day_of_era/1460 + day_of_era/36524 - day_of_era/146096)/365;
+    let day_of_year = day_of_era - (365*year_of_era + year_of_era/4 - year_of_era/100);
+    let month_index = (5*day_of_year + 2)/153;
+    let day = day_of_year - (153*month_index + 2)/5 + 1;
+    let month = if month_index < 10 {month_index + 3} else {month_index - 9};
+    let year = year_of_era + era*400 + if month <= 2 {1} else {0};
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, seconds/3600, seconds%3600/60, seconds%60)
+}
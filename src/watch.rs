@@ -0,0 +1,278 @@
+//! Watching search directories for new files which duplicate files already there.
+//! The index is brought up to date either by rescanning every search directory, which works on any filesystem, network shares included,
+//! or by rescanning only the paths filesystem events name.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use crate::{actions, Fileinfo, HashMode, ScanOptions};
+use crate::scan::{self, LengthBuckets};
+use serde_derive::Serialize;
+
+/// A newly added or changed file whose contents match files already indexed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct NewDuplicate{
+    /// The new file.
+    pub path: PathBuf,
+    /// The indexed files it duplicates.
+    pub copies: Vec<PathBuf>,
+    /// The length of the files in bytes.
+    pub length: u64,
+}
+
+impl NewDuplicate{
+    /// Gathers the new file and its copies into a group, with their modification times, for choosing a copy to keep with a `RetentionPolicy`.
+    pub fn group(&self) -> Fileinfo{
+        let mut group = Fileinfo::new(None, None, self.length, self.path.clone());
+        group.file_paths = self.copies.iter().chain(std::iter::once(&self.path)).cloned().collect();
+        group.file_roots = vec![0; group.file_paths.len()];
+        group.file_modified = group.file_paths.iter().map(|x| fs::metadata(x).and_then(|y| y.modified()).ok()).collect();
+        group
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct State{
+    length: u64,
+    modified: Option<SystemTime>,
+}
+
+#[derive(Debug)]
+struct Indexed{
+    state: State,
+    // Hashed only once another file of the same length arrives
+    full_hash: Option<u128>,
+}
+
+/// The files under a set of search directories, kept up to date by rescanning them.
+///
+/// Files are only hashed when a new file of the same length arrives, so indexing a large tree costs a traversal. A new or changed file is compared
+/// once it has kept the same length and modification time for a whole poll, so files still being downloaded or copied are not read half written.
+#[derive(Debug)]
+pub struct WatchIndex{
+    search_dirs: Vec<PathBuf>,
+    // The search directories with symlinks resolved, as some platforms name them in events
+    canonical_dirs: Vec<Option<PathBuf>>,
+    files: HashMap<PathBuf, Indexed>,
+    lengths: HashMap<u64, Vec<PathBuf>>,
+    pending: HashMap<PathBuf, State>,
+}
+
+impl WatchIndex{
+    /// Indexes the files under `search_dirs`, returning the index and the paths which could not be scanned.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::fs;
+    /// use ddh::ScanOptions;
+    /// use ddh::watch::WatchIndex;
+    ///
+    /// let temp = tempfile::tempdir().unwrap();
+    /// let root = temp.path();
+    /// fs::write(root.join("report.pdf"), b"contents").unwrap();
+    ///
+    /// let options = ScanOptions::default();
+    /// let (mut index, _) = WatchIndex::new(vec![&root], &options);
+    /// assert_eq!(1, index.len());
+    ///
+    /// fs::write(root.join("report (1).pdf"), b"contents").unwrap();
+    /// fs::write(root.join("other.pdf"), b"elsewise").unwrap();
+    /// // New files are compared once they are unchanged for a whole poll
+    /// assert!(index.poll(&options).0.is_empty());
+    /// let (found, _) = index.poll(&options);
+    /// assert_eq!(1, found.len());
+    /// assert_eq!(root.join("report (1).pdf"), found[0].path);
+    /// assert_eq!(vec![root.join("report.pdf")], found[0].copies);
+    /// assert_eq!(3, index.len());
+    /// ```
+    pub fn new<P: AsRef<Path>>(search_dirs: Vec<P>, options: &ScanOptions) -> (Self, Vec<(PathBuf, Error)>){
+        let search_dirs: Vec<PathBuf> = search_dirs.iter().map(|x| x.as_ref().to_path_buf()).collect();
+        let canonical_dirs = search_dirs.iter().map(|x| fs::canonicalize(x).ok()).collect();
+        let mut index = WatchIndex{search_dirs, canonical_dirs, files: HashMap::new(), lengths: HashMap::new(), pending: HashMap::new()};
+        let (found, errors) = index.scan(index.search_dirs.clone(), options);
+        for (path, state) in found.into_iter(){
+            index.insert(path, state, None);
+        }
+        (index, errors)
+    }
+    /// Gets the number of files indexed, leaving out new files which have not yet settled.
+    pub fn len(&self) -> usize{
+        self.files.len()
+    }
+    /// Checks whether no files are indexed.
+    pub fn is_empty(&self) -> bool{
+        self.files.is_empty()
+    }
+    /// Checks whether new or changed files are waiting to settle, which the next poll compares if they are unchanged by then.
+    pub fn is_settling(&self) -> bool{
+        !self.pending.is_empty()
+    }
+    /// Rescans the search directories, returning the settled new files which duplicate indexed files and the paths which could not be scanned or read.
+    /// New files are indexed whether or not they are duplicates, and files no longer found are dropped. A poll interrupted by `ScanOptions::stop` changes nothing.
+    pub fn poll(&mut self, options: &ScanOptions) -> (Vec<NewDuplicate>, Vec<(PathBuf, Error)>){
+        let (found, errors) = self.scan(self.search_dirs.clone(), options);
+        self.update(found, errors, |_| true, options)
+    }
+    /// Rescans only the `changed` paths, such as those named by filesystem events, along with the files still settling, and otherwise works as `poll`.
+    /// A changed directory is rescanned with everything under it, and a changed path no longer found drops the files indexed under it.
+    /// Paths outside the search directories or under an excluded directory are ignored.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::fs;
+    /// use ddh::ScanOptions;
+    /// use ddh::watch::WatchIndex;
+    ///
+    /// let temp = tempfile::tempdir().unwrap();
+    /// let root = temp.path();
+    /// fs::write(root.join("report.pdf"), b"contents").unwrap();
+    /// let options = ScanOptions::default();
+    /// let (mut index, _) = WatchIndex::new(vec![&root], &options);
+    ///
+    /// fs::write(root.join("report (1).pdf"), b"contents").unwrap();
+    /// fs::write(root.join("unreported.pdf"), b"contents").unwrap();
+    /// assert!(index.poll_changed(&[root.join("report (1).pdf")], &options).0.is_empty());
+    /// assert!(index.is_settling());
+    /// // Settling files are rescanned without being named again
+    /// let (found, _) = index.poll_changed(&[] as &[&std::path::Path], &options);
+    /// assert_eq!(vec![root.join("report (1).pdf")], found.iter().map(|x| x.path.clone()).collect::<Vec<_>>());
+    /// assert_eq!(2, index.len());
+    ///
+    /// fs::remove_file(root.join("report.pdf")).unwrap();
+    /// index.poll_changed(&[root.join("report.pdf")], &options);
+    /// assert_eq!(1, index.len());
+    /// ```
+    pub fn poll_changed<P: AsRef<Path>>(&mut self, changed: &[P], options: &ScanOptions) -> (Vec<NewDuplicate>, Vec<(PathBuf, Error)>){
+        let mut roots: Vec<PathBuf> = changed.iter().filter_map(|x| self.within_search_dirs(x.as_ref(), options)).collect();
+        roots.extend(self.pending.keys().cloned());
+        roots.sort();
+        roots.dedup();
+        // Paths under another changed directory are rescanned with it
+        let roots: Vec<PathBuf> = roots.iter().enumerate()
+            .filter(|(i, x)| !roots[..*i].iter().any(|y| x.starts_with(y)))
+            .map(|x| x.1.clone())
+            .collect();
+        let (found, mut errors) = self.scan(roots.clone(), options);
+        errors.retain(|(path, e)| e.kind() != ErrorKind::NotFound || !roots.contains(path));
+        self.update(found, errors, |path| roots.iter().any(|x| path.starts_with(x)), options)
+    }
+    // Gets a path as it is found under its search directory, or None for paths outside them, excluded or under an excluded directory
+    fn within_search_dirs(&self, path: &Path, options: &ScanOptions) -> Option<PathBuf>{
+        let (search_dir, relative) = self.search_dirs.iter().zip(self.canonical_dirs.iter()).find_map(|(dir, canonical)| {
+            path.strip_prefix(dir).ok()
+                .or_else(|| canonical.as_ref().and_then(|x| path.strip_prefix(x).ok()))
+                .map(|relative| (dir, relative))
+        })?;
+        let path = search_dir.join(relative);
+        let mut current = Some(path.as_path());
+        while let Some(part) = current.filter(|x| *x != search_dir.as_path()) {
+            if options.is_excluded(part) {
+                return None
+            }
+            current = part.parent();
+        }
+        Some(path)
+    }
+    // Brings the index up to date with the files found by a scan, which covered the paths `scanned` accepts
+    fn update<F: Fn(&Path) -> bool>(&mut self, found: HashMap<PathBuf, State>, mut errors: Vec<(PathBuf, Error)>, scanned: F, options: &ScanOptions) -> (Vec<NewDuplicate>, Vec<(PathBuf, Error)>){
+        if options.is_stopped(){
+            return (Vec::new(), errors)
+        }
+        let vanished: Vec<PathBuf> = self.files.keys().filter(|x| scanned(x) && !found.contains_key(*x)).cloned().collect();
+        for path in vanished.iter(){
+            self.remove(path);
+        }
+        self.pending.retain(|path, _| !scanned(path) || found.contains_key(path));
+        let mut settled: Vec<(PathBuf, State)> = Vec::new();
+        for (path, state) in found.into_iter(){
+            match self.files.get(&path) {
+                Some(indexed) if indexed.state == state => continue,
+                Some(_) => self.remove(&path),
+                None => {},
+            }
+            match self.pending.insert(path.clone(), state) {
+                Some(previous) if previous == state => {
+                    self.pending.remove(&path);
+                    settled.push((path, state));
+                },
+                _ => {},
+            }
+        }
+        settled.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut duplicates = Vec::new();
+        for (path, state) in settled.into_iter(){
+            let (copies, hash) = match self.find_copies(&path, state.length, options) {
+                Ok(found) => found,
+                Err(e) => {
+                    errors.push((path.clone(), e));
+                    (Vec::new(), None)
+                },
+            };
+            if !copies.is_empty() {
+                duplicates.push(NewDuplicate{path: path.clone(), copies, length: state.length});
+            }
+            self.insert(path, state, hash);
+        }
+        (duplicates, errors)
+    }
+    fn scan(&self, roots: Vec<PathBuf>, options: &ScanOptions) -> (HashMap<PathBuf, State>, Vec<(PathBuf, Error)>){
+        if roots.is_empty() {
+            return (HashMap::new(), Vec::new())
+        }
+        let pool = options.thread_pool();
+        let (buckets, errors): (LengthBuckets, _) = scan::scan_dirs(roots, options, false, &pool);
+        let found = buckets.into_values()
+            .flatten()
+            .flat_map(|file| {
+                let length = file.get_length();
+                file.file_paths.into_iter().zip(file.file_modified).map(move |(path, modified)| (path, State{length, modified}))
+            })
+            .collect();
+        (found, errors)
+    }
+    // Compares a new file with the indexed files of its length, hashing each side only once. Empty files are never duplicates, as under the default EmptyFilePolicy,
+    // and nor are hard links to an indexed file, such as those --hardlink leaves behind
+    fn find_copies(&mut self, path: &Path, length: u64, options: &ScanOptions) -> Result<(Vec<PathBuf>, Option<u128>), Error>{
+        let candidates: Vec<PathBuf> = match self.lengths.get(&length) {
+            Some(candidates) if length > 0 => candidates.clone(),
+            _ => return Ok((Vec::new(), None)),
+        };
+        let hash = full_hash(path, length, options)?;
+        let identity = actions::inode(path);
+        let mut copies = Vec::new();
+        for candidate in candidates.into_iter(){
+            if identity.is_some() && actions::inode(&candidate) == identity {
+                continue
+            }
+            let indexed = match self.files.get_mut(&candidate) {
+                Some(indexed) => indexed,
+                None => continue,
+            };
+            if indexed.full_hash.is_none() {
+                indexed.full_hash = full_hash(&candidate, length, options).ok().flatten();
+            }
+            if hash.is_some() && indexed.full_hash == hash && (options.trust_full_hash || actions::same_range_contents(&candidate, path, options.hash_range, options.throttle.as_deref()).unwrap_or(false)) {
+                copies.push(candidate);
+            }
+        }
+        copies.sort();
+        Ok((copies, hash))
+    }
+    fn insert(&mut self, path: PathBuf, state: State, full_hash: Option<u128>){
+        self.lengths.entry(state.length).or_default().push(path.clone());
+        self.files.insert(path, Indexed{state, full_hash});
+    }
+    fn remove(&mut self, path: &Path){
+        if let Some(indexed) = self.files.remove(path) {
+            if let Some(paths) = self.lengths.get_mut(&indexed.state.length) {
+                paths.retain(|x| x != path);
+            }
+        }
+    }
+}
+
+fn full_hash(path: &Path, length: u64, options: &ScanOptions) -> Result<Option<u128>, Error>{
+    Fileinfo::new(None, None, length, path.to_path_buf()).generate_hash(HashMode::Full, options)
+}
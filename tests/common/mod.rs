@@ -0,0 +1,49 @@
+//! Fixtures shared by the integration tests. Each fixture is a directory of its own that is removed when dropped, even after a failed
+//! assertion, so tests may run concurrently and a failed run leaves nothing behind for the next.
+
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use tempfile::TempDir;
+
+pub struct Fixture{
+    dir: TempDir,
+}
+
+impl Fixture{
+    pub fn new() -> Self{
+        Fixture{dir: tempfile::Builder::new().prefix("ddh-test-").tempdir().expect("Error creating fixture directory")}
+    }
+    pub fn path(&self) -> &Path{
+        self.dir.path()
+    }
+    /// Writes a file under the fixture, creating the directories it is in.
+    pub fn write<P: AsRef<Path>>(&self, path: P, contents: &[u8]) -> PathBuf{
+        let path = self.path().join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, contents).unwrap();
+        path
+    }
+    /// Creates a directory under the fixture.
+    pub fn dir<P: AsRef<Path>>(&self, path: P) -> PathBuf{
+        let path = self.path().join(path);
+        fs::create_dir_all(&path).unwrap();
+        path
+    }
+    /// Runs the ddh binary in the fixture's directory, so reports it writes by default land there.
+    pub fn ddh(&self, args: &[&str]) -> Output{
+        Command::new(env!("CARGO_BIN_EXE_ddh")).args(args).current_dir(self.path()).output().expect("Error running ddh")
+    }
+}
+
+/// Gets the paths of each group of duplicates, sorted, with the groups sorted by their first path.
+pub fn groups(files: &[ddh::Fileinfo]) -> Vec<Vec<PathBuf>>{
+    let mut groups: Vec<Vec<PathBuf>> = files.iter()
+        .filter(|x| x.get_paths().len() > 1)
+        .map(|x| x.get_paths_sorted().into_iter().map(|y| y.to_path_buf()).collect())
+        .collect();
+    groups.sort();
+    groups
+}
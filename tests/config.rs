@@ -0,0 +1,55 @@
+//! Settings read with `--config`, and how they combine with the command line.
+
+mod common;
+
+use common::Fixture;
+
+fn config_fixture(config: &str) -> Fixture{
+    let fixture = Fixture::new();
+    fixture.write("a/photo.jpg", b"the same bytes");
+    fixture.write("b/photo.jpg", b"the same bytes");
+    fixture.write("b/notes.tmp", b"the same bytes");
+    fixture.write("scan.toml", config.as_bytes());
+    fixture
+}
+
+#[test]
+fn searches_the_roots_with_the_settings(){
+    let fixture = config_fixture("roots = [\"a\", \"b\"]\nformat = \"json\"\nverbosity = \"duplicates\"\noutput = \"no\"\nexclude = [\"*.tmp\"]\n");
+    let output = fixture.ddh(&["--config", "scan.toml"]);
+    assert!(output.status.success());
+    let results: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(serde_json::json!(["a/photo.jpg", "b/photo.jpg"]), results["groups"][0]["file_paths"]);
+}
+
+#[test]
+fn command_line_takes_precedence(){
+    let fixture = config_fixture("roots = [\"a\", \"b\"]\nformat = \"json\"\nverbosity = \"duplicates\"\noutput = \"no\"\n");
+    let output = fixture.ddh(&["b", "--config", "scan.toml", "-f", "standard"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("2 Total files (with duplicates)"));
+    assert!(stdout.contains("b/notes.tmp"));
+    assert!(!stdout.contains("a/photo.jpg"));
+}
+
+#[test]
+fn rejects_unknown_keys_and_tables(){
+    for config in &["typo-key = false\n", "[report]\nformat = \"json\"\n", "min-size = \"large\"\n"]{
+        let fixture = config_fixture(config);
+        let output = fixture.ddh(&["--config", "scan.toml", "a"]);
+        assert_eq!(Some(1), output.status.code(), "{}", config);
+        assert!(String::from_utf8_lossy(&output.stderr).contains("Error reading config scan.toml"));
+        assert!(output.stdout.is_empty());
+    }
+}
+
+#[test]
+fn command_line_replaces_conflicting_settings(){
+    let fixture = config_fixture("roots = [\"a\", \"b\"]\noutput = \"no\"\nkeep-newest = true\ndelete = true\ndry-run = true\nmin-size = 100\n");
+    let output = fixture.ddh(&["--config", "scan.toml", "--keep-oldest", "--hardlink", "--min-size", "1"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Would Hardlink"));
+    assert!(!stdout.contains("Would Delete"));
+}
@@ -0,0 +1,33 @@
+//! `--dirs-equal` exits with 0 only when both trees were read in full and hold the same contents.
+
+mod common;
+
+use common::Fixture;
+
+fn trees() -> Fixture{
+    let fixture = Fixture::new();
+    fixture.write("original/photo.jpg", b"the same bytes");
+    fixture.write("backup/photo.jpg", b"the same bytes");
+    fixture
+}
+
+#[test]
+fn equal_trees_exit_with_zero(){
+    let output = trees().ddh(&["original", "backup", "--dirs-equal"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("original and backup hold the same contents"));
+}
+
+// A FIFO is reported as an error rather than read
+#[cfg(unix)]
+#[test]
+fn unread_paths_are_not_equal(){
+    use std::process::Command;
+    let fixture = trees();
+    assert!(Command::new("mkfifo").arg(fixture.path().join("backup/pipe")).status().unwrap().success());
+    let output = fixture.ddh(&["original", "backup", "--dirs-equal"]);
+    assert_eq!(Some(1), output.status.code());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("1 paths could not be read"));
+    assert!(stdout.contains("backup/pipe"));
+}
@@ -0,0 +1,106 @@
+//! With `--format json`, stdout carries only JSON documents and everything else goes to stderr.
+
+mod common;
+
+use common::Fixture;
+use serde_json::Value;
+
+fn json_documents(stdout: &[u8]) -> Vec<Value>{
+    serde_json::Deserializer::from_slice(stdout).into_iter::<Value>()
+        .collect::<Result<_, _>>()
+        .unwrap_or_else(|e| panic!("stdout is not JSON: {}\n{}", e, String::from_utf8_lossy(stdout)))
+}
+
+fn duplicates_fixture() -> Fixture{
+    let fixture = Fixture::new();
+    fixture.write("a/photo.jpg", b"the same bytes");
+    fixture.write("b/photo.jpg", b"the same bytes");
+    fixture.write("b/other.jpg", b"different bytes");
+    fixture
+}
+
+#[test]
+fn summary_goes_to_stderr(){
+    let fixture = duplicates_fixture();
+    let output = fixture.ddh(&["a", "b", "-f", "json", "-v", "duplicates", "-o", "no", "--confidence", "--by-root"]);
+    assert!(output.status.success());
+    let documents = json_documents(&output.stdout);
+    assert_eq!(1, documents.len());
+    assert_eq!(1, documents[0]["groups"].as_array().unwrap().len());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Total files (with duplicates)"));
+    assert!(stderr.contains("Duplicated bytes by search directory"));
+}
+
+#[test]
+fn overwrite_prompt_goes_to_stderr(){
+    let fixture = duplicates_fixture();
+    fixture.write("results.json", b"{}");
+    let output = fixture.ddh(&["a", "b", "-f", "json", "-v", "duplicates", "-o", "results.json"]);
+    assert_eq!(1, json_documents(&output.stdout).len());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("File results.json already exists."));
+    assert!(stderr.contains("Exiting."));
+}
+
+#[test]
+fn actions_go_to_stderr(){
+    let fixture = duplicates_fixture();
+    let output = fixture.ddh(&["a", "b", "-f", "json", "-v", "duplicates", "-o", "no", "--delete", "--keep-shallowest", "--dry-run"]);
+    assert!(output.status.success());
+    assert_eq!(1, json_documents(&output.stdout).len());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Would Delete"));
+}
+
+// Groups are listed longest first, so more than the output buffer holds is listed before the short group holding the path which cannot be serialized.
+// Only Linux is sure to accept a file name which is not valid UTF-8
+#[cfg(target_os = "linux")]
+#[test]
+fn unserializable_results_print_nothing(){
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+    let fixture = Fixture::new();
+    let name = "x".repeat(200);
+    for i in 0..6000 {
+        fixture.write(format!("a/{}{}", name, i), format!("{:04}", i).as_bytes());
+        fixture.write(format!("b/{}{}", name, i), format!("{:04}", i).as_bytes());
+    }
+    fixture.write(Path::new("a").join(OsStr::from_bytes(b"bad\xff")), b"0");
+    fixture.write("b/short", b"0");
+    let output = fixture.ddh(&["a", "b", "-f", "json", "-v", "duplicates", "-o", "no"]);
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+}
+
+// A FIFO is reported as an error rather than read, and its name need not be valid UTF-8
+#[cfg(target_os = "linux")]
+#[test]
+fn errors_with_unserializable_paths_are_reported(){
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+    use std::process::Command;
+    let fixture = duplicates_fixture();
+    let fifo = fixture.path().join("a").join(OsStr::from_bytes(b"bad\xff"));
+    assert!(Command::new("mkfifo").arg(&fifo).status().unwrap().success());
+    let output = fixture.ddh(&["a", "b", "-f", "json", "-v", "duplicates", "-o", "no"]);
+    assert!(output.status.success());
+    assert_eq!(1, json_documents(&output.stdout).len());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("\"path\":\"a/bad\u{fffd}\""));
+}
+
+// Reports other than the duplicates listing go through the same fallible path, so a name which is not valid UTF-8 ends the run with an error
+#[cfg(target_os = "linux")]
+#[test]
+fn unserializable_reports_fail_without_panicking(){
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+    let fixture = duplicates_fixture();
+    fixture.write(Path::new("a").join(OsStr::from_bytes(b"bad\xff")), b"different bytes");
+    let output = fixture.ddh(&["a", "b", "-f", "json", "-v", "quiet", "-o", "no", "--report-largest-duplicates", "5"]);
+    assert_eq!(Some(1), output.status.code());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Error encountered serializing results to JSON"));
+    assert!(!stderr.contains("panicked"));
+}
@@ -0,0 +1,14 @@
+//! `--watch` options are checked before indexing starts.
+
+mod common;
+
+use common::Fixture;
+
+#[test]
+fn zero_interval_is_rejected(){
+    let fixture = Fixture::new();
+    fixture.write("a/photo.jpg", b"the same bytes");
+    let output = fixture.ddh(&["a", "--watch", "--watch-interval", "0"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Seconds must be a positive number"));
+}